@@ -9,6 +9,9 @@ SELECT * FROM pgml.breast_cancer LIMIT 10;
 -- train a simple model to classify the data
 SELECT * FROM pgml.train('Breast Cancer Detection', 'classification', 'pgml.breast_cancer', 'malignant');
 
+-- keep the class balance the same in the train and test sets
+SELECT * FROM pgml.train('Breast Cancer Detection', 'classification', 'pgml.breast_cancer', 'malignant', test_sampling => 'stratified');
+
 -- check out the predictions
 SELECT malignant, pgml.predict(
     'Breast Cancer Detection', 
@@ -93,7 +96,8 @@ SELECT * FROM pgml.deploy('Breast Cancer Detection', 'most_recent', 'random_fore
 -- check out that throughput
 SELECT * FROM pgml.deployed_models ORDER BY deployed_at DESC LIMIT 5;
 
--- do a hyperparam search on your favorite algorithm
+-- do a hyperparam search on your favorite algorithm,
+-- optimizing recall so fewer malignant tumors are missed
 SELECT pgml.train(
     'Breast Cancer Detection', 
     algorithm => 'xgboost', 
@@ -101,7 +105,8 @@ SELECT pgml.train(
     search_params => '{
         "n_estimators": [2, 4],
         "max_depth": [1, 2, 3]
-    }'
+    }',
+    metric => 'recall'
 );
 
 -- deploy the "best" model for prediction use
@@ -0,0 +1,31 @@
+-- This example clusters the sklearn diabetes dataset
+-- Source URL: https://www4.stat.ncsu.edu/~boos/var.select/diabetes.html
+--
+-- This demonstrates learning groups of similar rows without a label.
+-- Clustering projects don't take a y_column_name; every column is a feature.
+
+-- Exit on error (psql)
+\set ON_ERROR_STOP true
+\timing
+
+SELECT pgml.load_dataset('diabetes');
+
+-- view the dataset
+SELECT * FROM pgml.diabetes LIMIT 10;
+
+-- train a simple model on the data
+SELECT * FROM pgml.train('Diabetes Clusters', 'clustering', 'pgml.diabetes', algorithm => 'kmeans', hyperparams => '{"n_clusters": 4}');
+
+-- check out the cluster assignments
+SELECT pgml.predict('Diabetes Clusters', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6, target]) AS cluster
+FROM pgml.diabetes
+LIMIT 10;
+
+-- DBSCAN finds the number of clusters itself, and labels outliers -1
+SELECT * FROM pgml.train('Diabetes Clusters', algorithm => 'dbscan', hyperparams => '{"eps": 50}');
+
+-- view the models sorted by silhouette score
+SELECT id, algorithm, runtime, metrics->>'silhouette' AS silhouette, metrics->>'inertia' AS inertia
+FROM pgml.models
+WHERE project_id = (SELECT id FROM pgml.projects WHERE name = 'Diabetes Clusters')
+ORDER BY metrics->>'silhouette' DESC;
@@ -18,6 +18,11 @@ SELECT target, pgml.predict('Iris Flower Types', ARRAY[sepal_length, sepal_width
 FROM pgml.iris_view 
 LIMIT 10;
 
+-- check out the probability of each class
+SELECT target, pgml.predict_proba('Iris Flower Types', ARRAY[sepal_length, sepal_width, petal_length, petal_width]) AS probabilities
+FROM pgml.iris_view 
+LIMIT 10;
+
 --
 -- After a project has been trained, ommited parameters will be reused from previous training runs
 -- In these examples we'll reuse the training data snapshots from the initial call.
@@ -58,6 +63,13 @@ SELECT trained_models.* FROM pgml.trained_models
 JOIN pgml.models on models.id = trained_models.id
 ORDER BY models.metrics->>'f1' DESC LIMIT 5;
 
+-- f1, precision and recall are macro averages for multiclass, the micro and weighted averages are there too
+SELECT metrics->'f1_macro' AS f1_macro, metrics->'f1_weighted' AS f1_weighted, metrics->'cohen_kappa' AS cohen_kappa, metrics->'classes' AS classes
+FROM pgml.models ORDER BY id DESC LIMIT 1;
+
+-- see which flowers get mistaken for each other
+SELECT * FROM pgml.confusion_matrix((SELECT max(id) FROM pgml.models));
+
 -- deploy the random_forest model for prediction use
 SELECT * FROM pgml.deploy('Iris Flower Types', 'most_recent', 'random_forest');
 -- check out that throughput
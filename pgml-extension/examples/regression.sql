@@ -26,6 +26,33 @@ SELECT target, pgml.predict('Diabetes Progression', ARRAY[age, sex, bmi, bp, s1,
 FROM pgml.diabetes 
 LIMIT 10;
 
+-- scale features before training, the fitted scales are saved with the model and applied to predictions
+SELECT * FROM pgml.train('Diabetes Progression Scaled', 'regression', 'pgml.diabetes', 'target', 'svm', preprocess => '{
+    "bmi": {"scale": "standard"},
+    "bp": {"scale": "robust"},
+    "s1": {"scale": "quantile"},
+    "s2": {"scale": "min_max"}
+}');
+
+SELECT target, pgml.predict('Diabetes Progression Scaled', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6]) AS prediction
+FROM pgml.diabetes
+LIMIT 10;
+
+-- or pass the whole row, features are matched to the training columns by name
+SELECT target, pgml.predict('Diabetes Progression', diabetes.*) AS prediction
+FROM pgml.diabetes
+LIMIT 10;
+
+-- predict many rows at once, which is much faster on large tables
+SELECT pgml.predict_batch('Diabetes Progression', array_agg(ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6]::REAL[] ORDER BY s6)) AS predictions
+FROM (SELECT * FROM pgml.diabetes LIMIT 10) diabetes;
+
+-- or buffer rows in groups, and predict each group in a single batch,
+-- predictions only line up with other aggregates that use the same ORDER BY
+SELECT pgml.predict_agg('Diabetes Progression', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6] ORDER BY row_number) AS predictions, array_agg(target ORDER BY row_number) AS targets
+FROM (SELECT *, row_number() OVER () AS row_number FROM pgml.diabetes) diabetes
+GROUP BY row_number / 100;
+
 -- Check predictions against a specific model id
 SELECT model_id, target, pgml.model_predict(model_id, ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6]) AS prediction
 FROM pgml.diabetes
@@ -91,11 +118,32 @@ SELECT trained_models.* FROM pgml.trained_models
 JOIN pgml.models on models.id = trained_models.id
 ORDER BY models.metrics->>'mean_squared_error' DESC LIMIT 5;
 
+-- look for bias in the last model, the residuals are summarized in its metrics too
+SELECT metrics->'residuals' AS residuals FROM pgml.models ORDER BY id DESC LIMIT 1;
+SELECT width_bucket(predicted, 0, 350, 7) AS bucket, avg(residual), count(*)
+FROM pgml.residuals((SELECT max(id) FROM pgml.models))
+GROUP BY 1 ORDER BY 1;
+
 -- deploy the random_forest model for prediction use
 SELECT * FROM pgml.deploy('Diabetes Progression', 'most_recent', 'random_forest');
 -- check out that throughput
 SELECT * FROM pgml.deployed_models ORDER BY deployed_at DESC LIMIT 5;
 
+-- see which features the deployed model relies on most
+SELECT * FROM pgml.feature_importance('Diabetes Progression') ORDER BY importance DESC;
+
+-- and why it made a particular prediction, the contributions add up from the base value to the prediction
+SELECT * FROM pgml.explain('Diabetes Progression', ARRAY[0.038, 0.051, 0.062, 0.022, -0.044, -0.035, -0.043, -0.003, 0.020, -0.018]);
+
+-- stop boosting once the score on the test set hasn't improved for 10 rounds,
+-- the best round is saved in the model's metrics
+SELECT * FROM pgml.train('Diabetes Progression', algorithm => 'xgboost', hyperparams => '{"n_estimators": 500, "early_stopping_rounds": 10, "eval_metric": "rmse"}');
+SELECT * FROM pgml.train('Diabetes Progression', algorithm => 'lightgbm', hyperparams => '{"n_estimators": 500, "early_stopping_rounds": 10, "eval_metric": "rmse"}');
+SELECT metrics->'best_iteration' AS best_iteration FROM pgml.models ORDER BY id DESC LIMIT 2;
+
+-- the scores on the train and test sets are recorded for every round, to spot overfitting
+SELECT key, value FROM pgml.models, jsonb_each(metrics->'history') WHERE id = (SELECT max(id) FROM pgml.models);
+
 -- do a hyperparam search on your favorite algorithm
 SELECT pgml.train(
     'Diabetes Progression', 
@@ -107,13 +155,101 @@ SELECT pgml.train(
     }'
 );
 
+-- or sample hyperparams from distributions with a random search,
+-- fitting 2 models at a time on threads (rust runtime only)
+SELECT pgml.train(
+    'Diabetes Progression',
+    algorithm => 'xgboost',
+    search => 'random',
+    search_params => '{
+        "eta": {"loguniform": [0.01, 0.3]},
+        "max_depth": {"randint": [2, 12]},
+        "subsample": {"uniform": [0.5, 1.0]},
+        "n_estimators": [20, 40]
+    }',
+    search_args => '{"n_iter": 4, "n_jobs": 2}'
+);
+
+-- bayesian searches learn from earlier trials which hyperparams look promising,
+-- and successive halving trains many candidates on a few rows, keeping only the best
+SELECT pgml.train(
+    'Diabetes Progression',
+    algorithm => 'xgboost',
+    search => 'bayesian',
+    search_params => '{
+        "eta": {"loguniform": [0.01, 0.3]},
+        "max_depth": {"randint": [2, 12]}
+    }',
+    search_args => '{"n_iter": 8, "n_initial_points": 4}'
+);
+SELECT pgml.train(
+    'Diabetes Progression',
+    algorithm => 'xgboost',
+    search => 'halving',
+    search_params => '{
+        "eta": {"loguniform": [0.01, 0.3]},
+        "max_depth": {"randint": [2, 12]}
+    }',
+    search_args => '{"n_iter": 9, "factor": 3}'
+);
+
 -- deploy the "best" model for prediction use
 SELECT * FROM pgml.deploy('Diabetes Progression', 'best_score');
 SELECT * FROM pgml.deploy('Diabetes Progression', 'most_recent');
 SELECT * FROM pgml.deploy('Diabetes Progression', 'rollback');
 SELECT * FROM pgml.deploy('Diabetes Progression', 'best_score', 'svm');
 
+-- send 10% of the traffic to the most recent model, while the live model keeps the rest
+SELECT * FROM pgml.deploy('Diabetes Progression', 'canary', traffic => 0.1);
+
+-- or split it evenly between the two best models for an A/B test
+SELECT * FROM pgml.deploy_split(
+    'Diabetes Progression',
+    (SELECT array_agg(id) FROM (SELECT id FROM pgml.models WHERE project_id = (SELECT id FROM pgml.projects WHERE name = 'Diabetes Progression') ORDER BY (metrics->>'r2')::FLOAT DESC NULLS LAST LIMIT 2) best),
+    ARRAY[0.5, 0.5]
+);
+
+-- the key routes each customer to the same model every time, and logged predictions record the model that served them
+SELECT target, pgml.predict('Diabetes Progression', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6], key => id::TEXT) AS prediction
+FROM (SELECT row_number() OVER () AS id, * FROM pgml.diabetes LIMIT 10) diabetes;
+
+-- promote a model by deploying it on its own
+SELECT * FROM pgml.deploy('Diabetes Progression', 'best_score');
+
+-- score the same inputs with the most recent model in the shadow of the live one, and compare them offline
+SELECT * FROM pgml.deploy('Diabetes Progression', 'shadow');
+SELECT target, pgml.predict('Diabetes Progression', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6]) AS prediction
+FROM pgml.diabetes
+LIMIT 10;
+SELECT model_id, shadow_model_id, avg(abs(prediction - shadow_prediction)) AS mean_absolute_difference
+FROM pgml.shadow_predictions
+GROUP BY model_id, shadow_model_id;
+SELECT * FROM pgml.deploy('Diabetes Progression', 'best_score');
+
+-- log the predictions, record their outcomes once they're known, and monitor the live metrics
+SELECT pgml.log_predictions('Diabetes Progression');
+SELECT target, pgml.predict('Diabetes Progression', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6], key => id::TEXT) AS prediction
+FROM (SELECT row_number() OVER () AS id, * FROM pgml.diabetes LIMIT 10) diabetes;
+SELECT pgml.record_outcome('Diabetes Progression', id::TEXT, target)
+FROM (SELECT row_number() OVER () AS id, * FROM pgml.diabetes LIMIT 10) diabetes;
+SELECT model_id, window_start, outcomes, metrics->>'r2' AS r2 FROM pgml.live_metrics('Diabetes Progression');
+SELECT model_id, window_start, outcomes, metrics->>'r2' AS r2
+FROM pgml.live_metrics('Diabetes Progression', since => (now() - INTERVAL '1 day')::TIMESTAMP, window_size => INTERVAL '1 hour');
+SELECT pgml.outcome_metrics('regression', array_agg(prediction), array_agg(actual))
+FROM pgml.predictions
+WHERE actual IS NOT NULL AND created_at > now() - INTERVAL '1 hour';
+
+-- compare the distribution of new data to the snapshot the deployed model was trained on
+SELECT feature, psi, ks, mean_shift, drifted FROM pgml.drift('Diabetes Progression', 'pgml.diabetes');
+SELECT feature, analysis FROM pgml.drift('Diabetes Progression', 'pgml.diabetes', threshold => 0.1) WHERE drifted;
+
 -- check out the improved predictions
 SELECT target, pgml.predict('Diabetes Progression', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6]) AS prediction
 FROM pgml.diabetes 
 LIMIT 10;
+
+-- retrain on a fresh snapshot every day, or as soon as 100 rows were added, and deploy the new model if it's better
+SELECT pgml.retrain_policy('Diabetes Progression', 'pgml.diabetes', 'target', 'xgboost', schedule => '1 day', row_delta => 100);
+-- the background worker checks the policies of the database set in `pgml.retrain_database`, or run the due ones now
+SELECT * FROM pgml.retrain();
+SELECT * FROM pgml.retrain_runs;
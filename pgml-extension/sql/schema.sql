@@ -49,6 +49,8 @@ CREATE TABLE IF NOT EXISTS pgml.projects(
 	id BIGSERIAL PRIMARY KEY,
 	name TEXT NOT NULL,
 	task pgml.task NOT NULL,
+	metric TEXT,
+	log_predictions BOOLEAN NOT NULL DEFAULT false,
 	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
 	updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp()
 );
@@ -108,14 +110,118 @@ CREATE TABLE IF NOT EXISTS pgml.deployments(
 	project_id BIGINT NOT NULL,
 	model_id BIGINT NOT NULL,
 	strategy pgml.strategy NOT NULL,
+	shadow_model_id BIGINT,
 	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
 	CONSTRAINT project_id_fk FOREIGN KEY(project_id) REFERENCES pgml.projects(id) ON DELETE CASCADE,
-	CONSTRAINT model_id_fk FOREIGN KEY(model_id) REFERENCES pgml.models(id) ON DELETE CASCADE
+	CONSTRAINT model_id_fk FOREIGN KEY(model_id) REFERENCES pgml.models(id) ON DELETE CASCADE,
+	CONSTRAINT shadow_model_id_fk FOREIGN KEY(shadow_model_id) REFERENCES pgml.models(id) ON DELETE CASCADE
 );
 CREATE INDEX IF NOT EXISTS deployments_project_id_created_at_idx ON pgml.deployments(project_id);
 CREATE INDEX IF NOT EXISTS deployments_model_id_created_at_idx ON pgml.deployments(model_id);
 SELECT pgml.auto_updated_at('pgml.deployments');
 
+---
+--- Deployments split their traffic between the models of their arms, e.g. for A/B tests and canaries
+---
+CREATE TABLE IF NOT EXISTS pgml.deployment_arms(
+	id BIGSERIAL PRIMARY KEY,
+	deployment_id BIGINT NOT NULL,
+	model_id BIGINT NOT NULL,
+	traffic REAL NOT NULL,
+	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	CONSTRAINT deployment_id_fk FOREIGN KEY(deployment_id) REFERENCES pgml.deployments(id) ON DELETE CASCADE,
+	CONSTRAINT model_id_fk FOREIGN KEY(model_id) REFERENCES pgml.models(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS deployment_arms_deployment_id_idx ON pgml.deployment_arms(deployment_id);
+
+---
+--- Shadow models score the same inputs as the live model, without serving their predictions
+---
+CREATE TABLE IF NOT EXISTS pgml.shadow_predictions(
+	id BIGSERIAL PRIMARY KEY,
+	deployment_id BIGINT NOT NULL,
+	model_id BIGINT NOT NULL,
+	shadow_model_id BIGINT NOT NULL,
+	features REAL[] NOT NULL,
+	prediction REAL NOT NULL,
+	shadow_prediction REAL NOT NULL,
+	key TEXT,
+	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	CONSTRAINT deployment_id_fk FOREIGN KEY(deployment_id) REFERENCES pgml.deployments(id) ON DELETE CASCADE,
+	CONSTRAINT model_id_fk FOREIGN KEY(model_id) REFERENCES pgml.models(id) ON DELETE CASCADE,
+	CONSTRAINT shadow_model_id_fk FOREIGN KEY(shadow_model_id) REFERENCES pgml.models(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS shadow_predictions_deployment_id_idx ON pgml.shadow_predictions(deployment_id);
+
+---
+--- Predictions served to projects that log them, and their outcomes once they're known
+---
+CREATE TABLE IF NOT EXISTS pgml.predictions(
+	id BIGSERIAL PRIMARY KEY,
+	project_id BIGINT NOT NULL,
+	model_id BIGINT NOT NULL,
+	features REAL[] NOT NULL,
+	prediction REAL NOT NULL,
+	key TEXT,
+	actual REAL,
+	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	CONSTRAINT project_id_fk FOREIGN KEY(project_id) REFERENCES pgml.projects(id) ON DELETE CASCADE,
+	CONSTRAINT model_id_fk FOREIGN KEY(model_id) REFERENCES pgml.models(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS predictions_project_id_key_idx ON pgml.predictions(project_id, key);
+CREATE INDEX IF NOT EXISTS predictions_project_id_created_at_idx ON pgml.predictions(project_id, created_at);
+SELECT pgml.auto_updated_at('pgml.predictions');
+
+---
+--- Retraining policies snapshot a relation again and retrain the project on a schedule,
+--- or once the relation gained or lost enough rows since the last snapshot
+---
+CREATE TABLE IF NOT EXISTS pgml.retrain_policies(
+	id BIGSERIAL PRIMARY KEY,
+	project_id BIGINT NOT NULL,
+	relation_name TEXT NOT NULL,
+	y_column_name TEXT[] NOT NULL,
+	algorithm TEXT NOT NULL,
+	hyperparams JSONB NOT NULL,
+	search TEXT,
+	search_params JSONB NOT NULL,
+	search_args JSONB NOT NULL,
+	preprocess JSONB NOT NULL,
+	test_size REAL NOT NULL DEFAULT 0.25,
+	test_sampling pgml.sampling NOT NULL DEFAULT 'last',
+	schedule INTERVAL,
+	row_delta BIGINT,
+	enabled BOOLEAN NOT NULL DEFAULT true,
+	owner TEXT NOT NULL DEFAULT current_user,
+	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	CONSTRAINT project_id_fk FOREIGN KEY(project_id) REFERENCES pgml.projects(id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX IF NOT EXISTS retrain_policies_project_id_idx ON pgml.retrain_policies(project_id);
+SELECT pgml.auto_updated_at('pgml.retrain_policies');
+
+---
+--- Each time a policy retrained its project, and how it went
+---
+CREATE TABLE IF NOT EXISTS pgml.retrain_runs(
+	id BIGSERIAL PRIMARY KEY,
+	policy_id BIGINT NOT NULL,
+	project_id BIGINT NOT NULL,
+	reason TEXT NOT NULL,
+	status TEXT NOT NULL,
+	model_id BIGINT,
+	deployed BOOLEAN,
+	worker BOOLEAN NOT NULL DEFAULT false,
+	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT clock_timestamp(),
+	CONSTRAINT policy_id_fk FOREIGN KEY(policy_id) REFERENCES pgml.retrain_policies(id) ON DELETE CASCADE,
+	CONSTRAINT project_id_fk FOREIGN KEY(project_id) REFERENCES pgml.projects(id) ON DELETE CASCADE,
+	CONSTRAINT model_id_fk FOREIGN KEY(model_id) REFERENCES pgml.models(id) ON DELETE SET NULL
+);
+CREATE INDEX IF NOT EXISTS retrain_runs_policy_id_idx ON pgml.retrain_runs(policy_id);
+SELECT pgml.auto_updated_at('pgml.retrain_runs');
+
 ---
 --- Distribute serialized models consistently for HA
 ---
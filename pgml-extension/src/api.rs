@@ -1,7 +1,6 @@
 use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::fmt::Write;
-use std::str::FromStr;
 
 use once_cell::sync::Lazy;
 use pgx::*;
@@ -12,6 +11,7 @@ use serde_json::json;
 use crate::orm::Algorithm;
 use crate::orm::Model;
 use crate::orm::Project;
+use crate::orm::RetrainPolicy;
 use crate::orm::Runtime;
 use crate::orm::Sampling;
 use crate::orm::Search;
@@ -19,14 +19,29 @@ use crate::orm::Snapshot;
 use crate::orm::Strategy;
 use crate::orm::Task;
 
-static PROJECT_ID_TO_DEPLOYED_MODEL_ID: PgLwLock<heapless::FnvIndexMap<i64, i64, 1024>> =
+static PROJECT_ID_TO_DEPLOYMENT_ID: PgLwLock<heapless::FnvIndexMap<i64, i64, 1024>> =
+    PgLwLock::new();
+static PROJECT_ID_TO_LOG_PREDICTIONS: PgLwLock<heapless::FnvIndexMap<i64, bool, 1024>> =
+    PgLwLock::new();
+// Deployments never change once they're made, so their arms and shadows never go stale.
+static DEPLOYMENT_ID_TO_ARMS: PgLwLock<
+    heapless::FnvIndexMap<i64, heapless::Vec<(i64, f32), MAX_ARMS>, 1024>,
+> = PgLwLock::new();
+static DEPLOYMENT_ID_TO_SHADOW_MODEL_ID: PgLwLock<heapless::FnvIndexMap<i64, Option<i64>, 1024>> =
     PgLwLock::new();
 static PROJECT_NAME_TO_PROJECT_ID: Lazy<Mutex<HashMap<String, i64>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// The most models a deployment can split its traffic between.
+const MAX_ARMS: usize = 16;
+
 #[pg_guard]
 pub extern "C" fn _PG_init() {
-    pg_shmem_init!(PROJECT_ID_TO_DEPLOYED_MODEL_ID);
+    pg_shmem_init!(PROJECT_ID_TO_DEPLOYMENT_ID);
+    pg_shmem_init!(PROJECT_ID_TO_LOG_PREDICTIONS);
+    pg_shmem_init!(DEPLOYMENT_ID_TO_ARMS);
+    pg_shmem_init!(DEPLOYMENT_ID_TO_SHADOW_MODEL_ID);
+    crate::worker::init();
 }
 
 #[cfg(feature = "python")]
@@ -119,6 +134,56 @@ fn train(
     test_sampling: default!(Sampling, "'last'"),
     runtime: Option<default!(Runtime, "NULL")>,
     automatic_deploy: Option<default!(bool, true)>,
+    preprocess: default!(JsonB, "'{}'"),
+    time_column_name: Option<default!(&str, "NULL")>,
+    metric: Option<default!(&str, "NULL")>,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(project, String),
+        name!(task, String),
+        name!(algorithm, String),
+        name!(deployed, bool),
+    ),
+> {
+    train_joint(
+        project_name,
+        task,
+        relation_name,
+        y_column_name.map(|y_column_name| vec![y_column_name.to_string()]),
+        algorithm,
+        hyperparams,
+        search,
+        search_params,
+        search_args,
+        test_size,
+        test_sampling,
+        runtime,
+        automatic_deploy,
+        preprocess,
+        time_column_name,
+        metric,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+#[pg_extern]
+fn train_joint(
+    project_name: &str,
+    task: Option<default!(Task, "NULL")>,
+    relation_name: Option<default!(&str, "NULL")>,
+    y_column_name: Option<default!(Vec<String>, "NULL")>,
+    algorithm: default!(Algorithm, "'linear'"),
+    hyperparams: default!(JsonB, "'{}'"),
+    search: Option<default!(Search, "NULL")>,
+    search_params: default!(JsonB, "'{}'"),
+    search_args: default!(JsonB, "'{}'"),
+    test_size: default!(f32, 0.25),
+    test_sampling: default!(Sampling, "'last'"),
+    runtime: Option<default!(Runtime, "NULL")>,
+    automatic_deploy: Option<default!(bool, true)>,
+    preprocess: default!(JsonB, "'{}'"),
+    time_column_name: Option<default!(&str, "NULL")>,
+    metric: Option<default!(&str, "NULL")>,
 ) -> impl std::iter::Iterator<
     Item = (
         name!(project, String),
@@ -127,11 +192,59 @@ fn train(
         name!(deployed, bool),
     ),
 > {
-    let project = match Project::find_by_name(project_name) {
+    let (project, model, deployed) = train_model(
+        project_name,
+        task,
+        relation_name,
+        y_column_name,
+        algorithm,
+        hyperparams,
+        search,
+        search_params,
+        search_args,
+        test_size,
+        test_sampling,
+        runtime,
+        automatic_deploy,
+        preprocess,
+        time_column_name,
+        metric,
+    );
+    vec![(
+        project.name,
+        project.task.to_string(),
+        model.algorithm.to_string(),
+        deployed,
+    )]
+    .into_iter()
+}
+
+/// Train a model of the project, and deploy it if it's better than the deployed one.
+/// Returns the model, so callers know which one they trained.
+#[allow(clippy::too_many_arguments)]
+fn train_model(
+    project_name: &str,
+    task: Option<Task>,
+    relation_name: Option<&str>,
+    y_column_name: Option<Vec<String>>,
+    algorithm: Algorithm,
+    hyperparams: JsonB,
+    search: Option<Search>,
+    search_params: JsonB,
+    search_args: JsonB,
+    test_size: f32,
+    test_sampling: Sampling,
+    runtime: Option<Runtime>,
+    automatic_deploy: Option<bool>,
+    preprocess: JsonB,
+    time_column_name: Option<&str>,
+    metric: Option<&str>,
+) -> (Project, Model, bool) {
+    let mut project = match Project::find_by_name(project_name) {
         Some(project) => project,
         None => Project::create(project_name, match task {
             Some(task) => task,
-            None => error!("Project `{}` does not exist. To create a new project, provide the task (regression or classification).", project_name),
+            None => error!("Project `{}` does not exist. To create a new project, provide the task (regression, classification or clustering).", project_name),
         }),
     };
 
@@ -139,8 +252,32 @@ fn train(
         error!("Project `{:?}` already exists with a different task: `{:?}`. Create a new project instead.", project.name, project.task);
     }
 
+    if let Some(metric) = metric {
+        project.update_metric(metric);
+    }
+
+    if let Some(y_column_name) = &y_column_name {
+        if project.task == Task::clustering && !y_column_name.is_empty() {
+            error!("Clustering does not use a `y_column_name`, the model will learn the groups from the features.");
+        }
+
+        if y_column_name.len() > 1 && project.task != Task::regression {
+            error!(
+                "Joint models with more than one `y_column_name` only support regression, got `{:?}`.",
+                project.task
+            );
+        }
+    }
+
     let snapshot = match relation_name {
         None => {
+            if time_column_name.is_some() {
+                error!("`time_column_name` is only used when snapshotting a new `relation_name`.");
+            }
+            if !preprocess.0.as_object().map_or(true, |p| p.is_empty()) {
+                error!("`preprocess` is only used when snapshotting a new `relation_name`.");
+            }
+
             let snapshot = project
                 .last_snapshot()
                 .expect("You must pass a `relation_name` and `y_column_name` to snapshot the first time you train a model.");
@@ -156,12 +293,19 @@ fn train(
                 relation_name
             );
 
+            let y_column_name = match project.task {
+                Task::clustering => Vec::new(),
+                _ => y_column_name
+                    .expect("You must pass a `y_column_name` when you pass a `relation_name`"),
+            };
+
             let snapshot = Snapshot::create(
                 relation_name,
-                y_column_name
-                    .expect("You must pass a `y_column_name` when you pass a `relation_name`"),
+                y_column_name,
                 test_size,
                 test_sampling,
+                time_column_name,
+                preprocess,
             );
 
             info!(
@@ -189,7 +333,7 @@ fn train(
         runtime,
     );
 
-    let new_metrics: &serde_json::Value = &model.metrics.unwrap().0;
+    let new_metrics: &serde_json::Value = &model.metrics.as_ref().unwrap().0;
     let new_metrics = new_metrics.as_object().unwrap();
 
     let deployed_metrics = Spi::get_one_with_args::<JsonB>(
@@ -213,20 +357,14 @@ fn train(
         Some(true) | None => {
             if let Some(deployed_metrics) = deployed_metrics {
                 let deployed_metrics = deployed_metrics.0.as_object().unwrap();
-                match project.task {
-                    Task::classification => {
-                        if deployed_metrics.get("f1").unwrap().as_f64()
-                            > new_metrics.get("f1").unwrap().as_f64()
-                        {
-                            deploy = false;
-                        }
-                    }
-                    Task::regression => {
-                        if deployed_metrics.get("r2").unwrap().as_f64()
-                            > new_metrics.get("r2").unwrap().as_f64()
-                        {
-                            deploy = false;
-                        }
+                let metric = project.target_metric();
+                let deployed_metric = deployed_metrics.get(metric).and_then(|m| m.as_f64());
+                let new_metric = new_metrics.get(metric).and_then(|m| m.as_f64());
+                if let (Some(deployed_metric), Some(new_metric)) = (deployed_metric, new_metric) {
+                    if project.greater_is_better() {
+                        deploy = new_metric >= deployed_metric;
+                    } else {
+                        deploy = new_metric <= deployed_metric;
                     }
                 }
             }
@@ -235,30 +373,30 @@ fn train(
         Some(false) => deploy = false,
     };
 
+    // Replacing a traffic split or a shadow would end the experiment early,
+    // so that's left to an explicit `pgml.deploy`.
     if deploy {
-        Spi::get_one_with_args::<i64>(
-            "INSERT INTO pgml.deployments (project_id, model_id, strategy) VALUES ($1, $2, $3::pgml.strategy) RETURNING id",
-            vec![
-                (PgBuiltInOids::INT8OID.oid(), project.id.into_datum()),
-                (PgBuiltInOids::INT8OID.oid(), model.id.into_datum()),
-                (PgBuiltInOids::TEXTOID.oid(), Strategy::most_recent.to_string().into_datum()),
-            ],
+        let deployment_id = Spi::get_one_with_args::<i64>(
+            "SELECT id FROM pgml.deployments WHERE project_id = $1 ORDER BY created_at DESC LIMIT 1",
+            vec![(PgBuiltInOids::INT8OID.oid(), project.id.into_datum())],
         );
-        let mut projects = PROJECT_ID_TO_DEPLOYED_MODEL_ID.exclusive();
-        if projects.len() == 1024 {
-            warning!("Active projects has exceeded capacity map, clearing caches.");
-            projects.clear();
+        if let Some(deployment_id) = deployment_id {
+            if deployment_arms(deployment_id).len() > 1 || shadow_model_id(deployment_id).is_some()
+            {
+                info!(
+                    "Not deploying model {}, the project is splitting its traffic or scoring a shadow model. Use `pgml.deploy` to replace them.",
+                    model.id
+                );
+                deploy = false;
+            }
         }
-        projects.insert(project.id, model.id).unwrap();
     }
 
-    vec![(
-        project.name,
-        project.task.to_string(),
-        model.algorithm.to_string(),
-        deploy,
-    )]
-    .into_iter()
+    if deploy {
+        create_deployment(project.id, Strategy::most_recent, &[(model.id, 1.)], None);
+    }
+
+    (project, model, deploy)
 }
 
 #[pg_extern]
@@ -266,6 +404,7 @@ fn deploy(
     project_name: &str,
     strategy: Strategy,
     algorithm: Option<default!(Algorithm, "NULL")>,
+    traffic: Option<default!(f32, "NULL")>,
 ) -> impl std::iter::Iterator<
     Item = (
         name!(project, String),
@@ -273,15 +412,8 @@ fn deploy(
         name!(algorithm, String),
     ),
 > {
-    let (project_id, task) = Spi::get_two_with_args::<i64, String>(
-        "SELECT id, task::TEXT from pgml.projects WHERE name = $1",
-        vec![(PgBuiltInOids::TEXTOID.oid(), project_name.into_datum())],
-    );
-
-    let project_id =
-        project_id.unwrap_or_else(|| error!("Project named `{}` does not exist.", project_name));
-
-    let task = Task::from_str(&task.unwrap()).unwrap();
+    let project = Project::find_by_name(project_name)
+        .unwrap_or_else(|| error!("Project named `{}` does not exist.", project_name));
 
     let mut sql = "SELECT models.id, models.algorithm::TEXT FROM pgml.models JOIN pgml.projects ON projects.id = models.project_id".to_string();
     let mut predicate = "\nWHERE projects.name = $1".to_string();
@@ -293,26 +425,33 @@ fn deploy(
         );
     }
     match strategy {
-        Strategy::best_score => match task {
-            Task::regression => {
-                let _ = write!(
-                    sql,
-                    "{predicate}\nORDER BY models.metrics->>'r2' DESC NULLS LAST"
-                );
-            }
-
-            Task::classification => {
-                let _ = write!(
-                    sql,
-                    "{predicate}\nORDER BY models.metrics->>'f1' DESC NULLS LAST"
-                );
-            }
-        },
+        Strategy::best_score => {
+            let _ = write!(
+                sql,
+                "{predicate}\nORDER BY (models.metrics->>'{}')::FLOAT {} NULLS LAST",
+                project.target_metric(),
+                if project.greater_is_better() {
+                    "DESC"
+                } else {
+                    "ASC"
+                },
+            );
+        }
 
         Strategy::most_recent => {
             let _ = write!(sql, "{predicate}\nORDER by models.created_at DESC");
         }
 
+        // The most recent model that isn't live yet, in any arm of the deployment.
+        Strategy::canary | Strategy::shadow => {
+            let _ = write!(
+                sql,
+                "{predicate}
+                AND models.id != ALL($2)
+                ORDER by models.created_at DESC"
+            );
+        }
+
         Strategy::rollback => {
             let _ = write!(
                 sql,
@@ -333,159 +472,1185 @@ fn deploy(
             "
             );
         }
+        Strategy::ab_test => {
+            error!("Use `pgml.deploy_split` to split traffic between models for an A/B test.")
+        }
         _ => error!("invalid stategy"),
     }
+    if strategy != Strategy::canary && traffic.is_some() {
+        error!("`traffic` is only used by the canary strategy.");
+    }
+
+    // A canary gets a share of the traffic of the live model, which stays deployed,
+    // and a shadow scores the same inputs.
+    let live_model_ids: Vec<i64> = match strategy {
+        Strategy::canary | Strategy::shadow => deployment_arms(deployment_id(project_name))
+            .into_iter()
+            .map(|(model_id, _)| model_id)
+            .collect(),
+        _ => Vec::new(),
+    };
+
     sql += "\nLIMIT 1";
     let (model_id, algorithm) = Spi::get_two_with_args::<i64, String>(
         &sql,
-        vec![(PgBuiltInOids::TEXTOID.oid(), project_name.into_datum())],
+        vec![
+            (PgBuiltInOids::TEXTOID.oid(), project_name.into_datum()),
+            (
+                PgBuiltInOids::INT8ARRAYOID.oid(),
+                live_model_ids.into_datum(),
+            ),
+        ],
     );
     let model_id = model_id.expect("No qualified models exist for this deployment.");
     let algorithm = algorithm.expect("No qualified models exist for this deployment.");
 
-    Spi::get_one_with_args::<i64>(
-        "INSERT INTO pgml.deployments (project_id, model_id, strategy) VALUES ($1, $2, $3::pgml.strategy) RETURNING id",
+    match strategy {
+        Strategy::canary => {
+            let traffic = traffic.unwrap_or(0.1);
+            if traffic <= 0. || traffic >= 1. {
+                error!(
+                    "The canary's `traffic` must be between 0 and 1, got {}.",
+                    traffic
+                );
+            }
+            create_deployment(
+                project.id,
+                strategy,
+                &[(live_model_ids[0], 1. - traffic), (model_id, traffic)],
+                None,
+            );
+        }
+        Strategy::shadow => {
+            let arms = deployment_arms(deployment_id(project_name));
+            create_deployment(project.id, strategy, &arms, Some(model_id));
+        }
+        _ => create_deployment(project.id, strategy, &[(model_id, 1.)], None),
+    };
+
+    vec![(project_name.to_string(), strategy.to_string(), algorithm)].into_iter()
+}
+
+/// Split the traffic of a project between several of its models, e.g. for an A/B test.
+/// Traffic is normalized to add up to 1. The first model is considered the live one.
+#[pg_extern]
+fn deploy_split(
+    project_name: &str,
+    model_ids: Vec<i64>,
+    traffic: Vec<f32>,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(project, String),
+        name!(model_id, i64),
+        name!(traffic, f32),
+    ),
+> {
+    let project = Project::find_by_name(project_name)
+        .unwrap_or_else(|| error!("Project named `{}` does not exist.", project_name));
+    if model_ids.is_empty() || model_ids.len() != traffic.len() {
+        error!(
+            "Expected the same number of `model_ids` and `traffic`, got {} and {}.",
+            model_ids.len(),
+            traffic.len()
+        );
+    }
+    if model_ids.len() > MAX_ARMS {
+        error!(
+            "Traffic can be split between at most {} models, got {}.",
+            MAX_ARMS,
+            model_ids.len()
+        );
+    }
+    if traffic.iter().any(|&traffic| traffic <= 0.) {
+        error!("`traffic` must be positive, got {:?}.", traffic);
+    }
+    for &model_id in &model_ids {
+        let project_id = Spi::get_one_with_args::<i64>(
+            "SELECT project_id FROM pgml.models WHERE id = $1",
+            vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+        );
+        if project_id != Some(project.id) {
+            error!(
+                "Model {} does not belong to the project named `{}`.",
+                model_id, project_name
+            );
+        }
+    }
+
+    let total: f32 = traffic.iter().sum();
+    let arms: Vec<(i64, f32)> = model_ids
+        .into_iter()
+        .zip(traffic)
+        .map(|(model_id, traffic)| (model_id, traffic / total))
+        .collect();
+    create_deployment(project.id, Strategy::ab_test, &arms, None);
+
+    arms.into_iter()
+        .map(|(model_id, traffic)| (project_name.to_string(), model_id, traffic))
+        .collect::<Vec<(String, i64, f32)>>()
+        .into_iter()
+}
+
+/// Record a deployment, and make it live. Deployments with more than one model
+/// split their traffic between them, and the shadow model scores their inputs too.
+fn create_deployment(
+    project_id: i64,
+    strategy: Strategy,
+    arms: &[(i64, f32)],
+    shadow_model_id: Option<i64>,
+) {
+    let deployment_id = Spi::get_one_with_args::<i64>(
+        "INSERT INTO pgml.deployments (project_id, model_id, strategy, shadow_model_id) VALUES ($1, $2, $3::pgml.strategy, $4) RETURNING id",
         vec![
             (PgBuiltInOids::INT8OID.oid(), project_id.into_datum()),
-            (PgBuiltInOids::INT8OID.oid(), model_id.into_datum()),
+            (PgBuiltInOids::INT8OID.oid(), arms[0].0.into_datum()),
             (PgBuiltInOids::TEXTOID.oid(), strategy.to_string().into_datum()),
+            (PgBuiltInOids::INT8OID.oid(), shadow_model_id.into_datum()),
         ]
+    )
+    .unwrap();
+
+    if arms.len() > 1 {
+        for (model_id, traffic) in arms {
+            Spi::get_one_with_args::<i64>(
+                "INSERT INTO pgml.deployment_arms (deployment_id, model_id, traffic) VALUES ($1, $2, $3) RETURNING id",
+                vec![
+                    (PgBuiltInOids::INT8OID.oid(), deployment_id.into_datum()),
+                    (PgBuiltInOids::INT8OID.oid(), model_id.into_datum()),
+                    (PgBuiltInOids::FLOAT4OID.oid(), traffic.into_datum()),
+                ],
+            );
+        }
+    }
+
+    cache_deployment(project_id, deployment_id);
+}
+
+/// Make the deployment live for every connection.
+fn cache_deployment(project_id: i64, deployment_id: i64) {
+    let mut projects = PROJECT_ID_TO_DEPLOYMENT_ID.exclusive();
+    if projects.len() == 1024 {
+        warning!("Active projects has exceeded capacity map, clearing caches.");
+        projects.clear();
+    }
+    projects.insert(project_id, deployment_id).unwrap();
+}
+
+/// Start or stop recording the predictions of a project in `pgml.predictions`.
+#[pg_extern]
+fn log_predictions(project_name: &str, enabled: default!(bool, true)) -> bool {
+    let project_id = project_id(project_name);
+    Spi::get_one_with_args::<i64>(
+        "UPDATE pgml.projects SET log_predictions = $2 WHERE id = $1 RETURNING id",
+        vec![
+            (PgBuiltInOids::INT8OID.oid(), project_id.into_datum()),
+            (PgBuiltInOids::BOOLOID.oid(), enabled.into_datum()),
+        ],
     );
+    cache_log_predictions(project_id, enabled);
+    enabled
+}
+
+/// Whether the project records its predictions, cached in shared memory.
+fn logs_predictions(project_id: i64) -> bool {
+    if let Some(enabled) = PROJECT_ID_TO_LOG_PREDICTIONS.share().get(&project_id) {
+        return *enabled;
+    }
+
+    let enabled = Spi::get_one_with_args::<bool>(
+        "SELECT log_predictions FROM pgml.projects WHERE id = $1",
+        vec![(PgBuiltInOids::INT8OID.oid(), project_id.into_datum())],
+    )
+    .unwrap_or(false);
+    cache_log_predictions(project_id, enabled);
+    enabled
+}
 
-    let mut projects = PROJECT_ID_TO_DEPLOYED_MODEL_ID.exclusive();
+fn cache_log_predictions(project_id: i64, enabled: bool) {
+    let mut projects = PROJECT_ID_TO_LOG_PREDICTIONS.exclusive();
     if projects.len() == 1024 {
         warning!("Active projects has exceeded capacity map, clearing caches.");
         projects.clear();
     }
-    projects.insert(project_id, model_id).unwrap();
+    projects.insert(project_id, enabled).unwrap();
+}
 
-    vec![(project_name.to_string(), strategy.to_string(), algorithm)].into_iter()
+/// Record a prediction, with its features before they were scaled.
+fn log_prediction(
+    project_id: i64,
+    model_id: i64,
+    features: Vec<Option<f32>>,
+    prediction: f32,
+    key: Option<&str>,
+) {
+    Spi::get_one_with_args::<i64>(
+        "INSERT INTO pgml.predictions (project_id, model_id, features, prediction, key) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        vec![
+            (PgBuiltInOids::INT8OID.oid(), project_id.into_datum()),
+            (PgBuiltInOids::INT8OID.oid(), model_id.into_datum()),
+            (PgBuiltInOids::FLOAT4ARRAYOID.oid(), features.into_datum()),
+            (PgBuiltInOids::FLOAT4OID.oid(), prediction.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), key.into_datum()),
+        ],
+    );
 }
 
+/// Record the actual outcome of the logged predictions made with the `key`,
+/// and return how many there were.
 #[pg_extern]
-fn predict(project_name: &str, features: Vec<f32>) -> f32 {
-    let mut projects = PROJECT_NAME_TO_PROJECT_ID.lock();
-    let project_id = match projects.get(project_name) {
-        Some(project_id) => *project_id,
-        None => {
-            let (project_id, model_id) = Spi::get_two_with_args::<i64, i64>(
-                "SELECT deployments.project_id, deployments.model_id 
-                FROM pgml.deployments
-                JOIN pgml.projects ON projects.id = deployments.project_id
-                WHERE projects.name = $1 
-                ORDER BY deployments.created_at DESC
-                LIMIT 1",
-                vec![(PgBuiltInOids::TEXTOID.oid(), project_name.into_datum())],
-            );
-            let project_id = project_id.unwrap_or_else(|| {
-                error!(
-                    "No deployed model exists for the project named: `{}`",
-                    project_name
-                )
-            });
-            let model_id = model_id.unwrap_or_else(|| {
-                error!(
-                    "No deployed model exists for the project named: `{}`",
-                    project_name
-                )
-            });
-            projects.insert(project_name.to_string(), project_id);
-            let mut projects = PROJECT_ID_TO_DEPLOYED_MODEL_ID.exclusive();
-            if projects.len() == 1024 {
-                warning!("Active projects have exceeded capacity map, clearing caches.");
-                projects.clear();
-            }
-            projects.insert(project_id, model_id).unwrap();
-            project_id
-        }
-    };
+fn record_outcome(project_name: &str, key: &str, actual: f32) -> i64 {
+    let project_id = project_id(project_name);
+    let count = Spi::get_one_with_args::<i64>(
+        "WITH updated AS (
+            UPDATE pgml.predictions SET actual = $3 WHERE project_id = $1 AND key = $2 RETURNING id
+        )
+        SELECT count(*) FROM updated",
+        vec![
+            (PgBuiltInOids::INT8OID.oid(), project_id.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), key.into_datum()),
+            (PgBuiltInOids::FLOAT4OID.oid(), actual.into_datum()),
+        ],
+    )
+    .unwrap();
+    if count == 0 {
+        warning!(
+            "No logged predictions of the project named `{}` have the key `{}`.",
+            project_name,
+            key
+        );
+    }
+    count
+}
 
-    let model_id = *PROJECT_ID_TO_DEPLOYED_MODEL_ID
-        .share()
-        .get(&project_id)
-        .unwrap();
-    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
-    estimator.predict(&features)
+/// The task's metrics of predictions against their actual outcomes,
+/// or NULL when there are no outcomes to score.
+#[pg_extern(immutable, parallel_safe)]
+fn outcome_metrics(task: Task, predictions: Vec<f32>, actuals: Vec<f32>) -> Option<JsonB> {
+    if predictions.len() != actuals.len() {
+        error!(
+            "Expected as many `predictions` as `actuals`, got {} and {}.",
+            predictions.len(),
+            actuals.len()
+        );
+    }
+    if predictions.is_empty() {
+        return None;
+    }
+    Some(JsonB(json!(crate::orm::model::outcome_metrics(
+        task,
+        &predictions,
+        &actuals
+    ))))
 }
 
+// Live metrics of the logged predictions that have an outcome, for each model and window
+// of time since `since`, e.g. each hour of the last day. Windows start at `since`,
+// or count from the Unix epoch when all the predictions are scored.
+extension_sql!(
+    r#"
+CREATE FUNCTION pgml.live_metrics(
+    project_name TEXT,
+    since TIMESTAMP WITHOUT TIME ZONE DEFAULT NULL,
+    window_size INTERVAL DEFAULT INTERVAL '1 day'
+)
+RETURNS TABLE(model_id BIGINT, window_start TIMESTAMP WITHOUT TIME ZONE, outcomes BIGINT, metrics JSONB)
+LANGUAGE sql STABLE AS $$
+SELECT
+    predictions.model_id,
+    windows.start,
+    count(*),
+    pgml.outcome_metrics(
+        projects.task,
+        array_agg(predictions.prediction ORDER BY predictions.id),
+        array_agg(predictions.actual ORDER BY predictions.id)
+    )
+FROM pgml.predictions
+JOIN pgml.projects ON projects.id = predictions.project_id
+CROSS JOIN LATERAL (
+    SELECT COALESCE(live_metrics.since, TIMESTAMP '1970-01-01') + live_metrics.window_size * floor(
+        extract(EPOCH FROM predictions.created_at - COALESCE(live_metrics.since, TIMESTAMP '1970-01-01'))
+        / extract(EPOCH FROM live_metrics.window_size)
+    ) AS start
+) windows
+WHERE projects.name = live_metrics.project_name
+    AND predictions.actual IS NOT NULL
+    AND predictions.created_at >= COALESCE(live_metrics.since, '-infinity')
+GROUP BY projects.task, predictions.model_id, windows.start
+ORDER BY windows.start, predictions.model_id;
+$$;
+"#,
+    name = "live_metrics",
+    requires = ["schema", outcome_metrics],
+);
+
+/// Predict with the deployed model. When traffic is split between several models,
+/// the `key` routes the same caller to the same model every time. A shadow model
+/// predicts the same features, and both predictions are logged, but only the
+/// deployed model's is returned. Projects that log their predictions record them
+/// with the `key`, to match them with their outcomes later.
 #[pg_extern]
-fn snapshot(
-    relation_name: &str,
-    y_column_name: &str,
-    test_size: default!(f32, 0.25),
-    test_sampling: default!(Sampling, "'last'"),
-) -> impl std::iter::Iterator<Item = (name!(relation, String), name!(y_column_name, String))> {
-    Snapshot::create(relation_name, y_column_name, test_size, test_sampling);
-    vec![(relation_name.to_string(), y_column_name.to_string())].into_iter()
+fn predict(
+    project_name: &str,
+    features: Vec<Option<f32>>,
+    key: Option<default!(&str, "NULL")>,
+) -> f32 {
+    serve(project_name, key, Output::predictions, |_| features.clone())
+        .first()
+        .copied()
+        .unwrap_or_else(|| error!("Expected features to predict, got an empty array."))
 }
 
 #[pg_extern]
-fn load_dataset(
-    source: &str,
-    limit: Option<default!(i64, "NULL")>,
-) -> impl std::iter::Iterator<Item = (name!(table_name, String), name!(rows, i64))> {
-    // cast limit since pgx doesn't support usize
-    let limit: Option<usize> = limit.map(|limit| limit.try_into().unwrap());
-    let (name, rows) = match source {
-        "breast_cancer" => crate::orm::dataset::load_breast_cancer(limit),
-        "diabetes" => crate::orm::dataset::load_diabetes(limit),
-        "digits" => crate::orm::dataset::load_digits(limit),
-        "iris" => crate::orm::dataset::load_iris(limit),
-        _ => error!("Unknown source: `{source}`"),
+fn predict_row(project_name: &str, row: AnyElement) -> f32 {
+    let row = unsafe { direct_function_call::<Json>(pg_sys::row_to_json, vec![Some(row.datum())]) }
+        .unwrap();
+    let row = row
+        .0
+        .as_object()
+        .unwrap_or_else(|| error!("Expected a row, got: {}", row.0));
+
+    // Each model matches the columns of the row to the features of its own snapshot.
+    serve(project_name, None, Output::predictions, |model_id| {
+        Snapshot::find_by_model_id(model_id)
+            .features_from_row(row)
+            .into_iter()
+            .map(Some)
+            .collect()
+    })[0]
+}
+
+/// What the predictions of an entry point are for each row.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq)]
+enum Output {
+    /// The prediction of each label.
+    predictions,
+    /// The probability of each class.
+    probabilities,
+}
+
+/// Serve the predictions of the deployed model for rows of features. Every entry point goes
+/// through here, so a shadow model scores the same rows, and projects that log their
+/// predictions record them. Both record a single prediction per row, probabilities are
+/// recorded as their most likely class. `features` are the features of a row for a model,
+/// before they're imputed and scaled.
+fn serve(
+    project_name: &str,
+    key: Option<&str>,
+    output: Output,
+    features: impl Fn(i64) -> Vec<Option<f32>>,
+) -> Vec<f32> {
+    let predict = |model_id: i64, features: Vec<Option<f32>>| match output {
+        Output::predictions => model_predict_batch(model_id, features),
+        Output::probabilities => model_predict_proba(model_id, features),
     };
 
-    vec![(name, rows)].into_iter()
+    let model_id = serving_model_id(project_name, key);
+    let model_features = features(model_id);
+    if model_features.is_empty() {
+        return Vec::new();
+    }
+    let deployment_id = deployment_id(project_name);
+    let shadow_model_id = shadow_model_id(deployment_id);
+    let project_id = project_id(project_name);
+    let logs_predictions = logs_predictions(project_id);
+    if shadow_model_id.is_none() && !logs_predictions {
+        return predict(model_id, model_features);
+    }
+
+    let predictions = predict(model_id, model_features.clone());
+    let num_features = Snapshot::find_by_model_id(model_id).num_features();
+    let rows: Vec<Vec<Option<f32>>> = model_features
+        .chunks(num_features)
+        .map(|row| row.to_vec())
+        .collect();
+    let recorded = |predictions: &[f32]| -> Vec<f32> {
+        predictions
+            .chunks(predictions.len() / rows.len())
+            .map(|row| match (output, row.len()) {
+                (Output::predictions, 1) => row[0],
+                (Output::predictions, _) => error!(
+                    "Predictions of joint models can't be logged or shadowed, they predict more than one label."
+                ),
+                (Output::probabilities, _) => row
+                    .iter()
+                    .enumerate()
+                    .fold((0, f32::MIN), |max, (class, &probability)| {
+                        if probability > max.1 {
+                            (class, probability)
+                        } else {
+                            max
+                        }
+                    })
+                    .0 as f32,
+            })
+            .collect()
+    };
+    let recorded_predictions = recorded(&predictions);
+
+    if let Some(shadow_model_id) = shadow_model_id {
+        let shadow_predictions = recorded(&predict(shadow_model_id, features(shadow_model_id)));
+        for ((row, prediction), shadow_prediction) in rows
+            .iter()
+            .zip(&recorded_predictions)
+            .zip(shadow_predictions)
+        {
+            Spi::get_one_with_args::<i64>(
+                "INSERT INTO pgml.shadow_predictions (deployment_id, model_id, shadow_model_id, features, prediction, shadow_prediction, key) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+                vec![
+                    (PgBuiltInOids::INT8OID.oid(), deployment_id.into_datum()),
+                    (PgBuiltInOids::INT8OID.oid(), model_id.into_datum()),
+                    (PgBuiltInOids::INT8OID.oid(), shadow_model_id.into_datum()),
+                    (PgBuiltInOids::FLOAT4ARRAYOID.oid(), row.clone().into_datum()),
+                    (PgBuiltInOids::FLOAT4OID.oid(), prediction.into_datum()),
+                    (PgBuiltInOids::FLOAT4OID.oid(), shadow_prediction.into_datum()),
+                    (PgBuiltInOids::TEXTOID.oid(), key.into_datum()),
+                ],
+            );
+        }
+    }
+    if logs_predictions {
+        for (row, prediction) in rows.into_iter().zip(recorded_predictions) {
+            log_prediction(project_id, model_id, row, prediction, key);
+        }
+    }
+    predictions
 }
 
+// Overloading `pgml.predict` with `anyelement` would make calls with arrays
+// ambiguous, so records get their own signature, e.g. `pgml.predict('project', t.*)`.
+extension_sql!(
+    r#"
+CREATE FUNCTION pgml."predict"("project_name" TEXT, "row" RECORD)
+RETURNS REAL
+STRICT
+LANGUAGE c AS 'MODULE_PATHNAME', 'predict_row_wrapper';
+"#,
+    name = "predict_record",
+    requires = [predict_row],
+);
+
 #[pg_extern]
-fn model_predict(model_id: i64, features: Vec<f32>) -> f32 {
-    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
-    estimator.predict(&features)
+fn predict_joint(project_name: &str, features: Vec<Option<f32>>) -> Vec<f32> {
+    serve(project_name, None, Output::predictions, |_| {
+        features.clone()
+    })
 }
 
 #[pg_extern]
-fn model_predict_batch(model_id: i64, features: Vec<f32>) -> Vec<f32> {
-    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
-    estimator.predict_batch(&features)
+fn predict_batch(project_name: &str, features: Vec<Option<f32>>) -> Vec<f32> {
+    predict_joint(project_name, features)
 }
 
-#[cfg(feature = "python")]
-#[pg_extern(name = "transform")]
-pub fn transform_json(
-    task: JsonB,
-    args: default!(JsonB, "'{}'"),
-    inputs: default!(Vec<String>, "ARRAY[]::TEXT[]"),
-) -> JsonB {
-    JsonB(crate::bindings::transformers::transform(
-        &task.0, &args.0, &inputs,
-    ))
+/// The rows buffered by `pgml.predict_agg`, kept in the aggregate's memory context.
+#[derive(Default)]
+struct PredictAggState {
+    project_name: String,
+    features: Vec<Option<f32>>,
 }
 
-#[cfg(feature = "python")]
-#[pg_extern(name = "transform")]
-pub fn transform_string(
-    task: String,
-    args: default!(JsonB, "'{}'"),
-    inputs: default!(Vec<String>, "ARRAY[]::TEXT[]"),
-) -> JsonB {
-    let mut task_map = HashMap::new();
-    task_map.insert("task", task);
-    let task_json = json!(task_map);
-    JsonB(crate::bindings::transformers::transform(
-        &task_json, &args.0, &inputs,
-    ))
+/// Buffers the features of every row in a group, and predicts them with a
+/// single call to `predict_batch`, which is much faster than calling `predict`
+/// for each row of a large table. Predictions are returned in the order the rows
+/// were aggregated, so use the same `ORDER BY` to line them up with other aggregates, e.g.:
+///
+///   SELECT pgml.predict_agg('project', ARRAY[a, b, c] ORDER BY id), array_agg(id ORDER BY id)
+///   FROM big_table
+///   GROUP BY id / 1000;
+pub struct PredictAgg;
+
+#[pg_aggregate]
+impl Aggregate for PredictAgg {
+    const NAME: &'static str = "predict_agg";
+    type State = Internal;
+    type Args = (
+        name!(project_name, &'static str),
+        name!(features, Vec<Option<f32>>),
+    );
+    type Finalize = Vec<f32>;
+
+    fn state(
+        mut current: Self::State,
+        (project_name, features): Self::Args,
+        fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::State {
+        // The state has to outlive the row, so it's allocated in the aggregate's context.
+        // Its vector grows on the Rust heap, and is freed when the context is.
+        let mut current = Self::in_memory_context(fcinfo, move |_| {
+            unsafe { current.get_or_insert_default::<PredictAggState>() };
+            current
+        });
+        let state = unsafe { current.get_mut::<PredictAggState>() }.unwrap();
+        if state.project_name.is_empty() {
+            state.project_name = project_name.to_string();
+        }
+        state.features.extend(features);
+        current
+    }
+
+    fn finalize(
+        current: Self::State,
+        _direct_args: Self::OrderedSetArgs,
+        _fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::Finalize {
+        match unsafe { current.get::<PredictAggState>() } {
+            Some(state) if !state.features.is_empty() => {
+                predict_batch(&state.project_name, state.features.clone())
+            }
+            _ => Vec::new(),
+        }
+    }
 }
 
-#[cfg(any(test, feature = "pg_test"))]
+#[pg_extern]
+fn predict_proba(project_name: &str, features: Vec<Option<f32>>) -> Vec<f32> {
+    serve(project_name, None, Output::probabilities, |_| {
+        features.clone()
+    })
+}
+
+/// Replace NULL features with the values learned when the model was trained,
+/// and scale them the same way as the training data.
+fn preprocess(model_id: i64, features: Vec<Option<f32>>) -> Vec<f32> {
+    let mut features = if features.iter().all(|feature| feature.is_some()) {
+        features.into_iter().flatten().collect()
+    } else {
+        Snapshot::find_by_model_id(model_id).impute(&features)
+    };
+
+    if let Some(scaler) = crate::orm::file::find_deployed_scaler_by_model_id(model_id) {
+        scaler.transform(&mut features);
+    }
+
+    features
+}
+
+/// The id of the project, cached in process memory.
+fn project_id(project_name: &str) -> i64 {
+    if let Some(project_id) = PROJECT_NAME_TO_PROJECT_ID.lock().get(project_name) {
+        return *project_id;
+    }
+
+    let project_id = Spi::get_one_with_args::<i64>(
+        "SELECT id FROM pgml.projects WHERE name = $1",
+        vec![(PgBuiltInOids::TEXTOID.oid(), project_name.into_datum())],
+    )
+    .unwrap_or_else(|| error!("Project named `{}` does not exist.", project_name));
+    PROJECT_NAME_TO_PROJECT_ID
+        .lock()
+        .insert(project_name.to_string(), project_id);
+    project_id
+}
+
+/// The id of the deployment currently live for the project, cached in shared memory.
+fn deployment_id(project_name: &str) -> i64 {
+    let project_id = project_id(project_name);
+    if let Some(deployment_id) = PROJECT_ID_TO_DEPLOYMENT_ID.share().get(&project_id) {
+        return *deployment_id;
+    }
+
+    let deployment_id = Spi::get_one_with_args::<i64>(
+        "SELECT id FROM pgml.deployments WHERE project_id = $1 ORDER BY created_at DESC LIMIT 1",
+        vec![(PgBuiltInOids::INT8OID.oid(), project_id.into_datum())],
+    )
+    .unwrap_or_else(|| {
+        error!(
+            "No deployed model exists for the project named: `{}`",
+            project_name
+        )
+    });
+    cache_deployment(project_id, deployment_id);
+    deployment_id
+}
+
+/// The models a deployment serves, and the share of the traffic each gets,
+/// cached in shared memory. The first one is the live model.
+fn deployment_arms(deployment_id: i64) -> Vec<(i64, f32)> {
+    if let Some(arms) = DEPLOYMENT_ID_TO_ARMS.share().get(&deployment_id) {
+        return arms.to_vec();
+    }
+
+    let mut arms = Vec::new();
+    Spi::connect(|client| {
+        let result = client.select(
+            "SELECT model_id, traffic FROM pgml.deployment_arms WHERE deployment_id = $1 ORDER BY id",
+            None,
+            Some(vec![(PgBuiltInOids::INT8OID.oid(), deployment_id.into_datum())]),
+        );
+        for row in result {
+            arms.push((
+                row[1].value::<i64>().unwrap(),
+                row[2].value::<f32>().unwrap(),
+            ));
+        }
+        Ok(Some(1))
+    });
+
+    // Deployments of a single model don't split their traffic.
+    if arms.is_empty() {
+        let model_id = Spi::get_one_with_args::<i64>(
+            "SELECT model_id FROM pgml.deployments WHERE id = $1",
+            vec![(PgBuiltInOids::INT8OID.oid(), deployment_id.into_datum())],
+        )
+        .unwrap();
+        arms.push((model_id, 1.));
+    }
+
+    let mut deployments = DEPLOYMENT_ID_TO_ARMS.exclusive();
+    if deployments.len() == 1024 {
+        warning!("Active deployments has exceeded capacity map, clearing caches.");
+        deployments.clear();
+    }
+    deployments
+        .insert(deployment_id, heapless::Vec::from_slice(&arms).unwrap())
+        .unwrap();
+    arms
+}
+
+/// The model that scores the inputs of a deployment without serving them, if any,
+/// cached in shared memory.
+fn shadow_model_id(deployment_id: i64) -> Option<i64> {
+    if let Some(shadow_model_id) = DEPLOYMENT_ID_TO_SHADOW_MODEL_ID.share().get(&deployment_id) {
+        return *shadow_model_id;
+    }
+
+    let shadow_model_id = Spi::get_one_with_args::<i64>(
+        "SELECT shadow_model_id FROM pgml.deployments WHERE id = $1",
+        vec![(PgBuiltInOids::INT8OID.oid(), deployment_id.into_datum())],
+    );
+    let mut deployments = DEPLOYMENT_ID_TO_SHADOW_MODEL_ID.exclusive();
+    if deployments.len() == 1024 {
+        warning!("Active deployments has exceeded capacity map, clearing caches.");
+        deployments.clear();
+    }
+    deployments.insert(deployment_id, shadow_model_id).unwrap();
+    shadow_model_id
+}
+
+/// The id of the model currently deployed for the project.
+fn deployed_model_id(project_name: &str) -> i64 {
+    deployment_arms(deployment_id(project_name))[0].0
+}
+
+/// The id of the model that serves a prediction. Traffic splits route by the `key` if there
+/// is one, so callers consistently get the same model, or at random otherwise. Projects that
+/// log their predictions record the model that served each one.
+fn serving_model_id(project_name: &str, key: Option<&str>) -> i64 {
+    let deployment_id = deployment_id(project_name);
+    let arms = deployment_arms(deployment_id);
+    if arms.len() == 1 {
+        return arms[0].0;
+    }
+
+    let point = match key {
+        // FNV-1a is stable across releases, unlike the std hasher.
+        Some(key) => {
+            let hash = key.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+            (hash as f64 / u64::MAX as f64) as f32
+        }
+        None => rand::random::<f32>(),
+    };
+    let mut model_id = arms[arms.len() - 1].0;
+    let mut cumulative = 0.;
+    for &(arm, traffic) in arms.iter() {
+        cumulative += traffic;
+        if point < cumulative {
+            model_id = arm;
+            break;
+        }
+    }
+    model_id
+}
+
+#[pg_extern]
+fn snapshot(
+    relation_name: &str,
+    y_column_name: Option<default!(&str, "NULL")>,
+    test_size: default!(f32, 0.25),
+    test_sampling: default!(Sampling, "'last'"),
+    preprocess: default!(JsonB, "'{}'"),
+    time_column_name: Option<default!(&str, "NULL")>,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(relation, String),
+        name!(y_column_name, Option<String>),
+    ),
+> {
+    // Clustering snapshots have no label.
+    Snapshot::create(
+        relation_name,
+        y_column_name
+            .map(|y_column_name| vec![y_column_name.to_string()])
+            .unwrap_or_default(),
+        test_size,
+        test_sampling,
+        time_column_name,
+        preprocess,
+    );
+    vec![(
+        relation_name.to_string(),
+        y_column_name.map(|y_column_name| y_column_name.to_string()),
+    )]
+    .into_iter()
+}
+
+#[pg_extern]
+fn load_dataset(
+    source: &str,
+    limit: Option<default!(i64, "NULL")>,
+) -> impl std::iter::Iterator<Item = (name!(table_name, String), name!(rows, i64))> {
+    // cast limit since pgx doesn't support usize
+    let limit: Option<usize> = limit.map(|limit| limit.try_into().unwrap());
+    let (name, rows) = match source {
+        "breast_cancer" => crate::orm::dataset::load_breast_cancer(limit),
+        "diabetes" => crate::orm::dataset::load_diabetes(limit),
+        "digits" => crate::orm::dataset::load_digits(limit),
+        "iris" => crate::orm::dataset::load_iris(limit),
+        "linnerud" => crate::orm::dataset::load_linnerud(limit),
+        _ => error!("Unknown source: `{source}`"),
+    };
+
+    vec![(name, rows)].into_iter()
+}
+
+#[pg_extern]
+fn model_predict(model_id: i64, features: Vec<Option<f32>>) -> f32 {
+    let features = preprocess(model_id, features);
+    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
+    estimator.predict(&features)
+}
+
+#[pg_extern]
+fn model_predict_batch(model_id: i64, features: Vec<Option<f32>>) -> Vec<f32> {
+    let features = preprocess(model_id, features);
+    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
+    estimator.predict_batch(&features)
+}
+
+#[pg_extern]
+fn model_predict_proba(model_id: i64, features: Vec<Option<f32>>) -> Vec<f32> {
+    let features = preprocess(model_id, features);
+    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
+    estimator.predict_proba(&features).unwrap_or_else(|| {
+        error!(
+            "Model {} does not predict probabilities, only classifiers that model them can.",
+            model_id
+        )
+    })
+}
+
+#[pg_extern]
+fn explain(
+    project_name: &str,
+    features: Vec<Option<f32>>,
+) -> impl std::iter::Iterator<Item = (name!(feature, String), name!(contribution, f32))> {
+    let model_id = deployed_model_id(project_name);
+    model_explain(model_id, features)
+}
+
+/// The SHAP value of each feature for a prediction, and the base value they add up from.
+/// Models that can't compute them natively are explained by sampling, with a sample of
+/// the training rows as the background.
+#[pg_extern]
+fn model_explain(
+    model_id: i64,
+    features: Vec<Option<f32>>,
+) -> impl std::iter::Iterator<Item = (name!(feature, String), name!(contribution, f32))> {
+    let snapshot = Snapshot::find_by_model_id(model_id);
+    let mut feature_names = snapshot.feature_names();
+    if features.len() != feature_names.len() {
+        error!(
+            "Expected {} features for a single prediction, got {}",
+            feature_names.len(),
+            features.len()
+        );
+    }
+
+    let features = preprocess(model_id, features);
+    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
+    let contributions = estimator.explain(&features).unwrap_or_else(|| {
+        let background = crate::orm::file::find_deployed_background_by_model_id(model_id);
+        crate::bindings::sample_shap_values(&**estimator, &features, &background)
+    });
+
+    feature_names.push("base_value".to_string());
+    feature_names
+        .into_iter()
+        .zip(contributions)
+        .collect::<Vec<(String, f32)>>()
+        .into_iter()
+}
+
+/// The number of test rows of each class that a classifier predicted as each class.
+#[pg_extern]
+fn confusion_matrix(
+    model_id: i64,
+) -> impl std::iter::Iterator<Item = (name!(actual, i64), name!(predicted, i64), name!(count, i64))>
+{
+    let confusion_matrix = Spi::get_one_with_args::<JsonB>(
+        "SELECT metrics->'confusion_matrix' FROM pgml.models WHERE id = $1",
+        vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+    )
+    .unwrap_or_else(|| {
+        error!(
+            "Model {} has no confusion matrix, only classifiers do.",
+            model_id
+        )
+    });
+    let confusion_matrix: Vec<Vec<i64>> = serde_json::from_value(confusion_matrix.0).unwrap();
+
+    let mut results = Vec::new();
+    for (actual, row) in confusion_matrix.into_iter().enumerate() {
+        for (predicted, count) in row.into_iter().enumerate() {
+            results.push((actual as i64, predicted as i64, count));
+        }
+    }
+    results.into_iter()
+}
+
+/// The residuals (`predicted - actual`) of a regression model, recomputed on the test split
+/// of its snapshot.
+#[pg_extern]
+fn residuals(
+    model_id: i64,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(actual, f32),
+        name!(predicted, f32),
+        name!(residual, f32),
+    ),
+> {
+    let task = Spi::get_one_with_args::<String>(
+        "SELECT projects.task::TEXT FROM pgml.models JOIN pgml.projects ON projects.id = models.project_id WHERE models.id = $1",
+        vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+    )
+    .unwrap_or_else(|| error!("Model {} does not exist", model_id));
+    if task != "regression" {
+        error!(
+            "Residuals are only defined for regression, model {} is a {} model.",
+            model_id, task
+        );
+    }
+
+    let mut dataset = Snapshot::find_by_model_id(model_id).dataset();
+    if let Some(scaler) = crate::orm::file::find_deployed_scaler_by_model_id(model_id) {
+        scaler.transform(&mut dataset.x_test);
+    }
+    let estimator = crate::orm::file::find_deployed_estimator_by_model_id(model_id);
+    let y_hat = estimator.predict_batch(&dataset.x_test);
+
+    y_hat
+        .into_iter()
+        .zip(dataset.y_test)
+        .map(|(predicted, actual)| (actual, predicted, predicted - actual))
+        .collect::<Vec<(f32, f32, f32)>>()
+        .into_iter()
+}
+
+/// How much each feature of the deployed model contributes to its predictions,
+/// as recorded when it was trained.
+#[pg_extern]
+fn feature_importance(
+    project_name: &str,
+) -> impl std::iter::Iterator<Item = (name!(feature, String), name!(importance, f32))> {
+    let model_id = deployed_model_id(project_name);
+    let importance = Spi::get_one_with_args::<JsonB>(
+        "SELECT metrics->'feature_importance' FROM pgml.models WHERE id = $1",
+        vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+    )
+    .unwrap_or_else(|| {
+        error!(
+            "Model {} does not report feature importance, it's only recorded for xgboost, lightgbm, linear models and scikit-learn estimators with `feature_importances_` or `coef_`.",
+            model_id
+        )
+    });
+    let importance: Vec<f32> = serde_json::from_value(importance.0).unwrap();
+
+    Snapshot::find_by_model_id(model_id)
+        .feature_names()
+        .into_iter()
+        .zip(importance)
+        .collect::<Vec<(String, f32)>>()
+        .into_iter()
+}
+
+/// How far the feature columns of a relation drifted from the snapshot the deployed model
+/// was trained on. Features are flagged as drifted when their population stability index
+/// exceeds the `threshold`.
+#[pg_extern]
+fn drift(
+    project_name: &str,
+    relation_name: &str,
+    threshold: default!(f32, 0.2),
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(feature, String),
+        name!(psi, f32),
+        name!(ks, Option<f32>),
+        name!(mean_shift, Option<f32>),
+        name!(drifted, bool),
+        name!(analysis, JsonB),
+    ),
+> {
+    let model_id = deployed_model_id(project_name);
+    Snapshot::find_by_model_id(model_id)
+        .drift(relation_name)
+        .into_iter()
+        .map(|drift| {
+            (
+                drift.feature,
+                drift.psi,
+                drift.ks,
+                drift.mean_shift,
+                drift.psi > threshold,
+                JsonB(drift.analysis),
+            )
+        })
+        .collect::<Vec<(String, f32, Option<f32>, Option<f32>, bool, JsonB)>>()
+        .into_iter()
+}
+
+/// Retrain a project from a fresh snapshot of the relation, on a `schedule`, e.g. `'1 day'`,
+/// and/or once it gained or lost `row_delta` rows since the last snapshot. The background
+/// worker checks the policies every `pgml.retrain_naptime` seconds, and `pgml.retrain`
+/// runs the ones that are due right away. New models are deployed if they score better.
+#[allow(clippy::too_many_arguments)]
+#[pg_extern]
+fn retrain_policy(
+    project_name: &str,
+    relation_name: &str,
+    y_column_name: Option<default!(&str, "NULL")>,
+    algorithm: default!(Algorithm, "'linear'"),
+    hyperparams: default!(JsonB, "'{}'"),
+    search: Option<default!(Search, "NULL")>,
+    search_params: default!(JsonB, "'{}'"),
+    search_args: default!(JsonB, "'{}'"),
+    preprocess: default!(JsonB, "'{}'"),
+    test_size: default!(f32, 0.25),
+    test_sampling: default!(Sampling, "'last'"),
+    schedule: Option<default!(&str, "NULL")>,
+    row_delta: Option<default!(i64, "NULL")>,
+    enabled: default!(bool, true),
+) -> i64 {
+    let project = Project::find_by_name(project_name).unwrap_or_else(|| {
+        error!(
+            "Project named `{}` does not exist, train it once before retraining it.",
+            project_name
+        )
+    });
+    if schedule.is_none() && row_delta.is_none() {
+        error!("Pass a `schedule`, a `row_delta` or both to decide when to retrain.");
+    }
+    if test_sampling == Sampling::time {
+        error!("Retraining policies don't support `time` sampling, they snapshot without a `time_column_name`.");
+    }
+    let y_column_name: Vec<String> = match (project.task, y_column_name) {
+        (Task::clustering, _) => Vec::new(),
+        (_, Some(y_column_name)) => vec![y_column_name.to_string()],
+        (_, None) => error!("You must pass the `y_column_name` of the relation."),
+    };
+
+    Spi::get_one_with_args::<i64>(
+        "INSERT INTO pgml.retrain_policies (project_id, relation_name, y_column_name, algorithm, hyperparams, search, search_params, search_args, preprocess, test_size, test_sampling, schedule, row_delta, enabled, owner)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11::pgml.sampling, $12::INTERVAL, $13, $14, current_user)
+        ON CONFLICT (project_id) DO UPDATE SET
+            relation_name = EXCLUDED.relation_name,
+            y_column_name = EXCLUDED.y_column_name,
+            algorithm = EXCLUDED.algorithm,
+            hyperparams = EXCLUDED.hyperparams,
+            search = EXCLUDED.search,
+            search_params = EXCLUDED.search_params,
+            search_args = EXCLUDED.search_args,
+            preprocess = EXCLUDED.preprocess,
+            test_size = EXCLUDED.test_size,
+            test_sampling = EXCLUDED.test_sampling,
+            schedule = EXCLUDED.schedule,
+            row_delta = EXCLUDED.row_delta,
+            enabled = EXCLUDED.enabled,
+            owner = EXCLUDED.owner
+        RETURNING id",
+        vec![
+            (PgBuiltInOids::INT8OID.oid(), project.id.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), relation_name.into_datum()),
+            (PgBuiltInOids::TEXTARRAYOID.oid(), y_column_name.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), algorithm.to_string().into_datum()),
+            (PgBuiltInOids::JSONBOID.oid(), hyperparams.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), search.map(|search| search.to_string()).into_datum()),
+            (PgBuiltInOids::JSONBOID.oid(), search_params.into_datum()),
+            (PgBuiltInOids::JSONBOID.oid(), search_args.into_datum()),
+            (PgBuiltInOids::JSONBOID.oid(), preprocess.into_datum()),
+            (PgBuiltInOids::FLOAT4OID.oid(), test_size.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), test_sampling.to_string().into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), schedule.into_datum()),
+            (PgBuiltInOids::INT8OID.oid(), row_delta.into_datum()),
+            (PgBuiltInOids::BOOLOID.oid(), enabled.into_datum()),
+        ],
+    )
+    .unwrap()
+}
+
+/// Retrain the projects whose policies are due now, instead of waiting for the background worker.
+#[pg_extern]
+fn retrain() -> impl std::iter::Iterator<
+    Item = (
+        name!(project, String),
+        name!(reason, String),
+        name!(model_id, i64),
+        name!(deployed, bool),
+    ),
+> {
+    RetrainPolicy::find_due()
+        .into_iter()
+        .map(|(policy, reason)| {
+            let run_id = policy.start_run(&reason, false);
+            let (model_id, deployed) = retrain_project(&policy, run_id);
+            (policy.project_name, reason, model_id, deployed)
+        })
+        .collect::<Vec<(String, String, i64, bool)>>()
+        .into_iter()
+}
+
+/// Snapshot the relation of the policy again, train the project on it and deploy the
+/// new model if it's better, then record the run's outcome.
+pub fn retrain_project(policy: &RetrainPolicy, run_id: i64) -> (i64, bool) {
+    info!(
+        "Retraining project `{}` on a new snapshot of `{}`",
+        policy.project_name, policy.relation_name
+    );
+    let (_, model, deployed) = train_model(
+        &policy.project_name,
+        None,
+        Some(&policy.relation_name),
+        Some(policy.y_column_name.clone()),
+        policy.algorithm,
+        JsonB(policy.hyperparams.clone()),
+        policy.search,
+        JsonB(policy.search_params.clone()),
+        JsonB(policy.search_args.clone()),
+        policy.test_size,
+        policy.test_sampling,
+        None,
+        Some(true),
+        JsonB(policy.preprocess.clone()),
+        None,
+        None,
+    );
+
+    RetrainPolicy::finish_run(run_id, model.id, deployed);
+    (model.id, deployed)
+}
+
+#[cfg(feature = "python")]
+#[pg_extern(name = "transform")]
+pub fn transform_json(
+    task: JsonB,
+    args: default!(JsonB, "'{}'"),
+    inputs: default!(Vec<String>, "ARRAY[]::TEXT[]"),
+) -> JsonB {
+    JsonB(crate::bindings::transformers::transform(
+        &task.0, &args.0, &inputs,
+    ))
+}
+
+#[cfg(feature = "python")]
+#[pg_extern(name = "transform")]
+pub fn transform_string(
+    task: String,
+    args: default!(JsonB, "'{}'"),
+    inputs: default!(Vec<String>, "ARRAY[]::TEXT[]"),
+) -> JsonB {
+    let mut task_map = HashMap::new();
+    task_map.insert("task", task);
+    let task_json = json!(task_map);
+    JsonB(crate::bindings::transformers::transform(
+        &task_json, &args.0, &inputs,
+    ))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
     use super::*;
     use crate::orm::algorithm::Algorithm;
-    use crate::orm::dataset::{load_diabetes, load_digits};
+    use crate::orm::dataset::{load_diabetes, load_digits, load_linnerud};
     use crate::orm::runtime::Runtime;
     use crate::orm::sampling::Sampling;
     use crate::orm::Hyperparams;
 
+    /// The arguments of `train`, defaulting to a linear regression of the diabetes dataset.
+    struct Training<'a> {
+        project_name: &'a str,
+        task: Option<Task>,
+        relation_name: Option<&'a str>,
+        y_column_name: Vec<&'a str>,
+        algorithm: Algorithm,
+        hyperparams: JsonB,
+        search: Option<Search>,
+        search_params: JsonB,
+        search_args: JsonB,
+        test_size: f32,
+        test_sampling: Sampling,
+        runtime: Option<Runtime>,
+        automatic_deploy: Option<bool>,
+        preprocess: JsonB,
+        time_column_name: Option<&'a str>,
+        metric: Option<&'a str>,
+    }
+
+    impl Default for Training<'_> {
+        fn default() -> Self {
+            Training {
+                project_name: "Test project",
+                task: Some(Task::regression),
+                relation_name: Some("pgml.diabetes"),
+                y_column_name: vec!["target"],
+                algorithm: Algorithm::linear,
+                hyperparams: JsonB(serde_json::Value::Object(Hyperparams::new())),
+                search: None,
+                search_params: JsonB(serde_json::Value::Object(Hyperparams::new())),
+                search_args: JsonB(serde_json::Value::Object(Hyperparams::new())),
+                test_size: 0.25,
+                test_sampling: Sampling::last,
+                runtime: Some(Runtime::rust),
+                automatic_deploy: Some(true),
+                preprocess: JsonB(serde_json::Value::Object(Hyperparams::new())),
+                time_column_name: None,
+                metric: None,
+            }
+        }
+    }
+
+    impl Training<'_> {
+        /// Train the project, with every label in `y_column_name`, or none when it's empty.
+        fn run(self) -> Vec<(String, String, String, bool)> {
+            let y_column_name = match self.y_column_name.is_empty() {
+                true => None,
+                false => Some(self.y_column_name.iter().map(|y| y.to_string()).collect()),
+            };
+            train_joint(
+                self.project_name,
+                self.task,
+                self.relation_name,
+                y_column_name,
+                self.algorithm,
+                self.hyperparams,
+                self.search,
+                self.search_params,
+                self.search_args,
+                self.test_size,
+                self.test_sampling,
+                self.runtime,
+                self.automatic_deploy,
+                self.preprocess,
+                self.time_column_name,
+                self.metric,
+            )
+            .collect()
+        }
+    }
+
     #[pg_test]
     fn test_project_lifecycle() {
         assert!(Project::create("test", Task::regression).id > 0);
@@ -495,7 +1660,14 @@ mod tests {
     #[pg_test]
     fn test_snapshot_lifecycle() {
         load_diabetes(Some(25));
-        let snapshot = Snapshot::create("pgml.diabetes", "target", 0.5, Sampling::last);
+        let snapshot = Snapshot::create(
+            "pgml.diabetes",
+            vec!["target".to_string()],
+            0.5,
+            Sampling::last,
+            None,
+            JsonB(serde_json::Value::Object(Hyperparams::new())),
+        );
         assert!(snapshot.id > 0);
     }
 
@@ -511,22 +1683,12 @@ mod tests {
         info!("Data directory: {}", setting.unwrap());
 
         for runtime in [Runtime::python, Runtime::rust] {
-            let result: Vec<(String, String, String, bool)> = train(
-                "Test project",
-                Some(Task::regression),
-                Some("pgml.diabetes"),
-                Some("target"),
-                Algorithm::linear,
-                JsonB(serde_json::Value::Object(Hyperparams::new())),
-                None,
-                JsonB(serde_json::Value::Object(Hyperparams::new())),
-                JsonB(serde_json::Value::Object(Hyperparams::new())),
-                0.25,
-                Sampling::last,
-                Some(runtime),
-                Some(true),
-            )
-            .collect();
+            let result = Training {
+                project_name: "Test project",
+                runtime: Some(runtime),
+                ..Default::default()
+            }
+            .run();
 
             assert_eq!(result.len(), 1);
             assert_eq!(result[0].0, String::from("Test project"));
@@ -536,6 +1698,614 @@ mod tests {
         }
     }
 
+    #[pg_test]
+    fn test_residuals() {
+        load_diabetes(None);
+
+        let result = Training {
+            project_name: "Test project residuals",
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(result.len(), 1);
+
+        let model_id = Spi::get_one::<i64>("SELECT max(id) FROM pgml.models").unwrap();
+        let residuals: Vec<(f32, f32, f32)> = residuals(model_id).collect();
+        assert_eq!(residuals.len(), 111);
+
+        // They're the same residuals that were summarized when the model was trained.
+        let mean = residuals
+            .iter()
+            .map(|(_, _, residual)| residual)
+            .sum::<f32>()
+            / residuals.len() as f32;
+        let summary = Spi::get_one_with_args::<f64>(
+            "SELECT (metrics->'residuals'->>'mean')::FLOAT8 FROM pgml.models WHERE id = $1",
+            vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+        )
+        .unwrap();
+        assert!((mean as f64 - summary).abs() < 1e-2);
+    }
+
+    #[pg_test]
+    fn test_train_joint_regression() {
+        load_linnerud(None);
+
+        for runtime in [Runtime::python, Runtime::rust] {
+            let result = Training {
+                project_name: "Test project joint",
+                relation_name: Some("pgml.linnerud"),
+                y_column_name: vec!["weight", "waist", "pulse"],
+                runtime: Some(runtime),
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+            assert_eq!(result[0].0, String::from("Test project joint"));
+            assert_eq!(result[0].1, String::from("regression"));
+            assert_eq!(result[0].2, String::from("linear"));
+        }
+    }
+
+    #[pg_test]
+    fn test_train_categorical() {
+        load_diabetes(None);
+        Spi::run(
+            "CREATE TABLE pgml.diabetes_categorical AS
+            SELECT age, bmi, CASE WHEN sex > 0 THEN 'male' ELSE 'female' END AS sex, target
+            FROM pgml.diabetes",
+        );
+
+        for encode in ["ordinal", "one_hot", "target"] {
+            let result = Training {
+                project_name: &format!("Test project categorical {}", encode),
+                relation_name: Some("pgml.diabetes_categorical"),
+                algorithm: Algorithm::xgboost,
+                preprocess: JsonB(serde_json::json!({"sex": {"encode": encode}})),
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+            assert_eq!(result[0].1, String::from("regression"));
+        }
+    }
+
+    #[pg_test]
+    fn test_train_imputation() {
+        load_diabetes(None);
+        Spi::run(
+            "CREATE TABLE pgml.diabetes_nulls AS
+            SELECT CASE WHEN age > 0 THEN age END AS age, bmi, target
+            FROM pgml.diabetes",
+        );
+
+        for impute in [
+            serde_json::json!({"impute": "drop"}),
+            serde_json::json!({"impute": "mean"}),
+            serde_json::json!({"impute": "median"}),
+            serde_json::json!({"impute": "mode"}),
+            serde_json::json!({"impute": "constant", "fill_value": 0}),
+        ] {
+            let result = Training {
+                project_name: "Test project imputation",
+                relation_name: Some("pgml.diabetes_nulls"),
+                preprocess: JsonB(serde_json::json!({ "age": impute })),
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+        }
+    }
+
+    #[pg_test]
+    fn test_train_scaling() {
+        load_diabetes(None);
+
+        for scale in ["standard", "min_max", "robust", "quantile", "log1p"] {
+            let result = Training {
+                project_name: "Test project scaling",
+                algorithm: Algorithm::svm,
+                preprocess: JsonB(serde_json::json!({
+                    "bmi": {"scale": scale},
+                    "bp": {"scale": scale},
+                })),
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+        }
+    }
+
+    #[pg_test]
+    fn test_train_clustering() {
+        load_diabetes(None);
+
+        for runtime in [Runtime::python, Runtime::rust] {
+            let result = Training {
+                project_name: "Test project clustering",
+                task: Some(Task::clustering),
+                y_column_name: Vec::new(),
+                algorithm: Algorithm::kmeans,
+                hyperparams: JsonB(serde_json::json!({"n_clusters": 3})),
+                runtime: Some(runtime),
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+            assert_eq!(result[0].1, String::from("clustering"));
+            assert_eq!(result[0].2, String::from("kmeans"));
+        }
+    }
+
+    #[pg_test]
+    fn test_train_sampling() {
+        load_digits(None);
+        load_diabetes(None);
+        Spi::run(
+            "CREATE TABLE pgml.diabetes_time AS
+            SELECT *, now() - (row_number() OVER ())::INT * INTERVAL '1 day' AS created_at
+            FROM pgml.diabetes",
+        );
+
+        for (relation_name, task, sampling, time_column_name) in [
+            (
+                "pgml.digits",
+                Task::classification,
+                Sampling::stratified,
+                None,
+            ),
+            (
+                "pgml.diabetes_time",
+                Task::regression,
+                Sampling::time,
+                Some("created_at"),
+            ),
+        ] {
+            let result = Training {
+                project_name: &format!("Test project {}", sampling.to_string()),
+                task: Some(task),
+                relation_name: Some(relation_name),
+                algorithm: Algorithm::xgboost,
+                search: Some(Search::grid),
+                search_params: JsonB(serde_json::json!({"max_depth": [2, 4]})),
+                search_args: JsonB(serde_json::json!({"cv": 3})),
+                test_sampling: sampling,
+                time_column_name,
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+        }
+    }
+
+    #[pg_test]
+    fn test_train_random_search() {
+        load_diabetes(None);
+
+        let result = Training {
+            project_name: "Test project random search",
+            algorithm: Algorithm::xgboost,
+            search: Some(Search::random),
+            search_params: JsonB(serde_json::json!({
+                "eta": {"loguniform": [0.01, 0.3]},
+                "max_depth": {"randint": [2, 6]},
+                "subsample": {"uniform": [0.5, 1.0]},
+                "n_estimators": [10, 20],
+            })),
+            search_args: JsonB(serde_json::json!({"n_iter": 3, "cv": 2, "n_jobs": 2})),
+            ..Default::default()
+        }
+        .run();
+
+        assert_eq!(result.len(), 1);
+
+        let trials = Spi::get_one::<i64>(
+            "SELECT jsonb_array_length(metrics->'search_results'->'params')::BIGINT
+            FROM pgml.models ORDER BY id DESC LIMIT 1",
+        );
+        assert_eq!(trials, Some(3));
+    }
+
+    #[pg_test]
+    fn test_train_bayesian_and_halving_search() {
+        load_diabetes(None);
+
+        for search in [Search::bayesian, Search::halving] {
+            let result = Training {
+                project_name: &format!("Test project {} search", search.to_string()),
+                algorithm: Algorithm::xgboost,
+                search: Some(search),
+                search_params: JsonB(serde_json::json!({
+                    "eta": {"loguniform": [0.01, 0.3]},
+                    "max_depth": {"randint": [2, 6]},
+                    "n_estimators": [10, 20],
+                })),
+                search_args: JsonB(
+                    serde_json::json!({"n_iter": 6, "n_initial_points": 3, "cv": 2}),
+                ),
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+        }
+    }
+
+    #[pg_test]
+    fn test_train_early_stopping() {
+        load_diabetes(None);
+
+        for algorithm in [Algorithm::xgboost, Algorithm::lightgbm] {
+            let result = Training {
+                project_name: "Test project early stopping",
+                algorithm,
+                hyperparams: JsonB(serde_json::json!({
+                    "n_estimators": 500,
+                    "early_stopping_rounds": 5,
+                    "eval_metric": "rmse",
+                })),
+                ..Default::default()
+            }
+            .run();
+
+            assert_eq!(result.len(), 1);
+
+            let best_iteration = Spi::get_one::<f64>(
+                "SELECT (metrics->>'best_iteration')::FLOAT8 FROM pgml.models ORDER BY id DESC LIMIT 1",
+            );
+            assert!(best_iteration.unwrap() < 500.);
+
+            let rounds = Spi::get_one::<i64>(
+                "SELECT jsonb_array_length(metrics->'history'->'train-rmse')::BIGINT FROM pgml.models ORDER BY id DESC LIMIT 1",
+            );
+            assert!(rounds.unwrap() as f64 > best_iteration.unwrap());
+        }
+    }
+
+    #[pg_test]
+    fn test_train_metric() {
+        load_diabetes(None);
+
+        let result = Training {
+            project_name: "Test project metric",
+            algorithm: Algorithm::xgboost,
+            search: Some(Search::grid),
+            search_params: JsonB(serde_json::json!({"max_depth": [1, 4]})),
+            search_args: JsonB(serde_json::json!({"cv": 2})),
+            metric: Some("mean_absolute_error"),
+            ..Default::default()
+        }
+        .run();
+
+        assert_eq!(result.len(), 1);
+
+        let project = Project::find_by_name("Test project metric").unwrap();
+        assert_eq!(project.target_metric(), "mean_absolute_error");
+        assert!(!project.greater_is_better());
+
+        let result: Vec<(String, String, String)> =
+            deploy("Test project metric", Strategy::best_score, None, None).collect();
+        assert_eq!(result.len(), 1);
+    }
+
+    #[pg_test]
+    fn test_explain() {
+        load_diabetes(None);
+
+        // xgboost explains itself, linear models are explained by sampling.
+        for (project_name, algorithm) in [
+            ("Test project explain xgboost", Algorithm::xgboost),
+            ("Test project explain linear", Algorithm::linear),
+        ] {
+            let result = Training {
+                project_name,
+                algorithm,
+                ..Default::default()
+            }
+            .run();
+            assert_eq!(result.len(), 1);
+
+            let features = vec![
+                Some(0.038),
+                Some(0.051),
+                Some(0.062),
+                Some(0.022),
+                Some(-0.044),
+                Some(-0.035),
+                Some(-0.043),
+                Some(-0.003),
+                Some(0.020),
+                Some(-0.018),
+            ];
+            let explanation: Vec<(String, f32)> = explain(project_name, features.clone()).collect();
+            assert_eq!(explanation.len(), 11);
+            assert_eq!(explanation[10].0, "base_value");
+
+            // The contributions add up to the prediction.
+            let prediction = predict(project_name, features, None);
+            let total: f32 = explanation
+                .iter()
+                .map(|(_, contribution)| contribution)
+                .sum();
+            assert!((total - prediction).abs() < 1e-2 * prediction.abs().max(1.));
+        }
+    }
+
+    #[pg_test]
+    fn test_predict_batch() {
+        load_diabetes(None);
+
+        let result = Training {
+            project_name: "Test project batch",
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(result.len(), 1);
+
+        let features = vec![Some(0.038); 10];
+        let prediction = predict("Test project batch", features.clone(), None);
+        let predictions = predict_batch(
+            "Test project batch",
+            features.iter().chain(features.iter()).cloned().collect(),
+        );
+        assert_eq!(predictions, vec![prediction, prediction]);
+
+        // Groups are predicted in the order their rows are aggregated.
+        let groups = Spi::get_one::<i64>(
+            "SELECT count(*) FROM (
+                SELECT pgml.predict_agg('Test project batch', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6] ORDER BY n) AS predictions,
+                    array_agg(pgml.predict('Test project batch', ARRAY[age, sex, bmi, bp, s1, s2, s3, s4, s5, s6]) ORDER BY n) AS expected
+                FROM (SELECT *, row_number() OVER () AS n FROM pgml.diabetes LIMIT 250) diabetes
+                GROUP BY n / 100
+            ) groups WHERE predictions = expected",
+        );
+        assert_eq!(groups, Some(3));
+
+        // Batches log one prediction per row.
+        log_predictions("Test project batch", true);
+        predict_batch(
+            "Test project batch",
+            features.iter().chain(features.iter()).cloned().collect(),
+        );
+        let logged = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pgml.predictions JOIN pgml.projects ON projects.id = predictions.project_id WHERE projects.name = 'Test project batch'",
+        );
+        assert_eq!(logged, Some(2));
+        assert!(predict_batch("Test project batch", Vec::new()).is_empty());
+    }
+
+    #[pg_test]
+    fn test_deploy_split() {
+        load_diabetes(None);
+
+        for algorithm in [Algorithm::linear, Algorithm::xgboost] {
+            let result = Training {
+                project_name: "Test project split",
+                algorithm,
+                automatic_deploy: Some(false),
+                ..Default::default()
+            }
+            .run();
+            assert_eq!(result.len(), 1);
+        }
+
+        let model_ids = Spi::get_one::<Vec<i64>>(
+            "SELECT array_agg(id ORDER BY id) FROM (SELECT id FROM pgml.models ORDER BY id DESC LIMIT 2) models",
+        )
+        .unwrap();
+        let arms: Vec<(String, i64, f32)> =
+            deploy_split("Test project split", model_ids.clone(), vec![9., 1.]).collect();
+        assert_eq!(arms.len(), 2);
+        assert!((arms[0].2 - 0.9).abs() < 1e-6);
+
+        // The same key is always served by the same model.
+        assert!(log_predictions("Test project split", true));
+        let features = vec![Some(0.038); 10];
+        let first = predict("Test project split", features.clone(), Some("customer 1"));
+        for _ in 0..10 {
+            let prediction = predict("Test project split", features.clone(), Some("customer 1"));
+            assert_eq!(prediction, first);
+        }
+
+        let served = Spi::get_one::<i64>(
+            "SELECT count(DISTINCT model_id) FROM pgml.predictions WHERE key = 'customer 1'",
+        );
+        assert_eq!(served, Some(1));
+
+        // A canary takes traffic from the live model.
+        let result: Vec<(String, String, String)> = deploy(
+            "Test project split",
+            Strategy::most_recent,
+            Some(Algorithm::linear),
+            None,
+        )
+        .collect();
+        assert_eq!(result.len(), 1);
+        let result: Vec<(String, String, String)> =
+            deploy("Test project split", Strategy::canary, None, Some(0.2)).collect();
+        assert_eq!(result[0].2, "xgboost");
+
+        // A shadow scores the same inputs, but the live model's predictions are served.
+        let result: Vec<(String, String, String)> = deploy(
+            "Test project split",
+            Strategy::most_recent,
+            Some(Algorithm::linear),
+            None,
+        )
+        .collect();
+        assert_eq!(result.len(), 1);
+        let result: Vec<(String, String, String)> =
+            deploy("Test project split", Strategy::shadow, None, None).collect();
+        assert_eq!(result[0].2, "xgboost");
+        let prediction = predict("Test project split", features.clone(), Some("customer 2"));
+        let (logged, shadow_prediction) = Spi::get_two::<f32, f32>(
+            "SELECT prediction, shadow_prediction FROM pgml.shadow_predictions WHERE key = 'customer 2'",
+        );
+        assert_eq!(logged, Some(prediction));
+        assert_eq!(
+            shadow_prediction,
+            Some(model_predict(model_ids[1], features.clone()))
+        );
+    }
+
+    #[pg_test]
+    fn test_record_outcome() {
+        load_diabetes(None);
+
+        let result = Training {
+            project_name: "Test project outcomes",
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(result.len(), 1);
+
+        // Predictions are only logged once the project opts in.
+        predict("Test project outcomes", vec![Some(0.038); 10], Some("a"));
+        assert!(log_predictions("Test project outcomes", true));
+        for (key, features) in [("a", 0.038), ("b", -0.044), ("c", 0.062)] {
+            predict("Test project outcomes", vec![Some(features); 10], Some(key));
+        }
+        let logged = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pgml.predictions JOIN pgml.projects ON projects.id = predictions.project_id WHERE projects.name = 'Test project outcomes'",
+        );
+        assert_eq!(logged, Some(3));
+
+        assert_eq!(record_outcome("Test project outcomes", "a", 151.), 1);
+        assert_eq!(record_outcome("Test project outcomes", "b", 75.), 1);
+        let (outcomes, r2) = Spi::get_two::<i64, f64>(
+            "SELECT outcomes, (metrics->>'r2')::FLOAT FROM pgml.live_metrics('Test project outcomes', (now() - INTERVAL '1 hour')::TIMESTAMP, INTERVAL '1 hour')",
+        );
+        assert_eq!(outcomes, Some(2));
+        assert!(r2.is_some());
+
+        // Nothing to score without outcomes.
+        assert!(outcome_metrics(Task::regression, Vec::new(), Vec::new()).is_none());
+        assert!(outcome_metrics(Task::classification, Vec::new(), Vec::new()).is_none());
+    }
+
+    #[pg_test]
+    fn test_drift() {
+        load_diabetes(None);
+
+        let result = Training {
+            project_name: "Test project drift",
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(result.len(), 1);
+
+        // The training data hasn't drifted.
+        let features: Vec<(String, f32, Option<f32>, Option<f32>, bool, JsonB)> =
+            drift("Test project drift", "pgml.diabetes", 0.2).collect();
+        assert_eq!(features.len(), 10);
+        assert!(features.iter().all(|(_, _, _, _, drifted, _)| !drifted));
+
+        // Shifting a feature by a standard deviation has.
+        Spi::run("CREATE TABLE pgml.diabetes_drift AS SELECT * FROM pgml.diabetes");
+        Spi::run(
+            "UPDATE pgml.diabetes_drift SET bmi = bmi + (SELECT stddev(bmi) FROM pgml.diabetes)",
+        );
+        let features: Vec<(String, f32, Option<f32>, Option<f32>, bool, JsonB)> =
+            drift("Test project drift", "pgml.diabetes_drift", 0.2).collect();
+        let bmi = features.iter().find(|feature| feature.0 == "bmi").unwrap();
+        assert!(bmi.4);
+        assert!((bmi.3.unwrap() - 1.).abs() < 1e-3);
+        assert!(features
+            .iter()
+            .filter(|feature| feature.0 != "bmi")
+            .all(|(_, _, _, _, drifted, _)| !drifted));
+    }
+
+    #[pg_test]
+    fn test_retrain() {
+        load_diabetes(None);
+        Spi::run("CREATE TABLE pgml.diabetes_retrain AS SELECT * FROM pgml.diabetes");
+
+        let result = Training {
+            project_name: "Test project retrain",
+            relation_name: Some("pgml.diabetes_retrain"),
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(result.len(), 1);
+
+        retrain_policy(
+            "Test project retrain",
+            "pgml.diabetes_retrain",
+            Some("target"),
+            Algorithm::linear,
+            JsonB(serde_json::Value::Object(Hyperparams::new())),
+            None,
+            JsonB(serde_json::Value::Object(Hyperparams::new())),
+            JsonB(serde_json::Value::Object(Hyperparams::new())),
+            JsonB(serde_json::Value::Object(Hyperparams::new())),
+            0.25,
+            Sampling::last,
+            None,
+            Some(10),
+            true,
+        );
+
+        // Nothing changed since the last snapshot.
+        let runs: Vec<(String, String, i64, bool)> = retrain().collect();
+        assert!(runs.is_empty());
+
+        Spi::run("INSERT INTO pgml.diabetes_retrain SELECT * FROM pgml.diabetes LIMIT 10");
+        let runs: Vec<(String, String, i64, bool)> = retrain().collect();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].1, "row_delta");
+        let (status, model_id) = Spi::get_two::<String, i64>(
+            "SELECT status, model_id FROM pgml.retrain_runs ORDER BY id DESC LIMIT 1",
+        );
+        assert_eq!(status, Some("successful".to_string()));
+        assert_eq!(model_id, Some(runs[0].2));
+
+        // The new snapshot has the new rows.
+        let runs: Vec<(String, String, i64, bool)> = retrain().collect();
+        assert!(runs.is_empty());
+
+        // A failed run backs off for a day, when there's no schedule.
+        Spi::run("UPDATE pgml.retrain_runs SET status = 'failed'");
+        Spi::run("INSERT INTO pgml.diabetes_retrain SELECT * FROM pgml.diabetes LIMIT 10");
+        let runs: Vec<(String, String, i64, bool)> = retrain().collect();
+        assert!(runs.is_empty());
+
+        // Only the runs the worker left in progress are failed when it restarts.
+        Spi::run("UPDATE pgml.retrain_runs SET status = 'in_progress', worker = (id = (SELECT min(id) FROM pgml.retrain_runs))");
+        RetrainPolicy::fail_interrupted_runs();
+        let (failed, in_progress) = Spi::get_two::<i64, i64>(
+            "SELECT count(*) FILTER (WHERE status = 'failed'), count(*) FILTER (WHERE status = 'in_progress') FROM pgml.retrain_runs",
+        );
+        assert_eq!(failed, Some(1));
+        assert!(in_progress.unwrap() >= 1);
+    }
+
+    #[pg_test]
+    fn test_feature_importance() {
+        load_diabetes(None);
+
+        let result = Training {
+            project_name: "Test project feature importance",
+            algorithm: Algorithm::xgboost,
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(result.len(), 1);
+
+        let importance: Vec<(String, f32)> =
+            feature_importance("Test project feature importance").collect();
+        assert_eq!(importance.len(), 10);
+        assert!(importance
+            .iter()
+            .any(|(feature, importance)| feature == "bmi" && *importance > 0.));
+    }
+
     #[pg_test]
     fn test_train_classification() {
         load_digits(None);
@@ -548,22 +2318,15 @@ mod tests {
         info!("Data directory: {}", setting.unwrap());
 
         for runtime in [Runtime::python, Runtime::rust] {
-            let result: Vec<(String, String, String, bool)> = train(
-                "Test project 2",
-                Some(Task::classification),
-                Some("pgml.digits"),
-                Some("target"),
-                Algorithm::xgboost,
-                JsonB(serde_json::Value::Object(Hyperparams::new())),
-                None,
-                JsonB(serde_json::Value::Object(Hyperparams::new())),
-                JsonB(serde_json::Value::Object(Hyperparams::new())),
-                0.25,
-                Sampling::last,
-                Some(runtime),
-                Some(true),
-            )
-            .collect();
+            let result = Training {
+                project_name: "Test project 2",
+                task: Some(Task::classification),
+                relation_name: Some("pgml.digits"),
+                algorithm: Algorithm::xgboost,
+                runtime: Some(runtime),
+                ..Default::default()
+            }
+            .run();
 
             assert_eq!(result.len(), 1);
             assert_eq!(result[0].0, String::from("Test project 2"));
@@ -572,4 +2335,44 @@ mod tests {
             // assert_eq!(result[0].3, true);
         }
     }
+
+    #[pg_test]
+    fn test_confusion_matrix() {
+        load_digits(None);
+
+        let result = Training {
+            project_name: "Test project confusion matrix",
+            task: Some(Task::classification),
+            relation_name: Some("pgml.digits"),
+            algorithm: Algorithm::xgboost,
+            hyperparams: JsonB(serde_json::json!({"n_estimators": 10})),
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(result.len(), 1);
+
+        let model_id = Spi::get_one::<i64>("SELECT max(id) FROM pgml.models").unwrap();
+        let confusion_matrix: Vec<(i64, i64, i64)> = confusion_matrix(model_id).collect();
+        assert_eq!(confusion_matrix.len(), 100);
+
+        // The diagonal are the correct predictions.
+        let total: i64 = confusion_matrix.iter().map(|(_, _, count)| count).sum();
+        let correct: i64 = confusion_matrix
+            .iter()
+            .filter(|(actual, predicted, _)| actual == predicted)
+            .map(|(_, _, count)| count)
+            .sum();
+        let accuracy = Spi::get_one_with_args::<f64>(
+            "SELECT (metrics->>'accuracy')::FLOAT8 FROM pgml.models WHERE id = $1",
+            vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+        )
+        .unwrap();
+        assert!((correct as f64 / total as f64 - accuracy).abs() < 1e-4);
+
+        let num_classes = Spi::get_one_with_args::<i64>(
+            "SELECT count(*) FROM pgml.models, jsonb_object_keys(metrics->'classes') WHERE id = $1",
+            vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+        );
+        assert_eq!(num_classes, Some(10));
+    }
 }
@@ -0,0 +1,88 @@
+/// Joint (multi-target) models.
+///
+/// Most Rust estimators only learn a single target. Joint models
+/// fit one estimator per target and interleave their predictions
+/// back into rows, so each row predicts `num_labels` values.
+use std::marker::PhantomData;
+
+use crate::bindings::{Bindings, Fit};
+use crate::orm::*;
+
+pub struct Estimator<T> {
+    estimators: Vec<Box<dyn Bindings>>,
+    // The type of the estimators is needed to deserialize them.
+    estimator_type: PhantomData<fn() -> T>,
+}
+
+/// Fit one estimator per label in the dataset.
+pub fn fit<T: Bindings + 'static>(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+    fit: Fit,
+) -> anyhow::Result<Box<dyn Bindings>> {
+    let estimators = (0..dataset.num_labels)
+        .map(|i| fit(&dataset.target(i), hyperparams))
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(Box::new(Estimator::<T> {
+        estimators,
+        estimator_type: PhantomData,
+    }))
+}
+
+impl<T: Bindings + 'static> Bindings for Estimator<T> {
+    /// Predict the first target of a novel datapoint.
+    fn predict(&self, features: &[f32]) -> f32 {
+        self.predict_batch(features)[0]
+    }
+
+    /// Predict all targets for a set of datapoints, row by row.
+    fn predict_batch(&self, features: &[f32]) -> Vec<f32> {
+        let predictions: Vec<Vec<f32>> = self
+            .estimators
+            .iter()
+            .map(|estimator| estimator.predict_batch(features))
+            .collect();
+
+        let num_rows = predictions[0].len();
+        let mut results = Vec::with_capacity(num_rows * predictions.len());
+        for i in 0..num_rows {
+            for target in &predictions {
+                results.push(target[i]);
+            }
+        }
+        results
+    }
+
+    /// Serialize self to bytes
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::from((self.estimators.len() as u64).to_be_bytes());
+        for estimator in &self.estimators {
+            let mut estimator = estimator.to_bytes();
+            bytes.append(&mut (estimator.len() as u64).to_be_bytes().to_vec());
+            bytes.append(&mut estimator);
+        }
+        bytes
+    }
+
+    /// Deserialize self from bytes, with additional context
+    fn from_bytes(bytes: &[u8]) -> Box<dyn Bindings>
+    where
+        Self: Sized,
+    {
+        let num_estimators = u64::from_be_bytes(bytes[..8].try_into().unwrap()) as usize;
+        let mut estimators = Vec::with_capacity(num_estimators);
+        let mut start = 8;
+        for _ in 0..num_estimators {
+            let len = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap()) as usize;
+            start += 8;
+            estimators.push(T::from_bytes(&bytes[start..start + len]));
+            start += len;
+        }
+
+        Box::new(Estimator::<T> {
+            estimators,
+            estimator_type: PhantomData,
+        })
+    }
+}
@@ -1,14 +1,22 @@
-use crate::bindings::Bindings;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+
+use anyhow::Context;
+
+use crate::bindings::{Bindings, EarlyStopping, History};
 use crate::orm::dataset::Dataset;
 use crate::orm::task::Task;
 use crate::orm::Hyperparams;
-use lightgbm;
-use serde_json::json;
 
+/// The lightgbm crate doesn't expose its booster's handle, so the model is kept in
+/// the C API directly, to predict and explain with it without reloading it.
 pub struct Estimator {
-    estimator: lightgbm::Booster,
+    estimator: BoosterHandle,
     num_features: usize,
     num_classes: usize,
+    best_iteration: Option<usize>,
+    history: Option<History>,
+    feature_importance: Option<Vec<f32>>,
 }
 
 unsafe impl Send for Estimator {}
@@ -23,15 +31,25 @@ impl std::fmt::Debug for Estimator {
     }
 }
 
-pub fn fit_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn fit_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, Task::regression)
 }
 
-pub fn fit_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn fit_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, Task::classification)
 }
 
-fn fit(dataset: &Dataset, hyperparams: &Hyperparams, task: Task) -> Box<dyn Bindings> {
+fn fit(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+    task: Task,
+) -> anyhow::Result<Box<dyn Bindings>> {
     let mut hyperparams = hyperparams.clone();
     match task {
         Task::regression => {
@@ -54,26 +72,350 @@ fn fit(dataset: &Dataset, hyperparams: &Hyperparams, task: Task) -> Box<dyn Bind
                 hyperparams.insert("objective".to_string(), serde_json::Value::from("binary"));
             }
         }
+        Task::clustering => anyhow::bail!("LightGBM does not support clustering"),
+    };
+
+    let num_classes = if task == Task::regression {
+        1
+    } else {
+        dataset.num_distinct_labels
     };
 
-    let data = lightgbm::Dataset::from_vec(
-        &dataset.x_train,
-        &dataset.y_train,
-        dataset.num_features as i32,
-    )
-    .unwrap();
+    // LightGBM calls it metric.
+    if let Some(eval_metric) = hyperparams.remove("eval_metric") {
+        hyperparams.insert("metric".to_string(), eval_metric);
+    }
+    let early_stopping_rounds = hyperparams
+        .remove("early_stopping_rounds")
+        .map(|value| {
+            value
+                .as_u64()
+                .context("early_stopping_rounds must be an integer")
+        })
+        .transpose()?
+        .map(|rounds| rounds as usize);
 
-    let estimator = lightgbm::Booster::train(data, &json! {hyperparams}).unwrap();
+    let (estimator, best_iteration, history, feature_importance) =
+        train(dataset, &hyperparams, early_stopping_rounds)?;
 
-    Box::new(Estimator {
+    Ok(Box::new(Estimator {
         estimator,
         num_features: dataset.num_features,
-        num_classes: if task == Task::regression {
-            1
-        } else {
-            dataset.num_distinct_labels
-        },
-    })
+        num_classes,
+        best_iteration,
+        history: Some(history),
+        feature_importance: Some(feature_importance),
+    }))
+}
+
+/// LightGBM's last error, if a call into the C API failed.
+fn check(result: i32) -> anyhow::Result<()> {
+    if result != 0 {
+        let error = unsafe { CStr::from_ptr(lightgbm_sys::LGBM_GetLastError()) };
+        anyhow::bail!("LightGBM: {}", error.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// A dataset created with the C API, freed when it's dropped.
+struct DatasetHandle(lightgbm_sys::DatasetHandle);
+
+impl Drop for DatasetHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { lightgbm_sys::LGBM_DatasetFree(self.0) };
+        }
+    }
+}
+
+/// A booster created with the C API, freed when it's dropped.
+struct BoosterHandle(lightgbm_sys::BoosterHandle);
+
+impl Drop for BoosterHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { lightgbm_sys::LGBM_BoosterFree(self.0) };
+        }
+    }
+}
+
+impl BoosterHandle {
+    /// Load a booster from the text of a saved model.
+    fn load_string(model: &[u8]) -> anyhow::Result<BoosterHandle> {
+        let model = CString::new(model)?;
+        let mut handle = BoosterHandle(std::ptr::null_mut());
+        let mut num_iterations = 0;
+        unsafe {
+            check(lightgbm_sys::LGBM_BoosterLoadModelFromString(
+                model.as_ptr(),
+                &mut num_iterations,
+                &mut handle.0,
+            ))?;
+        }
+        Ok(handle)
+    }
+
+    /// The text of the model, with its first `num_iteration` rounds, or all of them with -1.
+    fn save_string(&self, num_iteration: i32) -> anyhow::Result<Vec<u8>> {
+        let mut length = 0;
+        let mut model: Vec<u8> = Vec::new();
+        // The first call only measures the model, the second fills the buffer.
+        for _ in 0..2 {
+            model.resize(length as usize, 0);
+            unsafe {
+                check(lightgbm_sys::LGBM_BoosterSaveModelToString(
+                    self.0,
+                    0,
+                    num_iteration,
+                    lightgbm_sys::C_API_FEATURE_IMPORTANCE_SPLIT as i32,
+                    model.len() as i64,
+                    &mut length,
+                    model.as_mut_ptr() as *mut c_char,
+                ))?;
+            }
+        }
+        // Without the trailing NULL.
+        model.truncate(length as usize - 1);
+        Ok(model)
+    }
+
+    /// Predict rows of features, with one of the `C_API_PREDICT_*` types.
+    fn predict(
+        &self,
+        features: &[f32],
+        num_features: usize,
+        predict_type: u32,
+    ) -> anyhow::Result<Vec<f64>> {
+        let num_rows = features.len() / num_features;
+        let parameters = CString::new("")?;
+        let mut length = 0;
+        unsafe {
+            check(lightgbm_sys::LGBM_BoosterCalcNumPredict(
+                self.0,
+                num_rows as i32,
+                predict_type as i32,
+                0,
+                -1, // all iterations
+                &mut length,
+            ))?;
+        }
+        let mut results = vec![0_f64; length as usize];
+        unsafe {
+            check(lightgbm_sys::LGBM_BoosterPredictForMat(
+                self.0,
+                features.as_ptr() as *const c_void,
+                lightgbm_sys::C_API_DTYPE_FLOAT32 as i32,
+                num_rows as i32,
+                num_features as i32,
+                1, // row major
+                predict_type as i32,
+                0,
+                -1, // all iterations
+                parameters.as_ptr(),
+                &mut length,
+                results.as_mut_ptr(),
+            ))?;
+        }
+        Ok(results)
+    }
+}
+
+/// Create a LightGBM dataset from rows of features, binned like the reference dataset if there is one.
+fn create_dataset(
+    x: &[f32],
+    y: &[f32],
+    num_features: usize,
+    parameters: &CStr,
+    reference: lightgbm_sys::DatasetHandle,
+) -> anyhow::Result<DatasetHandle> {
+    let num_rows = y.len();
+    let mut handle = DatasetHandle(std::ptr::null_mut());
+    let label = CString::new("label")?;
+    unsafe {
+        check(lightgbm_sys::LGBM_DatasetCreateFromMat(
+            x.as_ptr() as *const c_void,
+            lightgbm_sys::C_API_DTYPE_FLOAT32 as i32,
+            num_rows as i32,
+            num_features as i32,
+            1, // row major
+            parameters.as_ptr(),
+            reference,
+            &mut handle.0,
+        ))?;
+        check(lightgbm_sys::LGBM_DatasetSetField(
+            handle.0,
+            label.as_ptr(),
+            y.as_ptr() as *const c_void,
+            num_rows as i32,
+            lightgbm_sys::C_API_DTYPE_FLOAT32 as i32,
+        ))?;
+    }
+    Ok(handle)
+}
+
+/// The lightgbm crate can't evaluate while training, so this boosts one round at a time with
+/// the C API, recording the metrics on the training and test rows. With early stopping, it stops
+/// once the first metric on the validation rows hasn't improved for early_stopping_rounds, and
+/// the model is saved up to the best round. It's loaded back like any other, but the gain of
+/// each feature is only available here.
+fn train(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+    early_stopping_rounds: Option<usize>,
+) -> anyhow::Result<(BoosterHandle, Option<usize>, History, Vec<f32>)> {
+    let mut hyperparams = hyperparams.clone();
+    let mut num_iterations = 100;
+    for alias in [
+        "num_iterations",
+        "num_iteration",
+        "n_iter",
+        "num_tree",
+        "num_trees",
+        "num_round",
+        "num_rounds",
+        "num_boost_round",
+        "n_estimators",
+    ] {
+        if let Some(value) = hyperparams.remove(alias) {
+            num_iterations = value
+                .as_u64()
+                .with_context(|| format!("{} must be an integer", alias))?
+                as usize;
+        }
+    }
+
+    // The metrics are named after the parameter, so it's always set.
+    if !hyperparams.contains_key("metric") {
+        let metric = match hyperparams.get("objective").and_then(|o| o.as_str()) {
+            Some("binary") => "binary_logloss",
+            Some("multiclass") => "multi_logloss",
+            _ => "l2",
+        };
+        hyperparams.insert("metric".to_string(), serde_json::Value::from(metric));
+    }
+    let metrics: Vec<String> = hyperparams["metric"]
+        .as_str()
+        .context("eval_metric must be a string")?
+        .split(',')
+        .map(|metric| metric.trim().to_string())
+        .collect();
+    hyperparams.insert(
+        "is_provide_training_metric".to_string(),
+        serde_json::Value::from(true),
+    );
+
+    let parameters = hyperparams
+        .iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(value) => format!("{key}={value}"),
+            value => format!("{key}={value}"),
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+    let parameters = CString::new(parameters)?;
+
+    // Early stopping needs rows the model isn't trained on, otherwise the test rows are only reported on.
+    let split = dataset.validation_split();
+    let (x_train, y_train, valid_name) = match early_stopping_rounds {
+        Some(_) => (split.x_train, split.y_train, "validation"),
+        None => (&dataset.x_train[..], &dataset.y_train[..], "test"),
+    };
+    let (x_valid, y_valid) = match early_stopping_rounds {
+        Some(_) => (split.x_valid, split.y_valid),
+        None => (&dataset.x_test[..], &dataset.y_test[..]),
+    };
+
+    let train = create_dataset(
+        x_train,
+        y_train,
+        dataset.num_features,
+        &parameters,
+        std::ptr::null_mut(),
+    )?;
+    let valid = create_dataset(x_valid, y_valid, dataset.num_features, &parameters, train.0)?;
+
+    let mut booster = BoosterHandle(std::ptr::null_mut());
+    let mut history = History::new();
+    let mut early_stopping = early_stopping_rounds
+        .map(|early_stopping_rounds| EarlyStopping::new(early_stopping_rounds, &metrics[0]));
+    let mut feature_importance = vec![0_f64; dataset.num_features];
+    let mut rounds = 0;
+    let num_iteration;
+    unsafe {
+        check(lightgbm_sys::LGBM_BoosterCreate(
+            train.0,
+            parameters.as_ptr(),
+            &mut booster.0,
+        ))?;
+        check(lightgbm_sys::LGBM_BoosterAddValidData(booster.0, valid.0))?;
+
+        let mut num_metrics = 0;
+        check(lightgbm_sys::LGBM_BoosterGetEvalCounts(
+            booster.0,
+            &mut num_metrics,
+        ))?;
+        let mut scores = vec![0_f64; num_metrics.max(1) as usize];
+
+        while rounds < num_iterations {
+            let mut finished = 0;
+            check(lightgbm_sys::LGBM_BoosterUpdateOneIter(
+                booster.0,
+                &mut finished,
+            ))?;
+            if finished == 1 {
+                break;
+            }
+            rounds += 1;
+
+            // 0 is the training data, and 1 the first validation data.
+            for (data, name) in [(0, "train"), (1, valid_name)] {
+                let mut num_scores = 0;
+                check(lightgbm_sys::LGBM_BoosterGetEval(
+                    booster.0,
+                    data,
+                    &mut num_scores,
+                    scores.as_mut_ptr(),
+                ))?;
+                for (metric, score) in metrics.iter().zip(&scores[..num_scores as usize]) {
+                    history
+                        .entry(format!("{name}-{metric}"))
+                        .or_default()
+                        .push(*score as f32);
+                }
+            }
+
+            if let Some(early_stopping) = &mut early_stopping {
+                let score = history[&format!("{valid_name}-{}", metrics[0])][rounds - 1];
+                if early_stopping.stop(score as f64) {
+                    break;
+                }
+            }
+        }
+
+        num_iteration = match &early_stopping {
+            Some(early_stopping) => early_stopping.best_iteration() as i32 + 1,
+            None => -1, // all of them
+        };
+        check(lightgbm_sys::LGBM_BoosterFeatureImportance(
+            booster.0,
+            num_iteration,
+            lightgbm_sys::C_API_FEATURE_IMPORTANCE_GAIN as i32,
+            feature_importance.as_mut_ptr(),
+        ))?;
+    }
+
+    // Reloading the model drops the rounds after the best one.
+    let estimator = BoosterHandle::load_string(&booster.save_string(num_iteration)?)?;
+
+    Ok((
+        estimator,
+        early_stopping.map(|early_stopping| early_stopping.best_iteration()),
+        history,
+        feature_importance
+            .into_iter()
+            .map(|gain| gain as f32)
+            .collect(),
+    ))
 }
 
 impl Bindings for Estimator {
@@ -86,7 +428,11 @@ impl Bindings for Estimator {
     fn predict_batch(&self, features: &[f32]) -> Vec<f32> {
         let results = self
             .estimator
-            .predict(features, self.num_features.try_into().unwrap())
+            .predict(
+                features,
+                self.num_features,
+                lightgbm_sys::C_API_PREDICT_NORMAL,
+            )
             .unwrap();
         let results: Vec<f32> = results.into_iter().map(|i| i as f32).collect();
 
@@ -114,17 +460,71 @@ impl Bindings for Estimator {
         }
     }
 
+    /// Predict the probability of each class for a set of datapoints.
+    fn predict_proba(&self, features: &[f32]) -> Option<Vec<f32>> {
+        let results = self
+            .estimator
+            .predict(
+                features,
+                self.num_features,
+                lightgbm_sys::C_API_PREDICT_NORMAL,
+            )
+            .unwrap();
+
+        // Binary classifiers only return the probability of the positive class.
+        match self.num_classes {
+            2 => Some(
+                results
+                    .into_iter()
+                    .flat_map(|i| [1. - i as f32, i as f32])
+                    .collect(),
+            ),
+            num_classes if num_classes > 2 => Some(results.into_iter().map(|i| i as f32).collect()),
+            _ => None,
+        }
+    }
+
+    fn best_iteration(&self) -> Option<usize> {
+        self.best_iteration
+    }
+
+    fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    fn feature_importance(&self) -> Option<Vec<f32>> {
+        self.feature_importance.clone()
+    }
+
+    /// LightGBM's `predict_contrib`, with the expected value last.
+    fn explain(&self, features: &[f32]) -> Option<Vec<f32>> {
+        let contributions: Vec<f32> = self
+            .estimator
+            .predict(
+                features,
+                self.num_features,
+                lightgbm_sys::C_API_PREDICT_CONTRIB,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|x| x as f32)
+            .collect();
+
+        // Multiclass models explain every class.
+        let size = self.num_features + 1;
+        if contributions.len() > size {
+            let class = self.predict(features) as usize;
+            Some(contributions[class * size..(class + 1) * size].to_vec())
+        } else {
+            Some(contributions)
+        }
+    }
+
     /// Serialize self to bytes
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::from((self.num_features as u64).to_be_bytes());
         bytes.append(&mut (self.num_classes as u64).to_be_bytes().to_vec());
-
-        let r: u64 = rand::random();
-        let path = format!("/tmp/pgml_{}.bin", r);
-        self.estimator.save_file(&path).unwrap();
-        bytes.append(&mut std::fs::read(&path).unwrap());
-        std::fs::remove_file(&path).unwrap();
-
+        bytes.append(&mut self.estimator.save_string(-1).unwrap());
         bytes
     }
 
@@ -135,14 +535,14 @@ impl Bindings for Estimator {
     {
         let num_features = u64::from_be_bytes(bytes[..8].try_into().unwrap()) as usize;
         let num_classes = u64::from_be_bytes(bytes[8..16].try_into().unwrap()) as usize;
-        let r: u64 = rand::random();
-        let path = format!("/tmp/pgml_{}.bin", r);
-        std::fs::write(&path, &bytes[16..]).unwrap();
-        let estimator = lightgbm::Booster::from_file(&path).unwrap();
+        let estimator = BoosterHandle::load_string(&bytes[16..]).unwrap();
         Box::new(Estimator {
             estimator,
             num_features,
             num_classes,
+            best_iteration: None,
+            history: None,
+            feature_importance: None,
         })
     }
 }
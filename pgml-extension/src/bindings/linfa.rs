@@ -1,13 +1,13 @@
 use std::convert::From;
 
+use anyhow::Context;
 use linfa::prelude::Predict;
 use linfa::traits::Fit;
 use ndarray::{ArrayView1, ArrayView2};
 use serde::{Deserialize, Serialize};
 
-use super::Bindings;
+use super::{Bindings, History};
 use crate::orm::*;
-use pgx::*;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LinearRegression {
@@ -16,7 +16,7 @@ pub struct LinearRegression {
 }
 
 impl LinearRegression {
-    pub fn fit(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings>
+    pub fn fit(dataset: &Dataset, hyperparams: &Hyperparams) -> anyhow::Result<Box<dyn Bindings>>
     where
         Self: Sized,
     {
@@ -35,18 +35,27 @@ impl LinearRegression {
             match key.as_str() {
                 "fit_intercept" => {
                     estimator = estimator
-                        .with_intercept(value.as_bool().expect("fit_intercept must be boolean"))
+                        .with_intercept(value.as_bool().context("fit_intercept must be boolean")?)
                 }
-                _ => error!("Unknown {}: {:?}", key.as_str(), value),
+                _ => anyhow::bail!("Unknown {}: {:?}", key.as_str(), value),
             };
         }
 
-        let estimator = estimator.fit(&linfa_dataset).unwrap();
+        let estimator = estimator
+            .fit(&linfa_dataset)
+            .map_err(|error| anyhow::anyhow!("{}", error))?;
 
-        Box::new(LinearRegression {
+        Ok(Box::new(LinearRegression {
             estimator,
             num_features: dataset.num_features,
-        })
+        }))
+    }
+
+    pub fn fit_joint(
+        dataset: &Dataset,
+        hyperparams: &Hyperparams,
+    ) -> anyhow::Result<Box<dyn Bindings>> {
+        super::joint::fit::<LinearRegression>(dataset, hyperparams, LinearRegression::fit)
     }
 }
 
@@ -66,6 +75,11 @@ impl Bindings for LinearRegression {
         self.estimator.predict(records).targets.into_raw_vec()
     }
 
+    /// The magnitude of each coefficient.
+    fn feature_importance(&self) -> Option<Vec<f32>> {
+        Some(self.estimator.params().iter().map(|x| x.abs()).collect())
+    }
+
     /// Serialize self to bytes
     fn to_bytes(&self) -> Vec<u8> {
         rmp_serde::to_vec(self).unwrap()
@@ -87,13 +101,54 @@ pub struct LogisticRegression {
     estimator_multi: Option<linfa_logistic::MultiFittedLogisticRegression<f32, i32>>,
     num_features: usize,
     num_distinct_labels: usize,
+    #[serde(skip)]
+    history: Option<History>,
 }
 
 impl LogisticRegression {
-    pub fn fit(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings>
+    pub fn fit(dataset: &Dataset, hyperparams: &Hyperparams) -> anyhow::Result<Box<dyn Bindings>>
     where
         Self: Sized,
     {
+        let mut estimator = Self::fit_iterations(dataset, hyperparams, None)?;
+
+        // linfa doesn't report the loss while optimizing, so trace the training curve
+        // by refitting with a doubling iteration budget until it converges.
+        let max_iterations = hyperparams
+            .get("max_iterations")
+            .map(|value| value.as_i64().unwrap() as u64)
+            .unwrap_or(100);
+        let final_loss = estimator.log_loss(dataset);
+        let mut iterations = Vec::new();
+        let mut losses = Vec::new();
+        let mut budget = 1;
+        while budget < max_iterations {
+            let loss = Self::fit_iterations(dataset, hyperparams, Some(budget))?.log_loss(dataset);
+            iterations.push(budget as f32);
+            losses.push(loss);
+            if loss <= final_loss {
+                break;
+            }
+            budget *= 2;
+        }
+        if budget >= max_iterations {
+            iterations.push(max_iterations as f32);
+            losses.push(final_loss);
+        }
+
+        let mut history = History::new();
+        history.insert("iteration".to_string(), iterations);
+        history.insert("train-log_loss".to_string(), losses);
+        estimator.history = Some(history);
+
+        Ok(Box::new(estimator))
+    }
+
+    fn fit_iterations(
+        dataset: &Dataset,
+        hyperparams: &Hyperparams,
+        max_iterations: Option<u64>,
+    ) -> anyhow::Result<LogisticRegression> {
         let records = ArrayView2::from_shape(
             (dataset.num_train_rows, dataset.num_features),
             &dataset.x_train,
@@ -112,34 +167,47 @@ impl LogisticRegression {
             for (key, value) in hyperparams {
                 match key.as_str() {
                     "fit_intercept" => {
-                        estimator = estimator
-                            .with_intercept(value.as_bool().expect("fit_intercept must be boolean"))
+                        estimator = estimator.with_intercept(
+                            value.as_bool().context("fit_intercept must be boolean")?,
+                        )
                     }
                     "alpha" => {
                         estimator =
-                            estimator.alpha(value.as_f64().expect("alpha must be a float") as f32)
+                            estimator.alpha(value.as_f64().context("alpha must be a float")? as f32)
                     }
                     "max_iterations" => {
                         estimator = estimator.max_iterations(
-                            value.as_i64().expect("max_iterations must be an integer") as u64,
+                            value
+                                .as_i64()
+                                .context("max_iterations must be an integer")?
+                                as u64,
                         )
                     }
                     "gradient_tolerance" => {
                         estimator = estimator.gradient_tolerance(
-                            value.as_f64().expect("gradient_tolerance must be a float") as f32,
+                            value
+                                .as_f64()
+                                .context("gradient_tolerance must be a float")?
+                                as f32,
                         )
                     }
-                    _ => error!("Unknown {}: {:?}", key.as_str(), value),
+                    _ => anyhow::bail!("Unknown {}: {:?}", key.as_str(), value),
                 };
             }
+            if let Some(max_iterations) = max_iterations {
+                estimator = estimator.max_iterations(max_iterations);
+            }
 
-            let estimator = estimator.fit(&linfa_dataset).unwrap();
+            let estimator = estimator
+                .fit(&linfa_dataset)
+                .map_err(|error| anyhow::anyhow!("{}", error))?;
 
-            Box::new(LogisticRegression {
+            Ok(LogisticRegression {
                 estimator_binary: None,
                 estimator_multi: Some(estimator),
                 num_features: dataset.num_features,
                 num_distinct_labels: dataset.num_distinct_labels,
+                history: None,
             })
         } else {
             let mut estimator = linfa_logistic::LogisticRegression::default();
@@ -147,37 +215,65 @@ impl LogisticRegression {
             for (key, value) in hyperparams {
                 match key.as_str() {
                     "fit_intercept" => {
-                        estimator = estimator
-                            .with_intercept(value.as_bool().expect("fit_intercept must be boolean"))
+                        estimator = estimator.with_intercept(
+                            value.as_bool().context("fit_intercept must be boolean")?,
+                        )
                     }
                     "alpha" => {
                         estimator =
-                            estimator.alpha(value.as_f64().expect("alpha must be a float") as f32)
+                            estimator.alpha(value.as_f64().context("alpha must be a float")? as f32)
                     }
                     "max_iterations" => {
                         estimator = estimator.max_iterations(
-                            value.as_i64().expect("max_iterations must be an integer") as u64,
+                            value
+                                .as_i64()
+                                .context("max_iterations must be an integer")?
+                                as u64,
                         )
                     }
                     "gradient_tolerance" => {
                         estimator = estimator.gradient_tolerance(
-                            value.as_f64().expect("gradient_tolerance must be a float") as f32,
+                            value
+                                .as_f64()
+                                .context("gradient_tolerance must be a float")?
+                                as f32,
                         )
                     }
-                    _ => error!("Unknown {}: {:?}", key.as_str(), value),
+                    _ => anyhow::bail!("Unknown {}: {:?}", key.as_str(), value),
                 };
             }
+            if let Some(max_iterations) = max_iterations {
+                estimator = estimator.max_iterations(max_iterations);
+            }
 
-            let estimator = estimator.fit(&linfa_dataset).unwrap();
+            let estimator = estimator
+                .fit(&linfa_dataset)
+                .map_err(|error| anyhow::anyhow!("{}", error))?;
 
-            Box::new(LogisticRegression {
+            Ok(LogisticRegression {
                 estimator_binary: Some(estimator),
                 estimator_multi: None,
                 num_features: dataset.num_features,
                 num_distinct_labels: dataset.num_distinct_labels,
+                history: None,
             })
         }
     }
+
+    /// Mean negative log likelihood of the training labels.
+    fn log_loss(&self, dataset: &Dataset) -> f32 {
+        let probabilities = self.predict_proba(&dataset.x_train).unwrap();
+        let loss: f32 = dataset
+            .y_train
+            .iter()
+            .enumerate()
+            .map(|(row, &label)| {
+                let probability = probabilities[row * self.num_distinct_labels + label as usize];
+                -probability.max(f32::EPSILON).ln()
+            })
+            .sum();
+        loss / dataset.num_train_rows as f32
+    }
 }
 
 impl Bindings for LogisticRegression {
@@ -217,6 +313,46 @@ impl Bindings for LogisticRegression {
         }
     }
 
+    /// Predict the probability of each class for a set of datapoints.
+    fn predict_proba(&self, features: &[f32]) -> Option<Vec<f32>> {
+        let records = ArrayView2::from_shape(
+            (features.len() / self.num_features, self.num_features),
+            features,
+        )
+        .unwrap();
+
+        if self.num_distinct_labels > 2 {
+            let estimator = self.estimator_multi.as_ref().unwrap();
+            let probabilities = estimator.predict_probabilities(&records);
+
+            // Columns are ordered like the classes seen during training,
+            // which may not include every label.
+            let mut results = vec![0.; records.nrows() * self.num_distinct_labels];
+            for (row, probabilities) in probabilities.rows().into_iter().enumerate() {
+                for (&class, &probability) in estimator.classes().iter().zip(probabilities) {
+                    results[row * self.num_distinct_labels + class as usize] = probability;
+                }
+            }
+            Some(results)
+        } else {
+            let estimator = self.estimator_binary.as_ref().unwrap();
+            let positive_class = estimator.labels().pos.class;
+            Some(
+                estimator
+                    .predict_probabilities(&records)
+                    .into_iter()
+                    .flat_map(|probability| {
+                        if positive_class == 1 {
+                            [1. - probability, probability]
+                        } else {
+                            [probability, 1. - probability]
+                        }
+                    })
+                    .collect(),
+            )
+        }
+    }
+
     /// Deserialize self from bytes, with additional context
     fn from_bytes(bytes: &[u8]) -> Box<dyn Bindings>
     where
@@ -230,6 +366,28 @@ impl Bindings for LogisticRegression {
     fn to_bytes(&self) -> Vec<u8> {
         rmp_serde::to_vec(self).unwrap()
     }
+
+    /// Training log loss by number of iterations.
+    fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// The magnitude of each coefficient, summed over the classes.
+    fn feature_importance(&self) -> Option<Vec<f32>> {
+        if self.num_distinct_labels > 2 {
+            let params = self.estimator_multi.as_ref().unwrap().params();
+            Some(
+                params
+                    .rows()
+                    .into_iter()
+                    .map(|row| row.iter().map(|x| x.abs()).sum())
+                    .collect(),
+            )
+        } else {
+            let params = self.estimator_binary.as_ref().unwrap().params();
+            Some(params.iter().map(|x| x.abs()).collect())
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -239,7 +397,7 @@ pub struct Svm {
 }
 
 impl Svm {
-    pub fn fit(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+    pub fn fit(dataset: &Dataset, hyperparams: &Hyperparams) -> anyhow::Result<Box<dyn Bindings>> {
         let records = ArrayView2::from_shape(
             (dataset.num_train_rows, dataset.num_features),
             &dataset.x_train,
@@ -261,30 +419,32 @@ impl Svm {
         for (key, value) in hyperparams {
             match key.as_str() {
                 "eps" => {
-                    estimator = estimator.eps(value.as_f64().expect("eps must be a float") as f32)
+                    estimator = estimator.eps(value.as_f64().context("eps must be a float")? as f32)
                 }
                 "shrinking" => {
                     estimator =
-                        estimator.shrinking(value.as_bool().expect("shrinking must be a bool"))
+                        estimator.shrinking(value.as_bool().context("shrinking must be a bool")?)
                 }
                 "kernel" => {
-                    match value.as_str().expect("kernel must be a string") {
+                    match value.as_str().context("kernel must be a string")? {
                         "poli" => estimator = estimator.polynomial_kernel(3.0, 1.0), // degree = 3, c = 1.0 as per Scikit
                         "linear" => estimator = estimator.linear_kernel(),
                         "rbf" => estimator = estimator.gaussian_kernel(1e-7), // Default eps
-                        value => error!("Unknown kernel: {}", value),
+                        value => anyhow::bail!("Unknown kernel: {}", value),
                     }
                 }
-                _ => error!("Unknown {}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown {}: {:?}", key, value),
             }
         }
 
-        let estimator = estimator.fit(&linfa_dataset).unwrap();
+        let estimator = estimator
+            .fit(&linfa_dataset)
+            .map_err(|error| anyhow::anyhow!("{}", error))?;
 
-        Box::new(Svm {
+        Ok(Box::new(Svm {
             estimator,
             num_features: dataset.num_features,
-        })
+        }))
     }
 }
 
@@ -319,3 +479,94 @@ impl Bindings for Svm {
         rmp_serde::to_vec(self).unwrap()
     }
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KMeans {
+    estimator: linfa_clustering::KMeans<f32, linfa_nn::distance::L2Dist>,
+    num_features: usize,
+}
+
+impl KMeans {
+    pub fn fit(dataset: &Dataset, hyperparams: &Hyperparams) -> anyhow::Result<Box<dyn Bindings>> {
+        let records = ArrayView2::from_shape(
+            (dataset.num_train_rows, dataset.num_features),
+            &dataset.x_train,
+        )
+        .unwrap();
+
+        let linfa_dataset = linfa::DatasetBase::from(records);
+
+        // Same default as Scikit.
+        let n_clusters = match hyperparams.get("n_clusters") {
+            Some(value) => value.as_u64().context("n_clusters must be an integer")? as usize,
+            None => 8,
+        };
+        let mut estimator = linfa_clustering::KMeans::params(n_clusters);
+
+        for (key, value) in hyperparams {
+            match key.as_str() {
+                "n_clusters" => (),
+                "n_init" => {
+                    estimator = estimator
+                        .n_runs(value.as_u64().context("n_init must be an integer")? as usize)
+                }
+                "max_iter" => {
+                    estimator = estimator
+                        .max_n_iterations(value.as_u64().context("max_iter must be an integer")?)
+                }
+                "tol" => {
+                    estimator =
+                        estimator.tolerance(value.as_f64().context("tol must be a float")? as f32)
+                }
+                _ => anyhow::bail!("Unknown {}: {:?}", key, value),
+            }
+        }
+
+        let estimator = estimator
+            .fit(&linfa_dataset)
+            .map_err(|error| anyhow::anyhow!("{}", error))?;
+
+        Ok(Box::new(KMeans {
+            estimator,
+            num_features: dataset.num_features,
+        }))
+    }
+}
+
+impl Bindings for KMeans {
+    /// Predict the cluster of a novel datapoint.
+    fn predict(&self, features: &[f32]) -> f32 {
+        self.predict_batch(features)[0]
+    }
+
+    /// Predict the clusters of a set of datapoints.
+    fn predict_batch(&self, features: &[f32]) -> Vec<f32> {
+        let records = ArrayView2::from_shape(
+            (features.len() / self.num_features, self.num_features),
+            features,
+        )
+        .unwrap();
+
+        self.estimator
+            .predict(records)
+            .targets
+            .into_raw_vec()
+            .into_iter()
+            .map(|x| x as f32)
+            .collect()
+    }
+
+    /// Deserialize self from bytes, with additional context
+    fn from_bytes(bytes: &[u8]) -> Box<dyn Bindings>
+    where
+        Self: Sized,
+    {
+        let estimator: KMeans = rmp_serde::from_read(bytes).unwrap();
+        Box::new(estimator)
+    }
+
+    /// Serialize self to bytes
+    fn to_bytes(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).unwrap()
+    }
+}
@@ -1,3 +1,4 @@
+pub mod joint;
 pub mod lightgbm;
 pub mod linfa;
 
@@ -11,9 +12,19 @@ pub mod transformers;
 
 pub mod xgboost;
 
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
 use crate::orm::*;
 
-pub type Fit = fn(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings>;
+/// Fits may run on other threads, where they can't raise errors in Postgres,
+/// so they return them instead.
+pub type Fit =
+    fn(dataset: &Dataset, hyperparams: &Hyperparams) -> anyhow::Result<Box<dyn Bindings>>;
+
+/// Scores recorded while training, one per round or checkpoint, by name, e.g. `train-rmse`.
+pub type History = indexmap::IndexMap<String, Vec<f32>>;
 
 /// The Bindings trait that has to be implemented by all algorithm
 /// providers we use in PostgresML. We don't rely on Serde serialization,
@@ -27,6 +38,36 @@ pub trait Bindings: Send + Sync {
     /// Predict a set of datapoints.
     fn predict_batch(&self, features: &[f32]) -> Vec<f32>;
 
+    /// Predict the probability of each class for a set of datapoints,
+    /// row by row. Only classifiers that model probabilities implement this.
+    fn predict_proba(&self, _features: &[f32]) -> Option<Vec<f32>> {
+        None
+    }
+
+    /// The boosting round that scored best on the validation rows,
+    /// when training stopped early.
+    fn best_iteration(&self) -> Option<usize> {
+        None
+    }
+
+    /// Scores recorded while training, which aren't serialized with the estimator.
+    fn history(&self) -> Option<&History> {
+        None
+    }
+
+    /// How much each feature contributes to predictions, in the order of the features,
+    /// e.g. the total gain of splits on it for trees, or the size of its coefficients.
+    fn feature_importance(&self) -> Option<Vec<f32>> {
+        None
+    }
+
+    /// The contribution of each feature to the prediction for one datapoint, followed by
+    /// the base value they add up from, for models that compute SHAP values natively.
+    /// Classifiers explain the margin of the predicted class.
+    fn explain(&self, _features: &[f32]) -> Option<Vec<f32>> {
+        None
+    }
+
     /// Deserialize self from bytes, with additional context
     fn from_bytes(bytes: &[u8]) -> Box<dyn Bindings>
     where
@@ -35,3 +76,179 @@ pub trait Bindings: Send + Sync {
     /// Serialize self to bytes
     fn to_bytes(&self) -> Vec<u8>;
 }
+
+/// A file in /tmp for libraries that only read and write models from disk,
+/// removed when it's dropped, even if saving or loading fails.
+pub struct TempFile {
+    pub path: String,
+}
+
+impl TempFile {
+    pub fn new() -> TempFile {
+        let r: u64 = rand::random();
+        TempFile {
+            path: format!("/tmp/pgml_{}.bin", r),
+        }
+    }
+}
+
+impl Default for TempFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Stops boosting once the validation score hasn't improved for `patience` rounds.
+pub struct EarlyStopping {
+    patience: usize,
+    greater_is_better: bool,
+    best_score: f64,
+    best_iteration: usize,
+    iteration: usize,
+}
+
+impl EarlyStopping {
+    /// Ranking metrics like auc are better when higher, errors and losses when lower.
+    /// Ranking metrics may be cut off at a position, e.g. `ndcg@5` or `map@3-`,
+    /// but errors like `mape` only share their prefix.
+    pub fn new(patience: usize, eval_metric: &str) -> EarlyStopping {
+        let greater_is_better = ["auc", "aucpr", "average_precision", "ndcg", "map"]
+            .contains(&eval_metric)
+            || ["ndcg@", "ndcg-", "map@", "map-"]
+                .iter()
+                .any(|prefix| eval_metric.starts_with(prefix));
+
+        EarlyStopping {
+            patience,
+            greater_is_better,
+            best_score: if greater_is_better {
+                f64::NEG_INFINITY
+            } else {
+                f64::INFINITY
+            },
+            best_iteration: 0,
+            iteration: 0,
+        }
+    }
+
+    /// Record the validation score of the next round, true when it's time to stop.
+    pub fn stop(&mut self, score: f64) -> bool {
+        let improved = if self.greater_is_better {
+            score > self.best_score
+        } else {
+            score < self.best_score
+        };
+        if improved {
+            self.best_score = score;
+            self.best_iteration = self.iteration;
+        }
+        self.iteration += 1;
+        self.iteration - self.best_iteration > self.patience
+    }
+
+    /// The round with the best validation score, counting from 0.
+    pub fn best_iteration(&self) -> usize {
+        self.best_iteration
+    }
+}
+
+/// Estimates SHAP values for models that can't explain themselves, by sampling permutations
+/// (Štrumbelj & Kononenko, 2014). Features are switched one at a time, in a random order,
+/// from a random background row to the explained datapoint, and each is credited with the
+/// change in prediction. Like `Bindings::explain`, the contributions are followed by the base
+/// value, the mean prediction of the background rows they add up from. Classifiers are
+/// explained by the probability of the predicted class.
+pub fn sample_shap_values(
+    estimator: &dyn Bindings,
+    features: &[f32],
+    background: &[f32],
+) -> Vec<f32> {
+    const NUM_PERMUTATIONS: usize = 64;
+
+    let num_features = features.len();
+    let num_background_rows = background.len() / num_features;
+    assert!(
+        num_background_rows > 0,
+        "SHAP values are estimated against at least one background row"
+    );
+
+    // Explanations are reproducible.
+    let mut rng = StdRng::seed_from_u64(0);
+
+    // Predict every step of every permutation in one batch.
+    let mut rows = Vec::with_capacity(NUM_PERMUTATIONS * (num_features + 1) * num_features);
+    let mut orders = Vec::with_capacity(NUM_PERMUTATIONS);
+    for _ in 0..NUM_PERMUTATIONS {
+        let i = rng.gen_range(0..num_background_rows);
+        let mut row = background[i * num_features..(i + 1) * num_features].to_vec();
+        rows.extend_from_slice(&row);
+
+        let mut order: Vec<usize> = (0..num_features).collect();
+        order.shuffle(&mut rng);
+        for &j in &order {
+            row[j] = features[j];
+            rows.extend_from_slice(&row);
+        }
+        orders.push(order);
+    }
+
+    let predictions = match estimator.predict_proba(&rows) {
+        Some(probabilities) => {
+            let num_classes = probabilities.len() / (rows.len() / num_features);
+            let class = estimator.predict(features) as usize;
+            probabilities
+                .into_iter()
+                .skip(class)
+                .step_by(num_classes)
+                .collect()
+        }
+        None => estimator.predict_batch(&rows),
+    };
+
+    let mut contributions = vec![0.; num_features + 1];
+    for (order, predictions) in orders.iter().zip(predictions.chunks(num_features + 1)) {
+        for (step, &j) in order.iter().enumerate() {
+            contributions[j] += predictions[step + 1] - predictions[step];
+        }
+        contributions[num_features] += predictions[0];
+    }
+    for contribution in &mut contributions {
+        *contribution /= NUM_PERMUTATIONS as f32;
+    }
+    contributions
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgx::pg_schema]
+mod tests {
+    use super::*;
+    use pgx::*;
+
+    #[pg_test]
+    fn test_early_stopping_on_errors() {
+        let mut early_stopping = EarlyStopping::new(2, "mape");
+        let stopped: Vec<bool> = [0.5, 0.3, 0.4, 0.35]
+            .iter()
+            .map(|&score| early_stopping.stop(score))
+            .collect();
+        assert_eq!(stopped, vec![false, false, false, true]);
+        assert_eq!(early_stopping.best_iteration(), 1);
+    }
+
+    #[pg_test]
+    fn test_early_stopping_on_rankings() {
+        for metric in ["auc", "ndcg@5", "map-"] {
+            let mut early_stopping = EarlyStopping::new(1, metric);
+            assert!(!early_stopping.stop(0.6));
+            assert!(!early_stopping.stop(0.8));
+            assert!(early_stopping.stop(0.7));
+            assert_eq!(early_stopping.best_iteration(), 1);
+        }
+    }
+}
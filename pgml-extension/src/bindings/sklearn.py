@@ -9,6 +9,8 @@ import sklearn.svm
 import sklearn.ensemble
 import sklearn.multioutput
 import sklearn.gaussian_process
+import sklearn.cluster
+import sklearn.metrics
 import sklearn.model_selection
 import xgboost as xgb
 import lightgbm
@@ -64,6 +66,28 @@ _ALGORITHM_MAP = {
     "xgboost_random_forest_classification": xgb.XGBRFClassifier,
     "lightgbm_regression": lightgbm.LGBMRegressor,
     "lightgbm_classification": lightgbm.LGBMClassifier,
+    "kmeans_clustering": sklearn.cluster.KMeans,
+    "dbscan_clustering": sklearn.cluster.DBSCAN,
+}
+
+# These estimators only learn a single target, so joint models
+# fit one copy of the estimator per target instead.
+_SINGLE_TARGET_ALGORITHMS = {
+    "bayesian_ridge_regression",
+    "automatic_relevance_determination_regression",
+    "stochastic_gradient_descent_regression",
+    "passive_aggressive_regression",
+    "theil_sen_regression",
+    "huber_regression",
+    "quantile_regression",
+    "svm_regression",
+    "nu_svm_regression",
+    "linear_svm_regression",
+    "ada_boost_regression",
+    "gradient_boosting_trees_regression",
+    "hist_gradient_boosting_regression",
+    "xgboost_random_forest_regression",
+    "lightgbm_regression",
 }
 
 
@@ -100,8 +124,17 @@ def estimator_joint(algorithm, num_features, num_targets, hyperparams):
 
         X_train = np.asarray(X_train).reshape((-1, num_features))
 
-        # Only support single value models for just now.
-        y_train = np.asarray(y_train).reshape((-1, num_targets))
+        # Clustering is unsupervised.
+        if num_targets == 0:
+            instance.fit(X_train)
+            return instance
+
+        if num_targets == 1:
+            y_train = np.asarray(y_train).flatten()
+        else:
+            y_train = np.asarray(y_train).reshape((-1, num_targets))
+            if algorithm in _SINGLE_TARGET_ALGORITHMS:
+                instance = sklearn.multioutput.MultiOutputRegressor(instance)
 
         instance.fit(X_train, y_train)
         return instance
@@ -129,17 +162,77 @@ def predictor_joint(estimator, num_targets):
     """
     def predict(X):
         X = np.asarray(X).reshape((-1, estimator.n_features_in_))
-        y_hat = estimator.predict(X)
-
-        # Only support single value models for just now.
-        if num_targets == 1:
-            return list(np.asarray(y_hat).flatten())
+        if isinstance(estimator, sklearn.cluster.DBSCAN):
+            y_hat = _dbscan_predict(estimator, X)
         else:
-            return list(y_hat)
+            y_hat = estimator.predict(X)
+
+        # Joint models return one row of num_targets per sample,
+        # flattened row by row.
+        return list(np.asarray(y_hat).flatten())
 
     return predict
 
 
+def predictor_proba(estimator):
+    """Return a function that predicts the probability of each class,
+    or None if the estimator doesn't model probabilities.
+
+    Parameters:
+        - estimator: Scikit-Learn estimator, instantiated.
+    """
+    def predict_proba(X):
+        if not hasattr(estimator, "predict_proba"):
+            return None
+
+        X = np.asarray(X).reshape((-1, estimator.n_features_in_))
+
+        # One row of probabilities per class, flattened row by row.
+        return list(np.asarray(estimator.predict_proba(X)).flatten())
+
+    return predict_proba
+
+
+def feature_importance(estimator):
+    """Return how much each feature contributes to predictions,
+    or None if the estimator doesn't say.
+
+    Parameters:
+        - estimator: Scikit-Learn estimator, fitted.
+    """
+    if hasattr(estimator, "feature_importances_"):
+        return list(np.asarray(estimator.feature_importances_, dtype=float))
+
+    if hasattr(estimator, "coef_"):
+        # Classifiers have a row of coefficients per class.
+        coef = np.abs(np.asarray(estimator.coef_, dtype=float))
+        return list(coef.reshape((-1, estimator.n_features_in_)).sum(axis=0))
+
+    return None
+
+
+def _dbscan_predict(estimator, X):
+    """DBSCAN can't predict novel datapoints, so we assign them to the cluster
+    of the nearest core sample within eps, or to noise (-1) otherwise.
+
+    Parameters:
+        - estimator: DBSCAN estimator, fitted.
+        - X: The datapoints to cluster.
+    """
+    y_hat = np.full(X.shape[0], -1)
+    if len(estimator.core_sample_indices_) == 0:
+        return y_hat
+
+    core_labels = estimator.labels_[estimator.core_sample_indices_]
+    distances = sklearn.metrics.pairwise_distances(
+        X, estimator.components_, metric=estimator.metric
+    )
+    nearest = distances.argmin(axis=1)
+    within_eps = distances[np.arange(X.shape[0]), nearest] <= estimator.eps
+    y_hat[within_eps] = core_labels[nearest[within_eps]]
+    return y_hat
+
+
 def save(estimator):
     """Save the estimtator as bytes (pickle).
 
@@ -14,45 +14,66 @@ use crate::bindings::Bindings;
 
 use crate::orm::*;
 
-pub fn linear_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn linear_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "linear_regression")
 }
 
-pub fn lasso_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn lasso_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "lasso_regression")
 }
 
-pub fn svm_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn svm_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "svm_regression")
 }
 
-pub fn elastic_net_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn elastic_net_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "elastic_net_regression")
 }
 
-pub fn ridge_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn ridge_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "ridge_regression")
 }
 
-pub fn random_forest_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn random_forest_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "random_forest_regression")
 }
 
-pub fn xgboost_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn xgboost_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "xgboost_regression")
 }
 
 pub fn xgboost_random_forest_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "xgboost_random_forest_regression")
 }
 
 pub fn orthogonal_matching_persuit_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(
         dataset,
         hyperparams,
@@ -63,14 +84,14 @@ pub fn orthogonal_matching_persuit_regression(
 pub fn bayesian_ridge_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "bayesian_ridge_regression")
 }
 
 pub fn automatic_relevance_determination_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(
         dataset,
         hyperparams,
@@ -81,7 +102,7 @@ pub fn automatic_relevance_determination_regression(
 pub fn stochastic_gradient_descent_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(
         dataset,
         hyperparams,
@@ -92,120 +113,168 @@ pub fn stochastic_gradient_descent_regression(
 pub fn passive_aggressive_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "passive_aggressive_regression")
 }
 
-pub fn ransac_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn ransac_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "ransac_regression")
 }
 
-pub fn theil_sen_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn theil_sen_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "theil_sen_regression")
 }
 
-pub fn huber_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn huber_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "huber_regression")
 }
 
-pub fn quantile_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn quantile_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "quantile_regression")
 }
 
-pub fn kernel_ridge_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn kernel_ridge_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "kernel_ridge_regression")
 }
 
 pub fn gaussian_process_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "gaussian_process_regression")
 }
 
-pub fn nu_svm_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn nu_svm_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "nu_svm_regression")
 }
 
-pub fn ada_boost_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn ada_boost_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "ada_boost_regression")
 }
 
-pub fn bagging_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn bagging_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "bagging_regression")
 }
 
-pub fn extra_trees_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn extra_trees_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "extra_trees_regression")
 }
 
 pub fn gradient_boosting_trees_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "gradient_boosting_trees_regression")
 }
 
 pub fn hist_gradient_boosting_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "hist_gradient_boosting_regression")
 }
 
-pub fn least_angle_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn least_angle_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "least_angle_regression")
 }
 
 pub fn lasso_least_angle_regression(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "lasso_least_angle_regression")
 }
 
-pub fn linear_svm_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn linear_svm_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "linear_svm_regression")
 }
 
-pub fn lightgbm_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn lightgbm_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "lightgbm_regression")
 }
 
-pub fn linear_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn linear_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "linear_classification")
 }
 
-pub fn svm_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn svm_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "svm_classification")
 }
 
-pub fn ridge_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn ridge_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "ridge_classification")
 }
 
 pub fn random_forest_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "random_forest_classification")
 }
 
-pub fn xgboost_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn xgboost_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "xgboost_classification")
 }
 
 pub fn xgboost_random_forest_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "xgboost_random_forest_classification")
 }
 
 pub fn stochastic_gradient_descent_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(
         dataset,
         hyperparams,
@@ -216,44 +285,56 @@ pub fn stochastic_gradient_descent_classification(
 pub fn perceptron_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "perceptron_classification")
 }
 
 pub fn passive_aggressive_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "passive_aggressive_classification")
 }
 
-pub fn gaussian_process(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn gaussian_process(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "gaussian_process")
 }
 
-pub fn nu_svm_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn nu_svm_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "nu_svm_classification")
 }
 
-pub fn ada_boost_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn ada_boost_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "ada_boost_classification")
 }
 
-pub fn bagging_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn bagging_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "bagging_classification")
 }
 
 pub fn extra_trees_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "extra_trees_classification")
 }
 
 pub fn gradient_boosting_trees_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(
         dataset,
         hyperparams,
@@ -264,7 +345,7 @@ pub fn gradient_boosting_trees_classification(
 pub fn hist_gradient_boosting_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(
         dataset,
         hyperparams,
@@ -275,19 +356,36 @@ pub fn hist_gradient_boosting_classification(
 pub fn linear_svm_classification(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "linear_svm_classification")
 }
 
-pub fn lightgbm_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn lightgbm_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, "lightgbm_classification")
 }
 
+pub fn kmeans_clustering(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
+    fit(dataset, hyperparams, "kmeans_clustering")
+}
+
+pub fn dbscan_clustering(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
+    fit(dataset, hyperparams, "dbscan_clustering")
+}
+
 fn fit(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
     algorithm_task: &'static str,
-) -> Box<dyn Bindings> {
+) -> anyhow::Result<Box<dyn Bindings>> {
     let module = include_str!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/src/bindings/sklearn.py"
@@ -295,9 +393,9 @@ fn fit(
 
     let hyperparams = serde_json::to_string(hyperparams).unwrap();
 
-    let (estimator, wrapper) = Python::with_gil(|py| -> (Py<PyAny>, Py<PyAny>) {
+    let (estimator, wrapper, proba_wrapper) = Python::with_gil(|py| {
         let module = PyModule::from_code(py, module, "", "").unwrap();
-        let estimator: Py<PyAny> = module.getattr("estimator").unwrap().into();
+        let estimator: Py<PyAny> = module.getattr("estimator_joint").unwrap().into();
 
         let train: Py<PyAny> = estimator
             .call1(
@@ -307,6 +405,7 @@ fn fit(
                     &[
                         String::from(algorithm_task).into_py(py),
                         dataset.num_features.into_py(py),
+                        dataset.num_labels.into_py(py),
                         hyperparams.into_py(py),
                     ],
                 ),
@@ -317,23 +416,39 @@ fn fit(
             .call1(py, PyTuple::new(py, &[&dataset.x_train, &dataset.y_train]))
             .unwrap();
 
-        let predictor = module.getattr("predictor").unwrap();
+        let predictor = module.getattr("predictor_joint").unwrap();
 
         let wrapper: Py<PyAny> = predictor
-            .call1(PyTuple::new(py, &[&estimator]))
+            .call1(PyTuple::new(
+                py,
+                &[estimator.clone_ref(py), dataset.num_labels.into_py(py)],
+            ))
+            .unwrap()
+            .extract()
+            .unwrap();
+
+        let proba_wrapper: Py<PyAny> = module
+            .getattr("predictor_proba")
+            .unwrap()
+            .call1(PyTuple::new(py, &[estimator.clone_ref(py)]))
             .unwrap()
             .extract()
             .unwrap();
 
-        (estimator, wrapper)
+        (estimator, wrapper, proba_wrapper)
     });
 
-    Box::new(Estimator { estimator, wrapper })
+    Ok(Box::new(Estimator {
+        estimator,
+        wrapper,
+        proba_wrapper,
+    }))
 }
 
 pub struct Estimator {
     estimator: Py<PyAny>,
     wrapper: Py<PyAny>,
+    proba_wrapper: Py<PyAny>,
 }
 
 unsafe impl Send for Estimator {}
@@ -367,6 +482,35 @@ impl Bindings for Estimator {
         y_hat
     }
 
+    /// Predict the probability of each class for a set of datapoints.
+    fn predict_proba(&self, features: &[f32]) -> Option<Vec<f32>> {
+        Python::with_gil(|py| -> Option<Vec<f32>> {
+            self.proba_wrapper
+                .call1(py, PyTuple::new(py, &[features]))
+                .unwrap()
+                .extract(py)
+                .unwrap()
+        })
+    }
+
+    /// Scikit-learn's `feature_importances_`, or the magnitude of `coef_`.
+    fn feature_importance(&self) -> Option<Vec<f32>> {
+        let module = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/bindings/sklearn.py"
+        ));
+
+        Python::with_gil(|py| -> Option<Vec<f32>> {
+            let module = PyModule::from_code(py, module, "", "").unwrap();
+            let feature_importance = module.getattr("feature_importance").unwrap();
+            feature_importance
+                .call1(PyTuple::new(py, &[&self.estimator]))
+                .unwrap()
+                .extract()
+                .unwrap()
+        })
+    }
+
     /// Serialize self to bytes
     fn to_bytes(&self) -> Vec<u8> {
         let module = include_str!(concat!(
@@ -411,7 +555,19 @@ impl Bindings for Estimator {
                 .extract()
                 .unwrap();
 
-            Box::new(Estimator { estimator, wrapper })
+            let proba_wrapper: Py<PyAny> = module
+                .getattr("predictor_proba")
+                .unwrap()
+                .call1(PyTuple::new(py, &[&estimator]))
+                .unwrap()
+                .extract()
+                .unwrap();
+
+            Box::new(Estimator {
+                estimator,
+                wrapper,
+                proba_wrapper,
+            })
         })
     }
 }
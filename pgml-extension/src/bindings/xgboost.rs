@@ -1,3 +1,7 @@
+use std::ffi::{CStr, CString};
+
+use anyhow::Context;
+
 use xgboost::parameters::tree::*;
 use xgboost::parameters::*;
 /// XGBoost implementation.
@@ -11,7 +15,7 @@ use xgboost::{Booster, DMatrix};
 use crate::orm::dataset::Dataset;
 use crate::orm::Hyperparams;
 
-use crate::bindings::Bindings;
+use crate::bindings::{Bindings, EarlyStopping, History, TempFile};
 
 use pgx::*;
 
@@ -20,78 +24,111 @@ fn xgboost_version() -> String {
     String::from("1.62")
 }
 
-fn get_dart_params(hyperparams: &Hyperparams) -> dart::DartBoosterParameters {
+fn float(key: &str, value: &serde_json::Value) -> anyhow::Result<f32> {
+    value
+        .as_f64()
+        .map(|value| value as f32)
+        .with_context(|| format!("{} must be a float", key))
+}
+
+fn integer(key: &str, value: &serde_json::Value) -> anyhow::Result<u64> {
+    value
+        .as_u64()
+        .with_context(|| format!("{} must be an integer", key))
+}
+
+fn boolean(key: &str, value: &serde_json::Value) -> anyhow::Result<bool> {
+    value
+        .as_bool()
+        .with_context(|| format!("{} must be a bool", key))
+}
+
+fn string<'a>(key: &str, value: &'a serde_json::Value) -> anyhow::Result<&'a str> {
+    value
+        .as_str()
+        .with_context(|| format!("{} must be a string", key))
+}
+
+fn get_dart_params(hyperparams: &Hyperparams) -> anyhow::Result<dart::DartBoosterParameters> {
     let mut params = dart::DartBoosterParametersBuilder::default();
     for (key, value) in hyperparams {
         match key.as_str() {
-            "rate_drop" => params.rate_drop(value.as_f64().unwrap() as f32),
-            "one_drop" => params.one_drop(value.as_bool().unwrap()),
-            "skip_drop" => params.skip_drop(value.as_f64().unwrap() as f32),
-            "sample_type" => match value.as_str().unwrap() {
+            "rate_drop" => params.rate_drop(float(key, value)?),
+            "one_drop" => params.one_drop(boolean(key, value)?),
+            "skip_drop" => params.skip_drop(float(key, value)?),
+            "sample_type" => match string(key, value)? {
                 "uniform" => params.sample_type(dart::SampleType::Uniform),
                 "weighted" => params.sample_type(dart::SampleType::Weighted),
-                _ => panic!("Unknown {:?}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown {:?}: {:?}", key, value),
             },
-            "normalize_type" => match value.as_str().unwrap() {
+            "normalize_type" => match string(key, value)? {
                 "tree" => params.normalize_type(dart::NormalizeType::Tree),
                 "forest" => params.normalize_type(dart::NormalizeType::Forest),
-                _ => panic!("Unknown {:?}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown {:?}: {:?}", key, value),
             },
-            "booster" | "n_estimators" | "boost_rounds" => &mut params, // Valid but not relevant to this section
-            _ => panic!("Unknown {:?}: {:?}", key, value),
+            "booster"
+            | "n_estimators"
+            | "boost_rounds"
+            | "early_stopping_rounds"
+            | "eval_metric" => &mut params, // Valid but not relevant to this section
+            _ => anyhow::bail!("Unknown {:?}: {:?}", key, value),
         };
     }
-    params.build().unwrap()
+    params.build().map_err(anyhow::Error::msg)
 }
 
-fn get_linear_params(hyperparams: &Hyperparams) -> linear::LinearBoosterParameters {
+fn get_linear_params(hyperparams: &Hyperparams) -> anyhow::Result<linear::LinearBoosterParameters> {
     let mut params = linear::LinearBoosterParametersBuilder::default();
     for (key, value) in hyperparams {
         match key.as_str() {
-            "alpha" => params.alpha(value.as_f64().unwrap() as f32),
-            "lambda" => params.lambda(value.as_f64().unwrap() as f32),
-            "updater" => match value.as_str().unwrap() {
+            "alpha" => params.alpha(float(key, value)?),
+            "lambda" => params.lambda(float(key, value)?),
+            "updater" => match string(key, value)? {
                 "shotgun" => params.updater(linear::LinearUpdate::Shotgun),
                 "coord_descent" => params.updater(linear::LinearUpdate::CoordDescent),
-                _ => panic!("Unknown {:?}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown {:?}: {:?}", key, value),
             },
-            "booster" | "n_estimators" | "boost_rounds" => &mut params, // Valid but not relevant to this section
-            _ => panic!("Unknown {:?}: {:?}", key, value),
+            "booster"
+            | "n_estimators"
+            | "boost_rounds"
+            | "early_stopping_rounds"
+            | "eval_metric" => &mut params, // Valid but not relevant to this section
+            _ => anyhow::bail!("Unknown {:?}: {:?}", key, value),
         };
     }
-    params.build().unwrap()
+    params.build().map_err(anyhow::Error::msg)
 }
 
-fn get_tree_params(hyperparams: &Hyperparams) -> tree::TreeBoosterParameters {
+fn get_tree_params(hyperparams: &Hyperparams) -> anyhow::Result<tree::TreeBoosterParameters> {
     let mut params = tree::TreeBoosterParametersBuilder::default();
     for (key, value) in hyperparams {
         match key.as_str() {
-            "eta" => params.eta(value.as_f64().unwrap() as f32),
-            "gamma" => params.gamma(value.as_f64().unwrap() as f32),
-            "max_depth" => params.max_depth(value.as_u64().unwrap() as u32),
-            "min_child_weight" => params.min_child_weight(value.as_f64().unwrap() as f32),
-            "max_delta_step" => params.max_delta_step(value.as_f64().unwrap() as f32),
-            "subsample" => params.subsample(value.as_f64().unwrap() as f32),
-            "colsample_bytree" => params.colsample_bytree(value.as_f64().unwrap() as f32),
-            "colsample_bylevel" => params.colsample_bylevel(value.as_f64().unwrap() as f32),
-            "lambda" => params.lambda(value.as_f64().unwrap() as f32),
-            "alpha" => params.alpha(value.as_f64().unwrap() as f32),
-            "tree_method" => match value.as_str().unwrap() {
+            "eta" => params.eta(float(key, value)?),
+            "gamma" => params.gamma(float(key, value)?),
+            "max_depth" => params.max_depth(integer(key, value)? as u32),
+            "min_child_weight" => params.min_child_weight(float(key, value)?),
+            "max_delta_step" => params.max_delta_step(float(key, value)?),
+            "subsample" => params.subsample(float(key, value)?),
+            "colsample_bytree" => params.colsample_bytree(float(key, value)?),
+            "colsample_bylevel" => params.colsample_bylevel(float(key, value)?),
+            "lambda" => params.lambda(float(key, value)?),
+            "alpha" => params.alpha(float(key, value)?),
+            "tree_method" => match string(key, value)? {
                 "auto" => params.tree_method(TreeMethod::Auto),
                 "exact" => params.tree_method(TreeMethod::Exact),
                 "approx" => params.tree_method(TreeMethod::Approx),
                 "hist" => params.tree_method(TreeMethod::Hist),
                 "gpu_exact" => params.tree_method(TreeMethod::GpuExact),
                 "gpu_hist" => params.tree_method(TreeMethod::GpuHist),
-                _ => panic!("Unknown hyperparameter {:?}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown hyperparameter {:?}: {:?}", key, value),
             },
-            "sketch_eps" => params.sketch_eps(value.as_f64().unwrap() as f32),
-            "scale_pos_weight" => params.scale_pos_weight(value.as_f64().unwrap() as f32),
+            "sketch_eps" => params.sketch_eps(float(key, value)?),
+            "scale_pos_weight" => params.scale_pos_weight(float(key, value)?),
             "updater" => match value.as_array() {
                 Some(array) => {
                     let mut v = Vec::new();
                     for value in array {
-                        match value.as_str().unwrap() {
+                        match string(key, value)? {
                             "grow_col_maker" => v.push(TreeUpdater::GrowColMaker),
                             "dist_col" => v.push(TreeUpdater::DistCol),
                             "grow_hist_maker" => v.push(TreeUpdater::GrowHistMaker),
@@ -100,117 +137,265 @@ fn get_tree_params(hyperparams: &Hyperparams) -> tree::TreeBoosterParameters {
                             "sync" => v.push(TreeUpdater::Sync),
                             "refresh" => v.push(TreeUpdater::Refresh),
                             "prune" => v.push(TreeUpdater::Prune),
-                            _ => panic!("Unknown hyperparameter {:?}: {:?}", key, value),
+                            _ => anyhow::bail!("Unknown hyperparameter {:?}: {:?}", key, value),
                         }
                     }
                     params.updater(v)
                 }
-                _ => panic!("updater should be a JSON array. Got: {:?}", value),
+                _ => anyhow::bail!("updater should be a JSON array. Got: {:?}", value),
             },
-            "refresh_leaf" => params.refresh_leaf(value.as_bool().unwrap()),
-            "process_type" => match value.as_str().unwrap() {
+            "refresh_leaf" => params.refresh_leaf(boolean(key, value)?),
+            "process_type" => match string(key, value)? {
                 "default" => params.process_type(ProcessType::Default),
                 "update" => params.process_type(ProcessType::Update),
-                _ => panic!("Unknown hyperparameter {:?}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown hyperparameter {:?}: {:?}", key, value),
             },
-            "grow_policy" => match value.as_str().unwrap() {
+            "grow_policy" => match string(key, value)? {
                 "depthwise" => params.grow_policy(GrowPolicy::Depthwise),
                 "loss_guide" => params.grow_policy(GrowPolicy::LossGuide),
-                _ => panic!("Unknown hyperparameter {:?}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown hyperparameter {:?}: {:?}", key, value),
             },
-            "predictor" => match value.as_str().unwrap() {
+            "predictor" => match string(key, value)? {
                 "cpu" => params.predictor(Predictor::Cpu),
                 "gpu" => params.predictor(Predictor::Gpu),
-                _ => panic!("Unknown hyperparameter {:?}: {:?}", key, value),
+                _ => anyhow::bail!("Unknown hyperparameter {:?}: {:?}", key, value),
             },
-            "max_leaves" => params.max_leaves(value.as_u64().unwrap() as u32),
-            "max_bin" => params.max_bin(value.as_u64().unwrap() as u32),
-            "booster" | "n_estimators" | "boost_rounds" => &mut params, // Valid but not relevant to this section
-            _ => panic!("Unknown hyperparameter {:?}: {:?}", key, value),
+            "max_leaves" => params.max_leaves(integer(key, value)? as u32),
+            "max_bin" => params.max_bin(integer(key, value)? as u32),
+            "booster"
+            | "n_estimators"
+            | "boost_rounds"
+            | "early_stopping_rounds"
+            | "eval_metric" => &mut params, // Valid but not relevant to this section
+            _ => anyhow::bail!("Unknown hyperparameter {:?}: {:?}", key, value),
         };
     }
-    params.build().unwrap()
+    params.build().map_err(anyhow::Error::msg)
 }
 
-pub fn fit_regression(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn fit_regression(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(dataset, hyperparams, learning::Objective::RegLinear)
 }
 
-pub fn fit_classification(dataset: &Dataset, hyperparams: &Hyperparams) -> Box<dyn Bindings> {
+pub fn fit_regression_joint(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
+    crate::bindings::joint::fit::<Estimator>(dataset, hyperparams, fit_regression)
+}
+
+pub fn fit_classification(
+    dataset: &Dataset,
+    hyperparams: &Hyperparams,
+) -> anyhow::Result<Box<dyn Bindings>> {
     fit(
         dataset,
         hyperparams,
-        learning::Objective::MultiSoftmax(dataset.num_distinct_labels.try_into().unwrap()),
+        learning::Objective::MultiSoftprob(dataset.num_distinct_labels.try_into().unwrap()),
     )
 }
 
+fn eval_metric(name: &str) -> anyhow::Result<learning::EvaluationMetric> {
+    Ok(match name {
+        "rmse" => learning::EvaluationMetric::RMSE,
+        "mae" => learning::EvaluationMetric::MAE,
+        "logloss" => learning::EvaluationMetric::LogLoss,
+        "error" => learning::EvaluationMetric::BinaryErrorRate(0.5),
+        "merror" => learning::EvaluationMetric::MultiClassErrorRate,
+        "mlogloss" => learning::EvaluationMetric::MultiClassLogLoss,
+        "auc" => learning::EvaluationMetric::AUC,
+        _ => anyhow::bail!("Unknown eval_metric: {:?}", name),
+    })
+}
+
 fn fit(
     dataset: &Dataset,
     hyperparams: &Hyperparams,
     objective: learning::Objective,
-) -> Box<dyn Bindings> {
-    // split the train/test data into DMatrix
-    let mut dtrain = DMatrix::from_dense(&dataset.x_train, dataset.num_train_rows).unwrap();
-    let mut dtest = DMatrix::from_dense(&dataset.x_test, dataset.num_test_rows).unwrap();
-    dtrain.set_labels(&dataset.y_train).unwrap();
-    dtest.set_labels(&dataset.y_test).unwrap();
+) -> anyhow::Result<Box<dyn Bindings>> {
+    let eval_metric = hyperparams
+        .get("eval_metric")
+        .map(|value| string("eval_metric", value))
+        .transpose()?;
+    let early_stopping_rounds = hyperparams
+        .get("early_stopping_rounds")
+        .map(|value| integer("early_stopping_rounds", value))
+        .transpose()?
+        .map(|rounds| rounds as usize);
 
-    // specify datasets to evaluate against during training
-    let evaluation_sets = &[(&dtrain, "train"), (&dtest, "test")];
+    // hold out the validation rows when stopping early, otherwise just report on the test set
+    let split = dataset.validation_split();
+    let (x_train, y_train, num_train_rows) = match early_stopping_rounds {
+        Some(_) => (split.x_train, split.y_train, split.num_train_rows),
+        None => (
+            &dataset.x_train[..],
+            &dataset.y_train[..],
+            dataset.num_train_rows,
+        ),
+    };
+    let (x_test, y_test, num_test_rows) = match early_stopping_rounds {
+        Some(_) => (split.x_valid, split.y_valid, split.num_valid_rows),
+        None => (
+            &dataset.x_test[..],
+            &dataset.y_test[..],
+            dataset.num_test_rows,
+        ),
+    };
+    let test_name = if early_stopping_rounds.is_some() {
+        "validation"
+    } else {
+        "test"
+    };
 
-    let learning_params = learning::LearningTaskParametersBuilder::default()
-        .objective(objective)
-        .build()
-        .unwrap();
+    // split the train/test data into DMatrix
+    let mut dtrain = DMatrix::from_dense(x_train, num_train_rows)?;
+    let mut dtest = DMatrix::from_dense(x_test, num_test_rows)?;
+    dtrain.set_labels(y_train)?;
+    dtest.set_labels(y_test)?;
+
+    let mut learning_params = learning::LearningTaskParametersBuilder::default();
+    learning_params.objective(objective);
+    if let Some(eval_metric) = eval_metric {
+        learning_params.eval_metrics(learning::Metrics::Custom(vec![self::eval_metric(
+            eval_metric,
+        )?]));
+    }
+    let learning_params = learning_params.build().map_err(anyhow::Error::msg)?;
 
     // overall configuration for Booster
     let booster_params = BoosterParametersBuilder::default()
         .learning_params(learning_params)
         .booster_type(match hyperparams.get("booster") {
-            Some(value) => match value.as_str().unwrap() {
-                "gbtree" => BoosterType::Tree(get_tree_params(hyperparams)),
-                "linear" => BoosterType::Linear(get_linear_params(hyperparams)),
-                "dart" => BoosterType::Dart(get_dart_params(hyperparams)),
-                _ => panic!("Unknown booster: {:?}", value),
+            Some(value) => match string("booster", value)? {
+                "gbtree" => BoosterType::Tree(get_tree_params(hyperparams)?),
+                "linear" => BoosterType::Linear(get_linear_params(hyperparams)?),
+                "dart" => BoosterType::Dart(get_dart_params(hyperparams)?),
+                _ => anyhow::bail!("Unknown booster: {:?}", value),
             },
-            _ => BoosterType::Tree(get_tree_params(hyperparams)),
+            _ => BoosterType::Tree(get_tree_params(hyperparams)?),
         })
         .verbose(true)
         .build()
-        .unwrap();
+        .map_err(anyhow::Error::msg)?;
 
-    let mut builder = TrainingParametersBuilder::default();
     // number of training iterations is aliased
-    match hyperparams.get("n_estimators") {
-        Some(value) => builder.boost_rounds(value.as_u64().unwrap() as u32),
+    let boost_rounds = match hyperparams.get("n_estimators") {
+        Some(value) => integer("n_estimators", value)? as usize,
         None => match hyperparams.get("boost_rounds") {
-            Some(value) => builder.boost_rounds(value.as_u64().unwrap() as u32),
-            None => &mut builder,
+            Some(value) => integer("boost_rounds", value)? as usize,
+            None => 10,
         },
     };
 
-    let params = builder
-        // dataset to train with
-        .dtrain(&dtrain)
-        // optional datasets to evaluate against in each iteration
-        .evaluation_sets(Some(evaluation_sets))
-        // model parameters
-        .booster_params(booster_params)
-        .build()
-        .unwrap();
+    // Boost one round at a time, recording the scores on the train and test sets,
+    // until the test score stops improving if stopping early.
+    let mut booster = Booster::new_with_cached_dmats(&booster_params, &[&dtrain, &dtest])?;
+    let mut history = History::new();
+    let mut early_stopping = early_stopping_rounds.map(|early_stopping_rounds| {
+        EarlyStopping::new(early_stopping_rounds, eval_metric.unwrap_or(""))
+    });
+    let mut rounds = 0;
+    while rounds < boost_rounds {
+        booster.update(&dtrain, rounds as i32)?;
+        rounds += 1;
+
+        for (dmatrix, name) in [(&dtrain, "train"), (&dtest, test_name)] {
+            for (metric, score) in booster.evaluate(dmatrix)? {
+                history
+                    .entry(format!("{name}-{metric}"))
+                    .or_default()
+                    .push(score);
+            }
+        }
 
-    // train model, and print evaluation data
-    let booster = Booster::train(&params).unwrap();
+        // like xgboost, stop on the last metric evaluated on the test set
+        if let Some(early_stopping) = &mut early_stopping {
+            let (_, scores) = history
+                .iter()
+                .filter(|(name, _)| name.starts_with(test_name))
+                .last()
+                .unwrap();
+            if early_stopping.stop(*scores.last().unwrap() as f64) {
+                break;
+            }
+        }
+    }
+
+    // Like LightGBM, only the rounds up to the best one are kept, dropping the trees
+    // that only overfit.
+    let best_iteration = early_stopping.map(|early_stopping| early_stopping.best_iteration());
+    if let Some(best_iteration) = best_iteration {
+        if best_iteration + 1 < rounds {
+            booster = slice(&booster, best_iteration + 1)?;
+        }
+    }
 
-    Box::new(Estimator {
+    Ok(Box::new(Estimator {
         estimator: booster,
         num_features: dataset.num_features,
-    })
+        best_iteration,
+        history: Some(history),
+    }))
+}
+
+/// XGBoost's last error, if a call into the C API failed.
+fn check(result: i32) -> anyhow::Result<()> {
+    if result != 0 {
+        let error = unsafe { CStr::from_ptr(xgboost_sys::XGBGetLastError()) };
+        anyhow::bail!("XGBoost: {}", error.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// A booster created with the C API, freed when it's dropped.
+struct BoosterHandle(xgboost_sys::BoosterHandle);
+
+impl Drop for BoosterHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { xgboost_sys::XGBoosterFree(self.0) };
+        }
+    }
+}
+
+/// A copy of the booster with only its first `num_rounds` boosting rounds.
+fn slice(booster: &Booster, num_rounds: usize) -> anyhow::Result<Booster> {
+    let file = TempFile::new();
+    booster.save(std::path::Path::new(&file.path))?;
+    let filename = CString::new(file.path.clone())?;
+
+    {
+        let mut handle = BoosterHandle(std::ptr::null_mut());
+        let mut sliced = BoosterHandle(std::ptr::null_mut());
+        unsafe {
+            check(xgboost_sys::XGBoosterCreate(
+                std::ptr::null(),
+                0,
+                &mut handle.0,
+            ))?;
+            check(xgboost_sys::XGBoosterLoadModel(handle.0, filename.as_ptr()))?;
+            check(xgboost_sys::XGBoosterSlice(
+                handle.0,
+                0,
+                num_rounds as i32,
+                1,
+                &mut sliced.0,
+            ))?;
+            check(xgboost_sys::XGBoosterSaveModel(sliced.0, filename.as_ptr()))?;
+        }
+    }
+
+    Ok(Booster::load(std::path::Path::new(&file.path))?)
 }
 
 pub struct Estimator {
     estimator: xgboost::Booster,
     num_features: usize,
+    best_iteration: Option<usize>,
+    history: Option<History>,
 }
 
 unsafe impl Send for Estimator {}
@@ -233,19 +418,114 @@ impl Bindings for Estimator {
 
     /// Predict a novel datapoint.
     fn predict_batch(&self, features: &[f32]) -> Vec<f32> {
-        let x = DMatrix::from_dense(features, features.len() / self.num_features).unwrap();
-        self.estimator.predict(&x).unwrap()
+        let num_rows = features.len() / self.num_features;
+        let x = DMatrix::from_dense(features, num_rows).unwrap();
+        let results = self.estimator.predict(&x).unwrap();
+
+        // Classifiers return probabilities for each class. Convert to discrete classes.
+        if results.len() > num_rows {
+            let num_classes = results.len() / num_rows;
+            results
+                .chunks(num_classes)
+                .map(|probabilities| {
+                    probabilities
+                        .iter()
+                        .enumerate()
+                        .fold((0, f32::MIN), |max, (i, &probability)| {
+                            if probability > max.1 {
+                                (i, probability)
+                            } else {
+                                max
+                            }
+                        })
+                        .0 as f32
+                })
+                .collect()
+        } else {
+            results
+        }
+    }
+
+    /// Predict the probability of each class for a set of datapoints.
+    fn predict_proba(&self, features: &[f32]) -> Option<Vec<f32>> {
+        let num_rows = features.len() / self.num_features;
+        let x = DMatrix::from_dense(features, num_rows).unwrap();
+        let results = self.estimator.predict(&x).unwrap();
+
+        // Regressors, and classifiers trained with the softmax objective
+        // before probabilities were supported, only return a value per row.
+        if results.len() > num_rows {
+            Some(results)
+        } else {
+            None
+        }
+    }
+
+    fn best_iteration(&self) -> Option<usize> {
+        self.best_iteration
+    }
+
+    fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// XGBoost's `pred_contribs`, with the bias last.
+    fn explain(&self, features: &[f32]) -> Option<Vec<f32>> {
+        let x = DMatrix::from_dense(features, 1).unwrap();
+        let contributions: Vec<f32> = self
+            .estimator
+            .predict_contributions(&x)
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+
+        // Multiclass models explain every class.
+        let size = self.num_features + 1;
+        if contributions.len() > size {
+            let class = self.predict(features) as usize;
+            Some(contributions[class * size..(class + 1) * size].to_vec())
+        } else {
+            Some(contributions)
+        }
+    }
+
+    /// The total gain of the splits on each feature, read from the text dump of the trees,
+    /// where splits look like `0:[f2<0.5] yes=1,no=2,missing=1,gain=12.3,cover=45`.
+    /// Linear boosters don't split, so they have none.
+    fn feature_importance(&self) -> Option<Vec<f32>> {
+        let dump = self.estimator.dump_model(true, None).unwrap();
+        let mut importance = vec![0.; self.num_features];
+        let mut splits = 0;
+        for line in dump.lines() {
+            let feature = match line.split_once("[f") {
+                Some((_, split)) => split.split(['<', ']']).next().unwrap(),
+                None => continue,
+            };
+            let gain = match line.split_once("gain=") {
+                Some((_, stats)) => stats.split(',').next().unwrap(),
+                None => continue,
+            };
+            importance[feature.parse::<usize>().unwrap()] += gain.parse::<f32>().unwrap();
+            splits += 1;
+        }
+
+        if splits == 0 {
+            None
+        } else {
+            Some(importance)
+        }
     }
 
     /// Serialize self to bytes
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::from((self.num_features as u64).to_be_bytes());
 
-        let r: u64 = rand::random();
-        let path = format!("/tmp/pgml_{}.bin", r);
-        self.estimator.save(std::path::Path::new(&path)).unwrap();
-        bytes.append(&mut std::fs::read(&path).unwrap());
-        std::fs::remove_file(&path).unwrap();
+        let file = TempFile::new();
+        self.estimator
+            .save(std::path::Path::new(&file.path))
+            .unwrap();
+        bytes.append(&mut std::fs::read(&file.path).unwrap());
 
         bytes
     }
@@ -260,6 +540,8 @@ impl Bindings for Estimator {
         Box::new(Estimator {
             estimator,
             num_features,
+            best_iteration: None,
+            history: None,
         })
     }
 }
@@ -12,6 +12,7 @@ pub mod api;
 pub mod bindings;
 pub mod orm;
 pub mod vectors;
+pub mod worker;
 
 pg_module_magic!();
 
@@ -1,6 +1,10 @@
 use pgx::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::fmt::{Display, Formatter};
 
+use crate::orm::Sampling;
+
 #[derive(Debug)]
 pub struct Dataset {
     pub x_train: Vec<f32>,
@@ -13,6 +17,20 @@ pub struct Dataset {
     pub num_train_rows: usize,
     pub num_test_rows: usize,
     pub num_distinct_labels: usize,
+    pub sampling: Sampling,
+}
+
+/// The fraction of the training rows held out for validation, e.g. to stop boosting early.
+const VALIDATION_SIZE: f32 = 0.1;
+
+/// Rows to train on, and rows to validate against while training, e.g. to stop boosting early.
+pub struct ValidationSplit<'a> {
+    pub x_train: &'a [f32],
+    pub y_train: &'a [f32],
+    pub num_train_rows: usize,
+    pub x_valid: &'a [f32],
+    pub y_valid: &'a [f32],
+    pub num_valid_rows: usize,
 }
 
 impl Display for Dataset {
@@ -27,25 +45,46 @@ impl Display for Dataset {
 
 impl Dataset {
     pub fn fold(&self, k: usize, folds: usize) -> Dataset {
-        if folds < 2 {
-            error!("It doesn't make sense to have k folds < 2. Use the dataset train/test split directly instead.");
-        }
-        let fold_test_size = self.num_train_rows / folds;
-        let test_start = k * fold_test_size;
+        // Folds are made on training threads, which can't raise errors in Postgres.
+        assert!(
+            folds >= 2,
+            "It doesn't make sense to have k folds < 2. Use the dataset train/test split directly instead."
+        );
+
+        // Stratified snapshots interleave the classes evenly, so contiguous folds
+        // keep the class proportions too. Time series are split into one more block
+        // than folds, and each fold only trains on the blocks before its test block,
+        // so the model never sees the future.
+        let (fold_test_size, test_start) = match self.sampling {
+            Sampling::time => {
+                let fold_test_size = self.num_train_rows / (folds + 1);
+                (fold_test_size, (k + 1) * fold_test_size)
+            }
+            _ => {
+                let fold_test_size = self.num_train_rows / folds;
+                (fold_test_size, k * fold_test_size)
+            }
+        };
         let test_end = test_start + fold_test_size;
-        let num_train_rows = self.num_train_rows - fold_test_size;
+        let train_end = match self.sampling {
+            Sampling::time => test_end,
+            _ => self.num_train_rows,
+        };
+        let num_train_rows = test_start + train_end - test_end;
 
         let x_test_start = test_start * self.num_features;
         let x_test_end = test_end * self.num_features;
+        let x_train_end = train_end * self.num_features;
         let y_test_start = test_start * self.num_labels;
         let y_test_end = test_end * self.num_labels;
+        let y_train_end = train_end * self.num_labels;
 
         let mut x_train = Vec::with_capacity(num_train_rows * self.num_features);
         x_train.extend_from_slice(&self.x_train[..x_test_start]);
-        x_train.extend_from_slice(&self.x_train[x_test_end..]);
+        x_train.extend_from_slice(&self.x_train[x_test_end..x_train_end]);
         let mut y_train = Vec::with_capacity(num_train_rows * self.num_labels);
         y_train.extend_from_slice(&self.y_train[..y_test_start]);
-        y_train.extend_from_slice(&self.y_train[y_test_end..]);
+        y_train.extend_from_slice(&self.y_train[y_test_end..y_train_end]);
 
         let x_test = self.x_train[x_test_start..x_test_end].to_vec();
         let y_test = self.y_train[y_test_start..y_test_end].to_vec();
@@ -61,6 +100,111 @@ impl Dataset {
             num_train_rows,
             num_test_rows: fold_test_size,
             num_distinct_labels: self.num_distinct_labels,
+            sampling: self.sampling,
+        }
+    }
+
+    /// A smaller dataset with only num_train_rows of the training rows, and all
+    /// the test rows. Time series keep their most recent rows, other snapshots
+    /// keep a random subset, in their original order, so stratified snapshots
+    /// stay interleaved.
+    pub fn subsample(&self, num_train_rows: usize) -> Dataset {
+        let num_train_rows = num_train_rows.min(self.num_train_rows);
+        let rows: Vec<usize> = match self.sampling {
+            Sampling::time => (self.num_train_rows - num_train_rows..self.num_train_rows).collect(),
+            _ => {
+                let mut rng = StdRng::seed_from_u64(0);
+                let mut rows =
+                    rand::seq::index::sample(&mut rng, self.num_train_rows, num_train_rows)
+                        .into_vec();
+                rows.sort_unstable();
+                rows
+            }
+        };
+
+        let mut x_train = Vec::with_capacity(num_train_rows * self.num_features);
+        let mut y_train = Vec::with_capacity(num_train_rows * self.num_labels);
+        for row in rows {
+            x_train.extend_from_slice(
+                &self.x_train[row * self.num_features..(row + 1) * self.num_features],
+            );
+            y_train.extend_from_slice(
+                &self.y_train[row * self.num_labels..(row + 1) * self.num_labels],
+            );
+        }
+
+        Dataset {
+            x_train,
+            y_train,
+            x_test: self.x_test.clone(),
+            y_test: self.y_test.clone(),
+            num_features: self.num_features,
+            num_labels: self.num_labels,
+            num_rows: num_train_rows + self.num_test_rows,
+            num_train_rows,
+            num_test_rows: self.num_test_rows,
+            num_distinct_labels: self.num_distinct_labels,
+            sampling: self.sampling,
+        }
+    }
+
+    /// Holds out the last of the training rows for validation, which are also the most
+    /// recent for time series. The test rows are never validated against, so they
+    /// stay unseen until the model is scored.
+    pub fn validation_split(&self) -> ValidationSplit<'_> {
+        let num_valid_rows = ((self.num_train_rows as f32 * VALIDATION_SIZE).ceil() as usize)
+            .clamp(1, self.num_train_rows.max(2) - 1);
+        let num_train_rows = self.num_train_rows - num_valid_rows;
+        let (x_train, x_valid) = self.x_train.split_at(num_train_rows * self.num_features);
+        let (y_train, y_valid) = self.y_train.split_at(num_train_rows * self.num_labels);
+
+        ValidationSplit {
+            x_train,
+            y_train,
+            num_train_rows,
+            x_valid,
+            y_valid,
+            num_valid_rows,
+        }
+    }
+
+    /// A view of the dataset with only the i-th label, used to fit
+    /// joint models one target at a time.
+    pub fn target(&self, i: usize) -> Dataset {
+        assert!(
+            i < self.num_labels,
+            "Target {} is out of bounds, the dataset only has {} labels.",
+            i,
+            self.num_labels
+        );
+
+        let y_train = self
+            .y_train
+            .iter()
+            .skip(i)
+            .step_by(self.num_labels)
+            .copied()
+            .collect();
+        let y_test = self
+            .y_test
+            .iter()
+            .skip(i)
+            .step_by(self.num_labels)
+            .copied()
+            .collect();
+
+        Dataset {
+            x_train: self.x_train.clone(),
+            y_train,
+            x_test: self.x_test.clone(),
+            y_test,
+            num_features: self.num_features,
+            num_labels: 1,
+            num_rows: self.num_rows,
+            num_train_rows: self.num_train_rows,
+            num_test_rows: self.num_test_rows,
+            num_distinct_labels: self.num_distinct_labels,
+            sampling: self.sampling,
         }
     }
 }
@@ -265,50 +409,72 @@ pub fn load_iris(limit: Option<usize>) -> (String, i64) {
 }
 
 // TODO add upstream into smartcore
-// pub fn load_linnerud(limit: Option<usize>) -> (String, i64) {
-//     let linnerud = smartcore::dataset::linnerud::load_dataset();
-//     Spi::run("DROP TABLE IF EXISTS pgml.linnerud");
-//     Spi::run("CREATE TABLE pgml.linnerud(
-//         chins FLOAT4,
-//         situps FLOAT4,
-//         jumps FLOAT4,
-//         weight FLOAT4,
-//         waste FLOAT4,
-//         pulse FLOAT4
-//     )");
-//     // TODO replace run_with_args with upstream when PR is accepted
-//     run_with_args(
-//         "COMMENT ON TABLE pgml.linnerud IS '{description}'",
-//         vec![(PgBuiltInOids::TEXTOID.oid(), linnerud.description.into_datum())],
-//     );
-
-//     info!("num_features: {}, num_samples: {}, feature_names: {:?}", linnerud.num_features, linnerud.num_samples, linnerud.feature_names);
-//     let limit = match limit {
-//         Some(limit) => if limit > linnerud.num_samples { linnerud.num_samples} else { limit },
-//         None => linnerud.num_samples,
-//     };
-//     for i in 0..limit {
-//         let chins = linnerud.data[(i * linnerud.num_features) + 0];
-//         let situps = linnerud.data[(i * linnerud.num_features) + 1];
-//         let jumps = linnerud.data[(i * linnerud.num_features) + 2];
-//         let weight = linnerud.target[(i * linnerud.num_labels) + 0];
-//         let waste = linnerud.target[(i * linnerud.num_labels) + 1];
-//         let pulse = linnerud.target[(i * linnerud.num_labels) + 2];
-//         run_with_args("
-//         INSERT INTO pgml.iris (chins, situps, jumps, weight, waste, pulse)
-//         VALUES ($1, $2, $3, $4, $5)
-//         ", vec![
-//             (PgBuiltInOids::FLOAT4OID.oid(), chins.into_datum()),
-//             (PgBuiltInOids::FLOAT4OID.oid(), situps.into_datum()),
-//             (PgBuiltInOids::FLOAT4OID.oid(), jumps.into_datum()),
-//             (PgBuiltInOids::FLOAT4OID.oid(), weight.into_datum()),
-//             (PgBuiltInOids::FLOAT4OID.oid(), waste.into_datum()),
-//             (PgBuiltInOids::FLOAT4OID.oid(), pulse.into_datum()),
-//         ]);
-//     }
-
-//     ("pgml.linnerud".to_string(), limit.try_into().unwrap())
-// }
+pub fn load_linnerud(limit: Option<usize>) -> (String, i64) {
+    // Chins, situps and jumps, followed by weight, waist and pulse.
+    let data: [[f32; 6]; 20] = [
+        [5., 162., 60., 191., 36., 50.],
+        [2., 110., 60., 189., 37., 52.],
+        [12., 101., 101., 193., 38., 58.],
+        [12., 105., 37., 162., 35., 62.],
+        [13., 155., 58., 189., 35., 46.],
+        [4., 101., 42., 182., 36., 56.],
+        [8., 101., 38., 211., 38., 56.],
+        [6., 125., 40., 167., 34., 60.],
+        [15., 200., 40., 176., 31., 74.],
+        [17., 251., 250., 154., 33., 56.],
+        [17., 120., 38., 169., 34., 50.],
+        [13., 210., 115., 166., 33., 52.],
+        [14., 215., 105., 154., 34., 64.],
+        [1., 50., 50., 247., 46., 50.],
+        [6., 70., 31., 193., 36., 46.],
+        [12., 210., 120., 202., 37., 62.],
+        [4., 60., 25., 176., 37., 54.],
+        [11., 230., 80., 157., 32., 52.],
+        [15., 225., 73., 156., 33., 54.],
+        [2., 110., 43., 138., 33., 68.],
+    ];
+    Spi::run("DROP TABLE IF EXISTS pgml.linnerud");
+    Spi::run(
+        "CREATE TABLE pgml.linnerud(
+        chins FLOAT4,
+        situps FLOAT4,
+        jumps FLOAT4,
+        weight FLOAT4,
+        waist FLOAT4,
+        pulse FLOAT4
+    )",
+    );
+    Spi::run("COMMENT ON TABLE pgml.linnerud IS 'Physical exercise (chins, situps, jumps) and physiological (weight, waist, pulse) measurements of 20 middle-aged men in a fitness club.'");
+
+    let limit = match limit {
+        Some(limit) => {
+            if limit > data.len() {
+                data.len()
+            } else {
+                limit
+            }
+        }
+        None => data.len(),
+    };
+    for row in data.iter().take(limit) {
+        run_with_args(
+            "
+        INSERT INTO pgml.linnerud (chins, situps, jumps, weight, waist, pulse)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+            vec![
+                (PgBuiltInOids::FLOAT4OID.oid(), row[0].into_datum()),
+                (PgBuiltInOids::FLOAT4OID.oid(), row[1].into_datum()),
+                (PgBuiltInOids::FLOAT4OID.oid(), row[2].into_datum()),
+                (PgBuiltInOids::FLOAT4OID.oid(), row[3].into_datum()),
+                (PgBuiltInOids::FLOAT4OID.oid(), row[4].into_datum()),
+                (PgBuiltInOids::FLOAT4OID.oid(), row[5].into_datum()),
+            ],
+        );
+    }
+
+    ("pgml.linnerud".to_string(), limit.try_into().unwrap())
+}
 
 pub fn load_breast_cancer(limit: Option<usize>) -> (String, i64) {
     let breast_cancer = smartcore::dataset::breast_cancer::load_dataset();
@@ -10,12 +10,78 @@ use crate::bindings::Bindings;
 
 use crate::orm::Algorithm;
 use crate::orm::Runtime;
+use crate::orm::Scaler;
+use crate::orm::Snapshot;
 use crate::orm::Task;
 
 #[allow(clippy::type_complexity)]
 static DEPLOYED_ESTIMATORS_BY_MODEL_ID: Lazy<Mutex<HashMap<i64, Arc<Box<dyn Bindings>>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+#[allow(clippy::type_complexity)]
+static DEPLOYED_SCALERS_BY_MODEL_ID: Lazy<Mutex<HashMap<i64, Option<Arc<Scaler>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[allow(clippy::type_complexity)]
+static DEPLOYED_BACKGROUNDS_BY_MODEL_ID: Lazy<Mutex<HashMap<i64, Arc<Vec<f32>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// How many training rows models that can't explain themselves are explained against.
+const BACKGROUND_ROWS: usize = 100;
+
+/// A sample of the scaled training rows of the given model, the background that SHAP values
+/// are estimated against when the model can't explain itself.
+pub fn find_deployed_background_by_model_id(model_id: i64) -> Arc<Vec<f32>> {
+    // Get the background from process memory, if we already sampled it.
+    {
+        let backgrounds = DEPLOYED_BACKGROUNDS_BY_MODEL_ID.lock();
+        if let Some(background) = backgrounds.get(&model_id) {
+            return background.clone();
+        }
+    }
+
+    let mut background = Snapshot::find_by_model_id(model_id)
+        .dataset()
+        .subsample(BACKGROUND_ROWS)
+        .x_train;
+    if background.is_empty() {
+        error!(
+            "Model {} has no training rows to explain its predictions against.",
+            model_id
+        );
+    }
+    if let Some(scaler) = find_deployed_scaler_by_model_id(model_id) {
+        scaler.transform(&mut background);
+    }
+    let background = Arc::new(background);
+
+    let mut backgrounds = DEPLOYED_BACKGROUNDS_BY_MODEL_ID.lock();
+    backgrounds.insert(model_id, background.clone());
+    background
+}
+
+/// Fetch and load the scaler for the given model, if it scales its features.
+pub fn find_deployed_scaler_by_model_id(model_id: i64) -> Option<Arc<Scaler>> {
+    // Get the scaler from process memory, if we already loaded it.
+    {
+        let scalers = DEPLOYED_SCALERS_BY_MODEL_ID.lock();
+        if let Some(scaler) = scalers.get(&model_id) {
+            return scaler.clone();
+        }
+    }
+
+    let data = Spi::get_one_with_args::<Vec<u8>>(
+        "SELECT data FROM pgml.files WHERE model_id = $1 AND path = 'scaler.rmp' LIMIT 1",
+        vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
+    );
+    let scaler = data.map(|data| Arc::new(Scaler::from_bytes(&data)));
+
+    // Cache the scaler in process memory, models without one are cached too.
+    let mut scalers = DEPLOYED_SCALERS_BY_MODEL_ID.lock();
+    scalers.insert(model_id, scaler.clone());
+    scaler
+}
+
 /// Fetch and load the most up-to-date estimator for the given model.
 pub fn find_deployed_estimator_by_model_id(model_id: i64) -> Arc<Box<dyn Bindings>> {
     // Get the estimator from process memory, if we already loaded it.
@@ -30,6 +96,7 @@ pub fn find_deployed_estimator_by_model_id(model_id: i64) -> Arc<Box<dyn Binding
     let mut runtime: Option<String> = None;
     let mut algorithm: Option<String> = None;
     let mut task: Option<String> = None;
+    let mut num_labels: Option<i32> = None;
 
     Spi::connect(|client| {
         let result = client
@@ -38,12 +105,16 @@ pub fn find_deployed_estimator_by_model_id(model_id: i64) -> Arc<Box<dyn Binding
                     data,
                     runtime::TEXT,
                     algorithm::TEXT,
-                    task::TEXT
+                    task::TEXT,
+                    COALESCE(array_length(snapshots.y_column_name, 1), 0)
                 FROM pgml.models
                     INNER JOIN pgml.files
                         ON models.id = files.model_id 
+                        AND files.path = 'estimator.rmp'
                     INNER JOIN pgml.projects
                         ON models.project_id = projects.id
+                    INNER JOIN pgml.snapshots
+                        ON models.snapshot_id = snapshots.id
                     WHERE models.id = $1
                     LIMIT 1
                 ",
@@ -74,6 +145,11 @@ pub fn find_deployed_estimator_by_model_id(model_id: i64) -> Arc<Box<dyn Binding
                     .expect("Algorithm for model is corrupted."),
             );
             task = Some(result.get_datum(4).expect("Task for project is corrupted."));
+            num_labels = Some(
+                result
+                    .get_datum(5)
+                    .expect("Snapshot for model is corrupted."),
+            );
         }
 
         Ok(Some(1))
@@ -83,6 +159,7 @@ pub fn find_deployed_estimator_by_model_id(model_id: i64) -> Arc<Box<dyn Binding
         "SELECT data, runtime::TEXT, algorithm::TEXT FROM pgml.models
         INNER JOIN pgml.files
             ON models.id = files.model_id 
+            AND files.path = 'estimator.rmp'
         WHERE models.id = $1
         LIMIT 1",
         vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())],
@@ -92,29 +169,66 @@ pub fn find_deployed_estimator_by_model_id(model_id: i64) -> Arc<Box<dyn Binding
     let runtime = Runtime::from_str(&runtime.unwrap()).unwrap();
     let algorithm = Algorithm::from_str(&algorithm.unwrap()).unwrap();
     let task = Task::from_str(&task.unwrap()).unwrap();
+    let num_labels = num_labels.unwrap();
 
     debug1!(
-        "runtime = {:?}, algorithm = {:?}, task = {:?}",
+        "runtime = {:?}, algorithm = {:?}, task = {:?}, num_labels = {:?}",
         runtime,
         algorithm,
-        task
+        task,
+        num_labels
     );
 
     let bindings: Box<dyn Bindings> = match runtime {
-        Runtime::rust => {
-            match algorithm {
-                Algorithm::xgboost => crate::bindings::xgboost::Estimator::from_bytes(&data),
-                Algorithm::lightgbm => crate::bindings::lightgbm::Estimator::from_bytes(&data),
-                Algorithm::linear => match task {
-                    Task::regression => crate::bindings::linfa::LinearRegression::from_bytes(&data),
-                    Task::classification => {
-                        crate::bindings::linfa::LogisticRegression::from_bytes(&data)
-                    }
-                },
-                Algorithm::svm => crate::bindings::linfa::Svm::from_bytes(&data),
-                _ => todo!(), //smartcore_load(&data, task, algorithm, &hyperparams),
-            }
-        }
+        Runtime::rust if num_labels > 1 => match algorithm {
+            Algorithm::xgboost => crate::bindings::joint::Estimator::<
+                crate::bindings::xgboost::Estimator,
+            >::from_bytes(&data),
+            Algorithm::linear => crate::bindings::joint::Estimator::<
+                crate::bindings::linfa::LinearRegression,
+            >::from_bytes(&data),
+            _ => error!(
+                "{:?} does not support joint regression with the rust runtime",
+                algorithm
+            ),
+        },
+
+        Runtime::rust => match algorithm {
+            Algorithm::xgboost => crate::bindings::xgboost::Estimator::from_bytes(&data),
+            Algorithm::lightgbm => crate::bindings::lightgbm::Estimator::from_bytes(&data),
+            Algorithm::linear => match task {
+                Task::regression => crate::bindings::linfa::LinearRegression::from_bytes(&data),
+                Task::classification => {
+                    crate::bindings::linfa::LogisticRegression::from_bytes(&data)
+                }
+                Task::clustering => error!("Linear models do not support clustering"),
+            },
+            Algorithm::svm => crate::bindings::linfa::Svm::from_bytes(&data),
+            Algorithm::knn => match task {
+                Task::regression => crate::bindings::smartcore::KNNRegressor::from_bytes(&data),
+                Task::classification => {
+                    crate::bindings::smartcore::KNNClassifier::from_bytes(&data)
+                }
+                Task::clustering => error!("KNN does not support clustering"),
+            },
+            Algorithm::random_forest => match task {
+                Task::regression => {
+                    crate::bindings::smartcore::RandomForestRegressor::from_bytes(&data)
+                }
+                Task::classification => {
+                    crate::bindings::smartcore::RandomForestClassifier::from_bytes(&data)
+                }
+                Task::clustering => error!("Random forests do not support clustering"),
+            },
+            Algorithm::kmeans => crate::bindings::linfa::KMeans::from_bytes(&data),
+            Algorithm::lasso => crate::bindings::smartcore::Lasso::from_bytes(&data),
+            Algorithm::ridge => crate::bindings::smartcore::RidgeRegression::from_bytes(&data),
+            Algorithm::elastic_net => crate::bindings::smartcore::ElasticNet::from_bytes(&data),
+            _ => error!(
+                "{:?} is not supported by the rust runtime, the model store has been corrupted.",
+                algorithm
+            ),
+        },
 
         #[cfg(feature = "python")]
         Runtime::python => crate::bindings::sklearn::Estimator::from_bytes(&data),
@@ -3,8 +3,10 @@ pub mod dataset;
 pub mod file;
 pub mod model;
 pub mod project;
+pub mod retrain_policy;
 pub mod runtime;
 pub mod sampling;
+pub mod scaler;
 pub mod search;
 pub mod snapshot;
 pub mod status;
@@ -15,9 +17,11 @@ pub use algorithm::Algorithm;
 pub use dataset::Dataset;
 pub use model::Model;
 pub use project::Project;
+pub use retrain_policy::RetrainPolicy;
 pub use runtime::Runtime;
 pub use sampling::Sampling;
-pub use search::Search;
+pub use scaler::{Scale, Scaler};
+pub use search::{Distribution, Search};
 pub use snapshot::Snapshot;
 pub use status::Status;
 pub use strategy::Strategy;
@@ -1,12 +1,16 @@
 use std::fmt::{Display, Error, Formatter};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::time::Instant;
 
-use ::linfa::prelude::{BinaryClassification, Pr, SingleTargetRegression, ToConfusionMatrix};
+use ::linfa::prelude::{BinaryClassification, Pr, SingleTargetRegression};
 use indexmap::IndexMap;
 use itertools::{izip, Itertools};
-use ndarray::ArrayView1;
+use ndarray::{Array1, ArrayView1, ArrayView2};
 use pgx::*;
-use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use serde_json::json;
 
 use crate::bindings::*;
@@ -40,6 +44,89 @@ impl Display for Model {
     }
 }
 
+/// The fewest training rows a round of successive halving will use.
+const MIN_HALVING_ROWS: usize = 100;
+
+/// One set of hyperparams, with the metrics and estimator of each fold it was scored on.
+struct Trial {
+    hyperparams: Hyperparams,
+    metrics: Vec<IndexMap<String, f32>>,
+    estimators: Vec<Box<dyn Bindings>>,
+    /// The successive halving round, always 0 for other searches.
+    iter: usize,
+    /// The number of training rows.
+    n_resources: usize,
+}
+
+impl Trial {
+    /// The mean of the project's target metric over all folds, negated
+    /// when lower is better, so a higher score is always better.
+    fn score(&self, project: &Project) -> f32 {
+        let mean = self
+            .metrics
+            .iter()
+            .map(|metrics| *metrics.get(project.target_metric()).unwrap())
+            .sum::<f32>()
+            / self.metrics.len() as f32;
+        if project.greater_is_better() {
+            mean
+        } else {
+            -mean
+        }
+    }
+}
+
+/// Options for the hyperparameter search, from `search_args`.
+struct SearchArgs {
+    /// Sets of hyperparams sampled by random, bayesian and halving searches.
+    n_iter: usize,
+    /// Cross validation folds.
+    cv: usize,
+    /// Random trials before a bayesian search starts to learn from them.
+    n_initial_points: usize,
+    /// How many times fewer candidates each successive halving round keeps.
+    factor: usize,
+    /// Fits run at the same time, for Rust runtimes.
+    n_jobs: usize,
+}
+
+impl SearchArgs {
+    fn new(search: Option<Search>, search_args: &JsonB) -> SearchArgs {
+        let mut args = SearchArgs {
+            n_iter: 10,
+            cv: if search.is_some() { 5 } else { 1 },
+            n_initial_points: 5,
+            factor: 3,
+            n_jobs: 1,
+        };
+        for (key, value) in search_args.0.as_object().unwrap() {
+            match key.as_str() {
+                "n_iter" => args.n_iter = value.as_i64().unwrap().try_into().unwrap(),
+                "cv" => args.cv = value.as_i64().unwrap().try_into().unwrap(),
+                "n_initial_points" => {
+                    args.n_initial_points = value.as_i64().unwrap().try_into().unwrap()
+                }
+                "factor" => args.factor = value.as_i64().unwrap().try_into().unwrap(),
+                // -1 uses every available core, like sklearn.
+                "n_jobs" => {
+                    args.n_jobs = match value.as_i64() {
+                        Some(-1) => std::thread::available_parallelism()
+                            .map(|n| n.get())
+                            .unwrap_or(1),
+                        Some(n_jobs) if n_jobs > 0 => n_jobs as usize,
+                        _ => error!(
+                            "search_args n_jobs must be a positive number or -1, got: {:?}",
+                            value
+                        ),
+                    }
+                }
+                _ => error!("Unknown search_args => {:?}: {:?}", key, value),
+            }
+        }
+        args
+    }
+}
+
 impl Model {
     #[allow(clippy::too_many_arguments)]
     pub fn create(
@@ -62,13 +149,23 @@ impl Model {
                 Algorithm::lightgbm => Runtime::rust,
                 Algorithm::linear => match project.task {
                     Task::classification => Runtime::python,
-                    Task::regression => Runtime::rust,
+                    _ => Runtime::rust,
                 },
                 _ => Runtime::python,
             },
         };
 
-        let dataset = snapshot.dataset();
+        let mut dataset = snapshot.dataset();
+        let scaler = Scaler::fit(
+            &snapshot.feature_scales(),
+            &snapshot.feature_names(),
+            &dataset,
+        );
+        if let Some(scaler) = &scaler {
+            scaler.transform(&mut dataset.x_train);
+            scaler.transform(&mut dataset.x_test);
+        }
+
         let status = Status::in_progress;
         // Create the model record.
         Spi::connect(|client| {
@@ -117,6 +214,18 @@ impl Model {
 
         model.fit(project, &dataset);
 
+        // Save the scaler next to the estimator, so predictions are scaled the same way.
+        if let Some(scaler) = scaler {
+            Spi::get_one_with_args::<i64>(
+                "INSERT INTO pgml.files (model_id, path, part, data) VALUES($1, 'scaler.rmp', 0, $2) RETURNING id",
+                vec![
+                    (PgBuiltInOids::INT8OID.oid(), model.id.into_datum()),
+                    (PgBuiltInOids::BYTEAOID.oid(), scaler.to_bytes().into_datum()),
+                ],
+            )
+            .unwrap();
+        }
+
         Spi::connect(|client| {
             client.select(
                 "UPDATE pgml.models SET status = $1::pgml.status WHERE id = $2",
@@ -136,22 +245,50 @@ impl Model {
         model
     }
 
-    fn get_fit_function(&self, project: &Project) -> crate::bindings::Fit {
+    fn get_fit_function(&self, project: &Project, dataset: &Dataset) -> crate::bindings::Fit {
         match self.runtime {
             Runtime::rust => match project.task {
+                Task::regression if dataset.num_labels > 1 => match self.algorithm {
+                    Algorithm::xgboost => xgboost::fit_regression_joint,
+                    Algorithm::linear => linfa::LinearRegression::fit_joint,
+                    _ => error!(
+                        "{:?} does not support joint regression with the rust runtime, use the python runtime instead",
+                        self.algorithm
+                    ),
+                },
                 Task::regression => match self.algorithm {
                     Algorithm::xgboost => xgboost::fit_regression,
                     Algorithm::lightgbm => lightgbm::fit_regression,
                     Algorithm::linear => linfa::LinearRegression::fit,
                     Algorithm::svm => linfa::Svm::fit,
-                    _ => todo!(),
+                    Algorithm::knn => smartcore::KNNRegressor::fit,
+                    Algorithm::random_forest => smartcore::RandomForestRegressor::fit,
+                    Algorithm::lasso => smartcore::Lasso::fit,
+                    Algorithm::ridge => smartcore::RidgeRegression::fit,
+                    Algorithm::elastic_net => smartcore::ElasticNet::fit,
+                    _ => error!(
+                        "{:?} does not support regression with the rust runtime, use the python runtime instead",
+                        self.algorithm
+                    ),
                 },
                 Task::classification => match self.algorithm {
                     Algorithm::xgboost => xgboost::fit_classification,
                     Algorithm::lightgbm => lightgbm::fit_classification,
                     Algorithm::linear => linfa::LogisticRegression::fit,
                     Algorithm::svm => linfa::Svm::fit,
-                    _ => todo!(),
+                    Algorithm::knn => smartcore::KNNClassifier::fit,
+                    Algorithm::random_forest => smartcore::RandomForestClassifier::fit,
+                    _ => error!(
+                        "{:?} does not support classification with the rust runtime, use the python runtime instead",
+                        self.algorithm
+                    ),
+                },
+                Task::clustering => match self.algorithm {
+                    Algorithm::kmeans => linfa::KMeans::fit,
+                    _ => error!(
+                        "{:?} does not support clustering with the rust runtime, use the python runtime instead",
+                        self.algorithm
+                    ),
                 },
             },
 
@@ -231,65 +368,369 @@ impl Model {
                     Algorithm::lightgbm => sklearn::lightgbm_classification,
                     _ => panic!("{:?} does not support classification", self.algorithm),
                 },
+                Task::clustering => match self.algorithm {
+                    Algorithm::kmeans => sklearn::kmeans_clustering,
+                    Algorithm::dbscan => sklearn::dbscan_clustering,
+                    _ => panic!("{:?} does not support clustering", self.algorithm),
+                },
             },
         }
     }
 
+    /// The distribution of each hyperparam in self.search_params.
+    fn search_space(&self) -> Vec<(String, Distribution)> {
+        let mut distributions = Vec::new();
+        for (key, value) in self.search_params.0.as_object().unwrap() {
+            if self.hyperparams.0.as_object().unwrap().contains_key(key) {
+                error!("`{key}` cannot be present in both hyperparams and search_params. Please choose one or the other.");
+            }
+            distributions.push((key.to_string(), Distribution::parse(key, value)));
+        }
+        distributions
+    }
+
+    /// Combines searched values with the fixed self.hyperparams.
+    fn with_defaults(&self, values: Vec<(String, serde_json::Value)>) -> Hyperparams {
+        let mut hyperparams = self.hyperparams.0.as_object().unwrap().clone();
+        hyperparams.extend(values);
+        hyperparams
+    }
+
     /// Generates a complete list of hyperparams that should be tested
     /// by combining the self.search_params. When search params are empty,
-    /// the set only contains the self.hyperparams.
+    /// the set only contains the self.hyperparams. Random searches sample
+    /// n_iter sets independently, so distributions never have to be enumerated.
     fn get_all_hyperparams(&self, n_iter: usize) -> Vec<Hyperparams> {
-        // Gather all hyperparams
-        let mut all_hyperparam_names = Vec::new();
-        let mut all_hyperparam_values = Vec::new();
-        for (key, value) in self.hyperparams.0.as_object().unwrap() {
-            all_hyperparam_names.push(key.to_string());
-            all_hyperparam_values.push(vec![value.clone()]);
+        let distributions = self.search_space();
+        if distributions.is_empty() {
+            // Empty set for a run of only the default values
+            return vec![self.with_defaults(Vec::new())];
         }
-        for (key, values) in self.search_params.0.as_object().unwrap() {
-            if all_hyperparam_names.contains(key) {
-                error!("`{key}` cannot be present in both hyperparams and search_params. Please choose one or the other.");
-            }
-            all_hyperparam_names.push(key.to_string());
-            all_hyperparam_values.push(values.as_array().unwrap().to_vec());
+
+        // Lists of values alone can be enumerated as a grid.
+        let grid_size = distributions
+            .iter()
+            .try_fold(1_usize, |size, (_, d)| match d {
+                Distribution::Choice(values) => size.checked_mul(values.len()),
+                _ => None,
+            });
+
+        let random = match self.search {
+            Some(Search::random) => true,
+            Some(Search::halving) => grid_size.is_none(),
+            _ => false,
+        };
+
+        if !random {
+            // The search space is all possible combinations
+            return distributions
+                .iter()
+                .map(|(name, distribution)| {
+                    distribution
+                        .choices(name)
+                        .into_iter()
+                        .map(|value| (name.clone(), value))
+                        .collect::<Vec<_>>()
+                })
+                .multi_cartesian_product()
+                .map(|values| self.with_defaults(values))
+                .collect();
         }
 
-        // The search space is all possible combinations
-        let all_hyperparam_values: Vec<Vec<serde_json::Value>> = all_hyperparam_values
-            .into_iter()
-            .multi_cartesian_product()
+        let mut rng = rand::thread_rng();
+
+        // Lists of values alone are sampled without replacement, like a partial grid.
+        if let Some(grid_size) = grid_size {
+            return rand::seq::index::sample(&mut rng, grid_size, n_iter.min(grid_size))
+                .into_iter()
+                .map(|mut index| {
+                    let mut values = Vec::with_capacity(distributions.len());
+                    for (name, distribution) in distributions.iter().rev() {
+                        let choices = distribution.choices(name);
+                        values.push((name.clone(), choices[index % choices.len()].clone()));
+                        index /= choices.len();
+                    }
+                    values.reverse();
+                    self.with_defaults(values)
+                })
+                .collect();
+        }
+
+        (0..n_iter)
+            .map(|_| {
+                self.with_defaults(
+                    distributions
+                        .iter()
+                        .map(|(name, distribution)| (name.clone(), distribution.sample(&mut rng)))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Fits and scores every set of hyperparams on each cross validation fold,
+    /// or on the dataset directly when there are 0 or 1 folds. Rust runtimes
+    /// run up to n_jobs fits at a time on threads.
+    fn trials(
+        &self,
+        project: &Project,
+        dataset: &Dataset,
+        fit: Fit,
+        all_hyperparams: Vec<Hyperparams>,
+        args: &SearchArgs,
+    ) -> Vec<Trial> {
+        let cv = args.cv;
+        let folds = cv.max(1);
+        let jobs: Vec<(usize, usize)> = (0..all_hyperparams.len())
+            .flat_map(|i| (0..folds).map(move |k| (i, k)))
             .collect();
-        let mut all_hyperparam_values = match self.search {
-            Some(Search::random) => {
-                // TODO support things like ranges to be random sampled
-                let mut rng = &mut rand::thread_rng();
-                all_hyperparam_values
-                    .choose_multiple(&mut rng, n_iter)
-                    .cloned()
-                    .collect()
-            }
-            _ => all_hyperparam_values,
+
+        // Python holds the GIL for the whole fit, so threads wouldn't help.
+        let n_jobs = match self.runtime {
+            Runtime::rust => args.n_jobs.min(jobs.len()),
+            Runtime::python => 1,
         };
 
-        // Empty set for a run of only the default values
-        if all_hyperparam_values.is_empty() {
-            all_hyperparam_values.push(Vec::new());
+        // Nothing in here may call into Postgres, since it runs on other threads.
+        let run =
+            |(i, k): (usize, usize)| -> anyhow::Result<(IndexMap<String, f32>, Box<dyn Bindings>)> {
+                let fold;
+                let dataset = if cv < 2 {
+                    dataset
+                } else {
+                    fold = dataset.fold(k, cv);
+                    &fold
+                };
+
+                let now = Instant::now();
+                let estimator = fit(dataset, &all_hyperparams[i])?;
+                let fit_time = now.elapsed();
+
+                let now = Instant::now();
+                let mut metrics = self.test(project, dataset, &estimator);
+                let score_time = now.elapsed();
+
+                metrics.insert("fit_time".to_string(), fit_time.as_secs_f32());
+                metrics.insert("score_time".to_string(), score_time.as_secs_f32());
+
+                Ok((metrics, estimator))
+            };
+        let failed =
+            |message: String| -> ! { error!("Training {:?} failed: {}", self.algorithm, message) };
+
+        let mut results: Vec<Option<(IndexMap<String, f32>, Box<dyn Bindings>)>> =
+            jobs.iter().map(|_| None).collect();
+        let mut report = |job: usize, result: (IndexMap<String, f32>, Box<dyn Bindings>)| {
+            let (i, k) = jobs[job];
+            info!(
+                "k = {}, hyperparams: {}, metrics: {}",
+                k,
+                serde_json::to_string_pretty(&all_hyperparams[i]).unwrap(),
+                serde_json::to_string_pretty(&result.0).unwrap()
+            );
+            results[job] = Some(result);
+        };
+
+        if n_jobs < 2 {
+            for (job, &(i, k)) in jobs.iter().enumerate() {
+                info!(
+                    "k = {}, hyperparams: {}",
+                    k,
+                    serde_json::to_string_pretty(&all_hyperparams[i]).unwrap()
+                );
+                match run((i, k)) {
+                    Ok(result) => report(job, result),
+                    Err(e) => failed(e.to_string()),
+                }
+            }
+        } else {
+            info!("Running {} fits on {} threads", jobs.len(), n_jobs);
+            let next = AtomicUsize::new(0);
+            let (sender, receiver) = mpsc::channel();
+            let failure = std::thread::scope(|scope| {
+                let (next, jobs, run) = (&next, &jobs, &run);
+                for _ in 0..n_jobs {
+                    let sender = sender.clone();
+                    scope.spawn(move || loop {
+                        let job = next.fetch_add(1, Ordering::Relaxed);
+                        if job >= jobs.len() {
+                            break;
+                        }
+                        // Errors and panics are both sent back as messages, for this thread to raise.
+                        let result =
+                            match std::panic::catch_unwind(AssertUnwindSafe(|| run(jobs[job]))) {
+                                Ok(result) => result.map_err(|e| e.to_string()),
+                                Err(payload) => Err(payload
+                                    .downcast_ref::<&str>()
+                                    .map(|message| message.to_string())
+                                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                                    .unwrap_or_default()),
+                            };
+                        if sender.send((job, result)).is_err() {
+                            break;
+                        }
+                    });
+                }
+                drop(sender);
+
+                // Only this thread may talk to Postgres, so it logs the results as they
+                // come in, and holds on to any failure until the other threads are done.
+                let mut failure = None;
+                for (job, result) in receiver {
+                    match result {
+                        Ok(result) => report(job, result),
+                        Err(message) => {
+                            next.store(jobs.len(), Ordering::Relaxed);
+                            failure.get_or_insert(message);
+                        }
+                    }
+                }
+                failure
+            });
+
+            if let Some(message) = failure {
+                failed(message);
+            }
         }
 
-        // Construct sets of hyperparams from the values
-        all_hyperparam_values
-            .iter()
-            .map(|hyperparam_values| {
-                let mut hyperparams = Hyperparams::new();
-                for (idx, value) in hyperparam_values.iter().enumerate() {
-                    let name = all_hyperparam_names[idx].clone();
-                    hyperparams.insert(name, value.clone());
+        let mut results = results.into_iter().map(|result| result.unwrap());
+        all_hyperparams
+            .into_iter()
+            .map(|hyperparams| {
+                let (metrics, estimators) = results.by_ref().take(folds).unzip();
+                let trial = Trial {
+                    hyperparams,
+                    metrics,
+                    estimators,
+                    iter: 0,
+                    n_resources: dataset.num_train_rows,
+                };
+                if !trial.metrics[0].contains_key(project.target_metric()) {
+                    error!(
+                        "{:?} models don't report `{}`, choose another metric for project `{}`",
+                        self.algorithm,
+                        project.target_metric(),
+                        project.name
+                    );
                 }
-                hyperparams
+                trial
             })
             .collect()
     }
 
+    /// Samples n_initial_points at random, then picks each following set of
+    /// hyperparams with a Parzen estimator of the ones that scored best so far.
+    fn bayesian_search(
+        &self,
+        project: &Project,
+        dataset: &Dataset,
+        fit: Fit,
+        args: &SearchArgs,
+    ) -> Vec<Trial> {
+        let distributions = self.search_space();
+        if distributions.is_empty() {
+            error!("search => 'bayesian' needs search_params to search");
+        }
+
+        info!(
+            "Hyperparameter searches: {}, cross validation folds: {}",
+            args.n_iter, args.cv
+        );
+
+        let mut rng = rand::thread_rng();
+        let mut trials = Vec::with_capacity(args.n_iter);
+        let mut history = Vec::with_capacity(args.n_iter);
+        for i in 0..args.n_iter {
+            let values: Vec<serde_json::Value> = if i < args.n_initial_points {
+                distributions
+                    .iter()
+                    .map(|(_, distribution)| distribution.sample(&mut rng))
+                    .collect()
+            } else {
+                search::suggest(&distributions, &history, &mut rng)
+            };
+            let hyperparams = self.with_defaults(
+                distributions
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .zip(values.iter().cloned())
+                    .collect(),
+            );
+
+            let trial = self
+                .trials(project, dataset, fit, vec![hyperparams], args)
+                .pop()
+                .unwrap();
+            history.push((values, trial.score(project)));
+            trials.push(trial);
+        }
+
+        trials
+    }
+
+    /// Successive halving: every candidate is trained on a small sample of the
+    /// training rows, and only the best 1 / factor of them move on to the next
+    /// round with factor times more rows, until the last round uses them all.
+    fn halving_search(
+        &self,
+        project: &Project,
+        dataset: &Dataset,
+        fit: Fit,
+        args: &SearchArgs,
+    ) -> Vec<Trial> {
+        let factor = args.factor;
+        if factor < 2 {
+            error!("search_args factor must be at least 2, got: {factor}");
+        }
+
+        let mut candidates = self.get_all_hyperparams(args.n_iter);
+        let mut n_rounds = 1;
+        let mut n_candidates = candidates.len();
+        while n_candidates > 1 {
+            n_candidates = (n_candidates + factor - 1) / factor;
+            n_rounds += 1;
+        }
+
+        info!(
+            "Hyperparameter searches: {}, successive halving rounds: {}, cross validation folds: {}",
+            candidates.len(),
+            n_rounds,
+            args.cv
+        );
+
+        let mut trials = Vec::new();
+        for iter in 0..n_rounds {
+            let n_resources = (dataset.num_train_rows / factor.pow((n_rounds - iter - 1) as u32))
+                .max(MIN_HALVING_ROWS)
+                .min(dataset.num_train_rows);
+            let sample = dataset.subsample(n_resources);
+            info!(
+                "Successive halving round {}: {} candidates, {} rows",
+                iter,
+                candidates.len(),
+                n_resources
+            );
+
+            let mut scores = Vec::with_capacity(candidates.len());
+            for mut trial in self.trials(project, &sample, fit, candidates, args) {
+                trial.iter = iter;
+                scores.push((trial.score(project), trials.len()));
+                trials.push(trial);
+            }
+
+            // The best candidates move on to the next round.
+            scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let n_survivors = (scores.len() + factor - 1) / factor;
+            candidates = scores
+                .iter()
+                .take(n_survivors)
+                .map(|(_, i)| trials[*i].hyperparams.clone())
+                .collect();
+        }
+
+        trials
+    }
+
     fn test(
         &self,
         project: &Project,
@@ -304,48 +745,57 @@ impl Model {
         let mut metrics = IndexMap::new();
         match project.task {
             Task::regression => {
-                let y_test = ArrayView1::from(&y_test);
-                let y_hat = ArrayView1::from(&y_hat);
-
-                metrics.insert("r2".to_string(), y_hat.r2(&y_test).unwrap());
-                metrics.insert(
-                    "mean_absolute_error".to_string(),
-                    y_hat.mean_absolute_error(&y_test).unwrap(),
-                );
-                metrics.insert(
-                    "mean_squared_error".to_string(),
-                    y_hat.mean_squared_error(&y_test).unwrap(),
-                );
+                metrics.extend(regression_metrics(&y_hat, y_test, dataset.num_labels));
             }
             Task::classification => {
-                if dataset.num_distinct_labels == 2 {
-                    let y_hat = ArrayView1::from(&y_hat).mapv(Pr::new);
-                    let y_test: Vec<bool> = y_test.iter().map(|&i| i == 1.).collect();
-                    metrics.insert(
-                        "roc_auc".to_string(),
-                        y_hat.roc(&y_test).unwrap().area_under_curve(),
-                    );
-                    metrics.insert("log_loss".to_string(), y_hat.log_loss(&y_test).unwrap());
+                // Ranking metrics need probabilities, hard labels would make them meaningless.
+                let y_proba = estimator.predict_proba(&dataset.x_test).filter(|y_proba| {
+                    y_proba.len() == dataset.num_test_rows * dataset.num_distinct_labels
+                });
+                if let Some(y_proba) = y_proba {
+                    let y_proba = ArrayView2::from_shape(
+                        (dataset.num_test_rows, dataset.num_distinct_labels),
+                        &y_proba,
+                    )
+                    .unwrap();
+
+                    if dataset.num_distinct_labels == 2 {
+                        let y_score = y_proba.column(1).mapv(|i| Pr::new(i.clamp(0., 1.)));
+                        let y_test: Vec<bool> = y_test.iter().map(|&i| i == 1.).collect();
+                        metrics.insert(
+                            "roc_auc".to_string(),
+                            y_score.roc(&y_test).unwrap().area_under_curve(),
+                        );
+                    }
+                    metrics.insert("log_loss".to_string(), log_loss(y_proba, y_test));
                 }
 
-                let y_hat: Vec<usize> = y_hat.into_iter().map(|i| i.round() as usize).collect();
-                let y_test: Vec<usize> = y_test.iter().map(|i| i.round() as usize).collect();
-                let y_hat = ArrayView1::from(&y_hat);
-                let y_test = ArrayView1::from(&y_test);
-                let confusion_matrix = y_hat.confusion_matrix(y_test).unwrap();
-                metrics.insert("f1".to_string(), confusion_matrix.f1_score());
-                metrics.insert("precision".to_string(), confusion_matrix.precision());
-                metrics.insert("recall".to_string(), confusion_matrix.recall());
-                metrics.insert("accuracy".to_string(), confusion_matrix.accuracy());
-                metrics.insert("mcc".to_string(), confusion_matrix.mcc());
+                let confusion_matrix =
+                    confusion_matrix(&y_hat, y_test, dataset.num_distinct_labels);
+                metrics.extend(classification_metrics(&confusion_matrix));
+            }
+            Task::clustering => {
+                let x_test = ArrayView2::from_shape(
+                    (dataset.num_test_rows, dataset.num_features),
+                    &dataset.x_test,
+                )
+                .unwrap();
+                let y_hat: Vec<i64> = y_hat.iter().map(|i| i.round() as i64).collect();
+
+                metrics.insert("silhouette".to_string(), silhouette_score(x_test, &y_hat));
+                metrics.insert("inertia".to_string(), inertia(x_test, &y_hat));
             }
         }
 
+        if let Some(best_iteration) = estimator.best_iteration() {
+            metrics.insert("best_iteration".to_string(), best_iteration as f32);
+        }
+
         metrics
     }
 
     fn fit(&mut self, project: &Project, dataset: &Dataset) {
-        let fit = self.get_fit_function(project);
+        let fit = self.get_fit_function(project, dataset);
 
         // Sometimes our algorithms take a long time. The only way to stop code
         // that we don't have control over is using a signal handler. Signal handlers
@@ -362,142 +812,94 @@ impl Model {
         }
         .unwrap();
 
-        let mut n_iter: usize = 10;
-        let mut cv: usize = if self.search.is_some() { 5 } else { 1 };
-        for (key, value) in self.search_args.0.as_object().unwrap() {
-            match key.as_str() {
-                "n_iter" => n_iter = value.as_i64().unwrap().try_into().unwrap(),
-                "cv" => cv = value.as_i64().unwrap().try_into().unwrap(),
-                _ => error!("Unknown search_args => {:?}: {:?}", key, value),
-            }
-        }
-
-        let all_hyperparams = self.get_all_hyperparams(n_iter);
-        let mut all_estimators = Vec::with_capacity(all_hyperparams.len());
-        let mut all_metrics = Vec::with_capacity(all_hyperparams.len());
+        let args = SearchArgs::new(self.search, &self.search_args);
+        let cv = args.cv;
 
-        info!(
-            "Hyperparameter searches: {}, cross validation folds: {}",
-            all_hyperparams.len(),
-            cv
-        );
+        // Find the best estimator, hyperparams and metrics
+        let target_metric = project.target_metric();
+        let greater_is_better = project.greater_is_better();
 
-        // Train and score all the st
-        if cv < 2 {
-            // It would be nice if this could be combined with the
-            // cv inline version invariant to the dataset/fold, but
-            // extracting this to a function would involve passing
-            // most of the loc as params, which doesn't actually
-            // simplify things much.
-            for hyperparams in &all_hyperparams {
-                // When there are 0 or 1 folds, we use the dataset directly
+        // Train and score all the trials
+        let trials = match self.search {
+            Some(Search::bayesian) => self.bayesian_search(project, dataset, fit, &args),
+            Some(Search::halving) => self.halving_search(project, dataset, fit, &args),
+            _ => {
+                let all_hyperparams = self.get_all_hyperparams(args.n_iter);
                 info!(
-                    "Hyperparams: {}",
-                    serde_json::to_string_pretty(&hyperparams).unwrap()
+                    "Hyperparameter searches: {}, cross validation folds: {}",
+                    all_hyperparams.len(),
+                    cv
                 );
-
-                let now = Instant::now();
-
-                let estimator = fit(dataset, hyperparams);
-
-                let fit_time = now.elapsed();
-
-                let now = Instant::now();
-                let mut metrics = self.test(project, dataset, &estimator);
-                let score_time = now.elapsed();
-
-                metrics.insert("fit_time".to_string(), fit_time.as_secs_f32());
-                metrics.insert("score_time".to_string(), score_time.as_secs_f32());
-
-                info!(
-                    "Metrics: {}",
-                    serde_json::to_string_pretty(&metrics).unwrap()
-                );
-
-                all_metrics.push(metrics);
-                all_estimators.push(estimator);
+                self.trials(project, dataset, fit, all_hyperparams, &args)
             }
-        } else {
-            for k in 0..cv {
-                // With 2 or more folds, generated for cross validation
-                let fold = dataset.fold(k, cv);
-                for hyperparams in &all_hyperparams {
-                    info!(
-                        "k = {}, hyperparameters: {}",
-                        k,
-                        serde_json::to_string_pretty(&hyperparams).unwrap()
-                    );
-
-                    let now = Instant::now();
-                    let estimator = fit(&fold, hyperparams);
-                    let fit_time = now.elapsed();
-
-                    let now = Instant::now();
-                    let mut metrics = self.test(project, &fold, &estimator);
-                    let score_time = now.elapsed();
-
-                    metrics.insert("fit_time".to_string(), fit_time.as_secs_f32());
-                    metrics.insert("score_time".to_string(), score_time.as_secs_f32());
-
-                    info!(
-                        "k = {}, metrics: {}",
-                        k,
-                        serde_json::to_string_pretty(&metrics).unwrap()
-                    );
-
-                    all_metrics.push(metrics);
-                    all_estimators.push(estimator);
-                }
-            }
-        }
+        };
 
         // Phew, we're done.
         signal_hook::low_level::unregister(signal_id);
 
-        let (bytes, best_metrics, best_hyperparams) = if all_metrics.len() == 1 {
+        let (estimator, mut best_metrics, best_hyperparams) = if trials.len() == 1 && cv < 2 {
+            let mut trial = trials.into_iter().next().unwrap();
             (
-                (*all_estimators.first().unwrap()).to_bytes(),
-                json!(all_metrics.first().unwrap()),
-                json!(all_hyperparams.first().unwrap()),
+                trial.estimators.pop().unwrap(),
+                json!(trial.metrics.pop().unwrap()),
+                json!(trial.hyperparams),
             )
         } else {
+            let folds = cv.max(1);
+            let all_hyperparams: Vec<Hyperparams> = trials
+                .iter()
+                .map(|trial| trial.hyperparams.clone())
+                .collect();
             let mut search_results = IndexMap::new();
             search_results.insert("params".to_string(), json!(all_hyperparams));
-            search_results.insert("n_splits".to_string(), json!(cv));
+            search_results.insert("n_splits".to_string(), json!(folds));
+            if self.search == Some(Search::halving) {
+                search_results.insert(
+                    "iter".to_string(),
+                    json!(trials
+                        .iter()
+                        .map(|trial| trial.iter)
+                        .collect::<Vec<usize>>()),
+                );
+                search_results.insert(
+                    "n_resources".to_string(),
+                    json!(trials
+                        .iter()
+                        .map(|trial| trial.n_resources)
+                        .collect::<Vec<usize>>()),
+                );
+            }
 
-            // Find the best estimator, hyperparams and metrics
-            let target_metric = match project.task {
-                Task::regression => "r2",
-                Task::classification => "f1",
-            };
-            let mut i = 0;
+            // Only the last round of a successive halving search is trained on all the data.
+            let last_iter = trials.iter().map(|trial| trial.iter).max().unwrap();
             let mut best_index = 0;
-            let mut best_metric = f32::NEG_INFINITY;
+            let mut best_score = f32::NEG_INFINITY;
             let mut best_metrics = None;
             let mut best_hyperparams = None;
             let mut best_estimator = None;
-            let mut fit_times: Vec<Vec<f32>> = vec![vec![0.; cv]; all_hyperparams.len()];
-            let mut score_times: Vec<Vec<f32>> = vec![vec![0.; cv]; all_hyperparams.len()];
-            let mut test_scores: Vec<Vec<f32>> = vec![vec![0.; cv]; all_hyperparams.len()];
-            let mut fold_scores: Vec<Vec<f32>> = vec![vec![0.; all_hyperparams.len()]; cv];
-            for (metrics, estimator) in izip!(all_metrics, all_estimators) {
-                let fold_i = i / all_hyperparams.len();
-                let hyperparams_i = i % all_hyperparams.len();
-                let hyperparams = &all_hyperparams[hyperparams_i];
-                let metric = *metrics.get(target_metric).unwrap();
-                fit_times[hyperparams_i][fold_i] = *metrics.get("fit_time").unwrap();
-                score_times[hyperparams_i][fold_i] = *metrics.get("score_time").unwrap();
-                test_scores[hyperparams_i][fold_i] = metric;
-                fold_scores[fold_i][hyperparams_i] = metric;
-
-                if metric > best_metric {
-                    best_index = hyperparams_i;
-                    best_metric = metric;
-                    best_metrics = Some(metrics);
-                    best_hyperparams = Some(hyperparams);
-                    best_estimator = Some(estimator);
+            let mut fit_times: Vec<Vec<f32>> = vec![vec![0.; folds]; trials.len()];
+            let mut score_times: Vec<Vec<f32>> = vec![vec![0.; folds]; trials.len()];
+            let mut test_scores: Vec<Vec<f32>> = vec![vec![0.; folds]; trials.len()];
+            let mut fold_scores: Vec<Vec<f32>> = vec![vec![0.; trials.len()]; folds];
+            for (hyperparams_i, trial) in trials.into_iter().enumerate() {
+                for (fold_i, (metrics, estimator)) in
+                    izip!(trial.metrics, trial.estimators).enumerate()
+                {
+                    let metric = *metrics.get(target_metric).unwrap();
+                    fit_times[hyperparams_i][fold_i] = *metrics.get("fit_time").unwrap();
+                    score_times[hyperparams_i][fold_i] = *metrics.get("score_time").unwrap();
+                    test_scores[hyperparams_i][fold_i] = metric;
+                    fold_scores[fold_i][hyperparams_i] = metric;
+
+                    let score = if greater_is_better { metric } else { -metric };
+                    if trial.iter == last_iter && score > best_score {
+                        best_index = hyperparams_i;
+                        best_score = score;
+                        best_metrics = Some(metrics);
+                        best_hyperparams = Some(trial.hyperparams.clone());
+                        best_estimator = Some(estimator);
+                    }
                 }
-                i += 1;
             }
 
             search_results.insert("best_index".to_string(), json!(best_index));
@@ -543,10 +945,11 @@ impl Model {
                     .map(|v| ArrayView1::from(v).std(0.))
                     .collect::<Vec<f32>>()),
             );
-            for k in 0..cv {
-                search_results.insert(format!("split{k}_test_score"), json!(fold_scores[k]));
+            for (k, scores) in fold_scores.iter().enumerate() {
+                search_results.insert(format!("split{k}_test_score"), json!(scores));
             }
-            for param in best_hyperparams.unwrap().keys() {
+            let best_hyperparams = best_hyperparams.unwrap();
+            for param in best_hyperparams.keys() {
                 let params: Vec<serde_json::Value> = all_hyperparams
                     .iter()
                     .map(|hyperparams| json!(hyperparams.get(param).unwrap()))
@@ -559,12 +962,33 @@ impl Model {
             }
             metrics.insert("search_results".to_string(), json!(search_results));
             (
-                best_estimator.unwrap().to_bytes(),
+                best_estimator.unwrap(),
                 json!(metrics),
-                json!(best_hyperparams.unwrap()),
+                json!(best_hyperparams),
             )
         };
 
+        // Details of the chosen estimator that trials aren't compared by.
+        if let Some(history) = estimator.history() {
+            best_metrics["history"] = json!(history);
+        }
+        if let Some(feature_importance) = estimator.feature_importance() {
+            best_metrics["feature_importance"] = json!(feature_importance);
+        }
+        if project.task == Task::classification {
+            // On the test split, which cross validation folds don't train on either.
+            let y_hat = estimator.predict_batch(&dataset.x_test);
+            let confusion_matrix =
+                confusion_matrix(&y_hat, &dataset.y_test, dataset.num_distinct_labels);
+            best_metrics["confusion_matrix"] = json!(confusion_matrix);
+            best_metrics["classes"] = json!(class_metrics(&confusion_matrix));
+        }
+        if project.task == Task::regression {
+            let y_hat = estimator.predict_batch(&dataset.x_test);
+            best_metrics["residuals"] = residual_summary(&y_hat, &dataset.y_test);
+        }
+        let bytes = estimator.to_bytes();
+
         self.hyperparams = JsonB(best_hyperparams.clone());
         self.metrics = Some(JsonB(best_metrics.clone()));
         Spi::get_one_with_args::<i64>(
@@ -593,3 +1017,366 @@ impl Model {
             ).unwrap();
     }
 }
+
+/// Mean cross-entropy of the true classes. Probabilities are clipped
+/// so confidently wrong predictions don't make it infinite.
+fn log_loss(y_proba: ArrayView2<f32>, y_test: &[f32]) -> f32 {
+    let eps = 1e-7;
+    let total: f32 = y_proba
+        .rows()
+        .into_iter()
+        .zip(y_test)
+        .map(|(probabilities, &class)| -probabilities[class as usize].clamp(eps, 1. - eps).ln())
+        .sum();
+    total / y_test.len() as f32
+}
+
+/// Metrics of predictions against the ground truth. Joint models, predicting `num_labels`
+/// targets per row, report the average of the metrics for each target.
+fn regression_metrics(y_hat: &[f32], y_test: &[f32], num_labels: usize) -> IndexMap<String, f32> {
+    let mut metrics = IndexMap::new();
+    let y_test = ArrayView2::from_shape((y_test.len() / num_labels, num_labels), y_test).unwrap();
+    let y_hat = ArrayView2::from_shape((y_hat.len() / num_labels, num_labels), y_hat).unwrap();
+
+    for (y_hat, y_test) in y_hat.columns().into_iter().zip(y_test.columns()) {
+        let mean_squared_error = y_hat.mean_squared_error(&y_test).unwrap();
+        let y_test_variance = y_test.var(0.);
+        let mut errors: Vec<f32> = y_hat
+            .iter()
+            .zip(y_test)
+            .map(|(y_hat, y_test)| y_hat - y_test)
+            .collect();
+        let errors_variance = ArrayView1::from(&errors).var(0.);
+        let mean_absolute_percentage_error = y_hat
+            .iter()
+            .zip(y_test)
+            .map(|(y_hat, y_test)| (y_hat - y_test).abs() / y_test.abs().max(f32::EPSILON))
+            .sum::<f32>()
+            / y_test.len() as f32;
+        errors.iter_mut().for_each(|error| *error = error.abs());
+        let max_error = errors.iter().copied().fold(0., f32::max);
+
+        for (metric, value) in [
+            ("r2", y_hat.r2(&y_test).unwrap()),
+            (
+                "mean_absolute_error",
+                y_hat.mean_absolute_error(&y_test).unwrap(),
+            ),
+            ("mean_squared_error", mean_squared_error),
+            ("root_mean_squared_error", mean_squared_error.sqrt()),
+            (
+                "mean_absolute_percentage_error",
+                mean_absolute_percentage_error,
+            ),
+            ("median_absolute_error", quantile(&mut errors, 0.5)),
+            (
+                "explained_variance",
+                if y_test_variance > 0. {
+                    1. - errors_variance / y_test_variance
+                } else {
+                    0.
+                },
+            ),
+            ("max_error", max_error),
+        ] {
+            *metrics.entry(metric.to_string()).or_insert(0.) += value / num_labels as f32;
+        }
+    }
+
+    metrics
+}
+
+/// The metrics of a task that only need the predictions and the ground truth, e.g. to score
+/// the outcomes of live predictions. Ranking metrics would need probabilities, and clustering
+/// metrics the features.
+pub fn outcome_metrics(task: Task, y_hat: &[f32], y_test: &[f32]) -> IndexMap<String, f32> {
+    match task {
+        Task::regression => regression_metrics(y_hat, y_test, 1),
+        Task::classification => classification_metrics(&confusion_matrix(y_hat, y_test, 2)),
+        Task::clustering => error!("Clustering metrics can't be computed from outcomes alone."),
+    }
+}
+
+/// The number of test rows of each class (rows) predicted as each class (columns).
+fn confusion_matrix(y_hat: &[f32], y_test: &[f32], num_classes: usize) -> Vec<Vec<usize>> {
+    let classes = |y: &[f32]| y.iter().map(|&i| i.round() as usize).max().unwrap_or(0) + 1;
+    let num_classes = num_classes.max(classes(y_hat)).max(classes(y_test));
+    let mut confusion_matrix = vec![vec![0; num_classes]; num_classes];
+    for (&y_hat, &y_test) in y_hat.iter().zip(y_test) {
+        confusion_matrix[y_test.round() as usize][y_hat.round() as usize] += 1;
+    }
+    confusion_matrix
+}
+
+/// The precision, recall, f1 and support of each class.
+fn class_metrics(confusion_matrix: &[Vec<usize>]) -> IndexMap<String, IndexMap<String, f32>> {
+    let ratio = |a: f32, b: f32| if b > 0. { a / b } else { 0. };
+    let mut classes = IndexMap::new();
+    for (class, row) in confusion_matrix.iter().enumerate() {
+        let true_positives = row[class] as f32;
+        let support = row.iter().sum::<usize>() as f32;
+        let predicted = confusion_matrix.iter().map(|row| row[class]).sum::<usize>() as f32;
+        let precision = ratio(true_positives, predicted);
+        let recall = ratio(true_positives, support);
+
+        let mut metrics = IndexMap::new();
+        metrics.insert("precision".to_string(), precision);
+        metrics.insert("recall".to_string(), recall);
+        metrics.insert(
+            "f1".to_string(),
+            ratio(2. * precision * recall, precision + recall),
+        );
+        metrics.insert("support".to_string(), support);
+        classes.insert(class.to_string(), metrics);
+    }
+    classes
+}
+
+/// Summarize the confusion matrix. Precision, recall and f1 are reported for the positive
+/// class of binary classifiers, and as the macro average over the classes otherwise. The
+/// macro, micro and weighted (by support) averages are also reported explicitly.
+fn classification_metrics(confusion_matrix: &[Vec<usize>]) -> IndexMap<String, f32> {
+    let classes = class_metrics(confusion_matrix);
+    let num_classes = classes.len() as f32;
+    let total = classes.values().map(|class| class["support"]).sum::<f32>();
+    let correct = (0..confusion_matrix.len())
+        .map(|class| confusion_matrix[class][class])
+        .sum::<usize>() as f32;
+    let accuracy = if total > 0. { correct / total } else { 0. };
+
+    let mut metrics = IndexMap::new();
+    for metric in ["precision", "recall", "f1"] {
+        let macro_average = classes.values().map(|class| class[metric]).sum::<f32>() / num_classes;
+        let weighted_average = if total > 0. {
+            classes
+                .values()
+                .map(|class| class[metric] * class["support"])
+                .sum::<f32>()
+                / total
+        } else {
+            0.
+        };
+        let value = if confusion_matrix.len() == 2 {
+            classes["1"][metric]
+        } else {
+            macro_average
+        };
+        metrics.insert(metric.to_string(), value);
+        metrics.insert(format!("{metric}_macro"), macro_average);
+        // Every misclassification is a false positive of one class and a false negative of another.
+        metrics.insert(format!("{metric}_micro"), accuracy);
+        metrics.insert(format!("{metric}_weighted"), weighted_average);
+    }
+    metrics.insert("accuracy".to_string(), accuracy);
+
+    // Recall of the classes that are in the test set.
+    let present = classes.values().filter(|class| class["support"] > 0.);
+    let num_present = present.clone().count() as f32;
+    metrics.insert(
+        "balanced_accuracy".to_string(),
+        present.map(|class| class["recall"]).sum::<f32>() / num_present.max(1.),
+    );
+
+    // Agreement beyond what the class frequencies would give by chance.
+    let chance = (0..confusion_matrix.len())
+        .map(|class| {
+            let actual = confusion_matrix[class].iter().sum::<usize>() as f32;
+            let predicted = confusion_matrix.iter().map(|row| row[class]).sum::<usize>() as f32;
+            actual * predicted
+        })
+        .sum::<f32>();
+    let expected = if total > 0. {
+        chance / (total * total)
+    } else {
+        0.
+    };
+    metrics.insert(
+        "cohen_kappa".to_string(),
+        if expected < 1. {
+            (accuracy - expected) / (1. - expected)
+        } else {
+            0.
+        },
+    );
+
+    // Gorodkin's generalization of the Matthews correlation coefficient to many classes.
+    let sum_of_squares = |counts: Vec<f32>| counts.iter().map(|count| count * count).sum::<f32>();
+    let actual = sum_of_squares(
+        confusion_matrix
+            .iter()
+            .map(|row| row.iter().sum::<usize>() as f32)
+            .collect(),
+    );
+    let predicted = sum_of_squares(
+        (0..confusion_matrix.len())
+            .map(|class| confusion_matrix.iter().map(|row| row[class]).sum::<usize>() as f32)
+            .collect(),
+    );
+    let denominator = ((total * total - predicted) * (total * total - actual)).sqrt();
+    metrics.insert(
+        "mcc".to_string(),
+        if denominator > 0. {
+            (correct * total - chance) / denominator
+        } else {
+            0.
+        },
+    );
+
+    metrics
+}
+
+/// The q-th quantile of the values, interpolated linearly between the closest ranks.
+fn quantile(values: &mut [f32], q: f32) -> f32 {
+    if values.is_empty() {
+        return 0.;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let rank = q * (values.len() - 1) as f32;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    values[lower] + (values[upper] - values[lower]) * (rank - lower as f32)
+}
+
+/// Quantiles of the residuals (`y_hat - y_test`), and a histogram of the residuals by
+/// prediction, to see if the model is biased for some ranges of predictions.
+fn residual_summary(y_hat: &[f32], y_test: &[f32]) -> serde_json::Value {
+    const NUM_BINS: usize = 10;
+
+    let residuals: Vec<f32> = y_hat
+        .iter()
+        .zip(y_test)
+        .map(|(y_hat, y_test)| y_hat - y_test)
+        .collect();
+    let mut sorted = residuals.clone();
+    let mut quantiles = IndexMap::new();
+    for q in [0., 0.05, 0.25, 0.5, 0.75, 0.95, 1.] {
+        quantiles.insert(q.to_string(), quantile(&mut sorted, q));
+    }
+
+    // Equal width bins of the predictions.
+    let min = y_hat.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = y_hat.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let width = (max - min) / NUM_BINS as f32;
+    let mut counts = vec![0_usize; NUM_BINS];
+    let mut totals = vec![0_f32; NUM_BINS];
+    for (y_hat, residual) in y_hat.iter().zip(&residuals) {
+        let bin = if width > 0. {
+            (((y_hat - min) / width) as usize).min(NUM_BINS - 1)
+        } else {
+            0
+        };
+        counts[bin] += 1;
+        totals[bin] += residual;
+    }
+    let histogram: Vec<serde_json::Value> = (0..NUM_BINS)
+        .filter(|&bin| counts[bin] > 0)
+        .map(|bin| {
+            json!({
+                "prediction_min": min + width * bin as f32,
+                "prediction_max": min + width * (bin + 1) as f32,
+                "count": counts[bin],
+                "mean_residual": totals[bin] / counts[bin] as f32,
+            })
+        })
+        .collect();
+
+    json!({
+        "mean": residuals.iter().sum::<f32>() / residuals.len().max(1) as f32,
+        "quantiles": quantiles,
+        "histogram": histogram,
+    })
+}
+
+/// The squared euclidean distance between two datapoints.
+fn squared_distance(a: ArrayView1<f32>, b: ArrayView1<f32>) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+/// Mean silhouette coefficient of all clustered datapoints. Noise (negative
+/// cluster ids, e.g. from DBSCAN) is excluded, and it's 0 when there are fewer
+/// than 2 clusters since the score is undefined. It's quadratic in the number of
+/// datapoints, so large test sets are scored on a reproducible random sample.
+fn silhouette_score(x: ArrayView2<f32>, clusters: &[i64]) -> f32 {
+    const MAX_SAMPLES: usize = 2000;
+
+    let num_clusters = clusters.iter().filter(|&&c| c >= 0).unique().count();
+    if num_clusters < 2 {
+        return 0.;
+    }
+
+    let mut sample: Vec<usize> = (0..clusters.len()).filter(|&i| clusters[i] >= 0).collect();
+    if sample.len() > MAX_SAMPLES {
+        let mut rng = StdRng::seed_from_u64(0);
+        sample = rand::seq::index::sample(&mut rng, sample.len(), MAX_SAMPLES)
+            .into_iter()
+            .map(|i| sample[i])
+            .collect();
+    }
+
+    let mut total = 0.;
+    let mut num_samples = 0;
+    for &i in &sample {
+        let cluster = clusters[i];
+
+        // Mean distance to every other sampled datapoint, by cluster.
+        let mut distances: IndexMap<i64, (f32, usize)> = IndexMap::new();
+        for &j in &sample {
+            let other = clusters[j];
+            if i == j {
+                continue;
+            }
+            let distance = squared_distance(x.row(i), x.row(j)).sqrt();
+            let entry = distances.entry(other).or_insert((0., 0));
+            entry.0 += distance;
+            entry.1 += 1;
+        }
+
+        num_samples += 1;
+        let a = match distances.get(&cluster) {
+            Some((distance, count)) => distance / *count as f32,
+            // Singleton clusters have a silhouette of 0 by convention.
+            None => continue,
+        };
+        let b = distances
+            .iter()
+            .filter(|(&other, _)| other != cluster)
+            .map(|(_, (distance, count))| distance / *count as f32)
+            .fold(f32::INFINITY, f32::min);
+
+        let max = a.max(b);
+        if max > 0. {
+            total += (b - a) / max;
+        }
+    }
+
+    if num_samples == 0 {
+        0.
+    } else {
+        total / num_samples as f32
+    }
+}
+
+/// Sum of squared distances of datapoints to the center of their cluster.
+/// Noise (negative cluster ids) is excluded.
+fn inertia(x: ArrayView2<f32>, clusters: &[i64]) -> f32 {
+    let mut centroids: IndexMap<i64, (Array1<f32>, usize)> = IndexMap::new();
+    for (row, &cluster) in x.rows().into_iter().zip(clusters) {
+        if cluster < 0 {
+            continue;
+        }
+        let entry = centroids
+            .entry(cluster)
+            .or_insert_with(|| (Array1::zeros(x.ncols()), 0));
+        entry.0 += &row;
+        entry.1 += 1;
+    }
+    for (centroid, count) in centroids.values_mut() {
+        *centroid /= *count as f32;
+    }
+
+    x.rows()
+        .into_iter()
+        .zip(clusters)
+        .filter(|(_, &cluster)| cluster >= 0)
+        .map(|(row, cluster)| squared_distance(row, centroids.get(cluster).unwrap().0.view()))
+        .sum()
+}
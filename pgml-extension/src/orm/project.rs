@@ -3,6 +3,7 @@ use std::str::FromStr;
 
 use pgx::*;
 
+use crate::orm::task;
 use crate::orm::Snapshot;
 use crate::orm::Task;
 
@@ -11,6 +12,7 @@ pub struct Project {
     pub id: i64,
     pub name: String,
     pub task: Task,
+    pub metric: Option<String>,
     pub created_at: Timestamp,
     pub updated_at: Timestamp,
 }
@@ -19,8 +21,11 @@ impl Display for Project {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(
             f,
-            "Project {{ id: {}, name: {}, task: {:?} }}",
-            self.id, self.name, self.task
+            "Project {{ id: {}, name: {}, task: {:?}, metric: {} }}",
+            self.id,
+            self.name,
+            self.task,
+            self.target_metric()
         )
     }
 }
@@ -30,7 +35,7 @@ impl Project {
         let mut project: Option<Project> = None;
 
         Spi::connect(|client| {
-            let result = client.select("SELECT id, name, task::TEXT, created_at, updated_at FROM pgml.projects WHERE id = $1 LIMIT 1;",
+            let result = client.select("SELECT id, name, task::TEXT, metric, created_at, updated_at FROM pgml.projects WHERE id = $1 LIMIT 1;",
                 Some(1),
                 Some(vec![
                     (PgBuiltInOids::INT8OID.oid(), id.into_datum()),
@@ -41,8 +46,9 @@ impl Project {
                     id: result.get_datum(1).unwrap(),
                     name: result.get_datum(2).unwrap(),
                     task: Task::from_str(result.get_datum(3).unwrap()).unwrap(),
-                    created_at: result.get_datum(4).unwrap(),
-                    updated_at: result.get_datum(5).unwrap(),
+                    metric: result.get_datum(4),
+                    created_at: result.get_datum(5).unwrap(),
+                    updated_at: result.get_datum(6).unwrap(),
                 });
             }
             Ok(Some(1))
@@ -55,7 +61,7 @@ impl Project {
         let mut project = None;
 
         Spi::connect(|client| {
-            let result = client.select("SELECT id, name, task::TEXT, created_at, updated_at FROM pgml.projects WHERE name = $1 LIMIT 1;",
+            let result = client.select("SELECT id, name, task::TEXT, metric, created_at, updated_at FROM pgml.projects WHERE name = $1 LIMIT 1;",
                 Some(1),
                 Some(vec![
                     (PgBuiltInOids::TEXTOID.oid(), name.into_datum()),
@@ -66,8 +72,9 @@ impl Project {
                     id: result.get_datum(1).unwrap(),
                     name: result.get_datum(2).unwrap(),
                     task: Task::from_str(result.get_datum(3).unwrap()).unwrap(),
-                    created_at: result.get_datum(4).unwrap(),
-                    updated_at: result.get_datum(5).unwrap(),
+                    metric: result.get_datum(4),
+                    created_at: result.get_datum(5).unwrap(),
+                    updated_at: result.get_datum(6).unwrap(),
                 });
             }
             Ok(Some(1))
@@ -80,7 +87,7 @@ impl Project {
         let mut project: Option<Project> = None;
 
         Spi::connect(|client| {
-            let result = client.select(r#"INSERT INTO pgml.projects (name, task) VALUES ($1, $2::pgml.task) RETURNING id, name, task::TEXT, created_at, updated_at;"#,
+            let result = client.select(r#"INSERT INTO pgml.projects (name, task) VALUES ($1, $2::pgml.task) RETURNING id, name, task::TEXT, metric, created_at, updated_at;"#,
                 Some(1),
                 Some(vec![
                     (PgBuiltInOids::TEXTOID.oid(), name.into_datum()),
@@ -92,8 +99,9 @@ impl Project {
                     id: result.get_datum(1).unwrap(),
                     name: result.get_datum(2).unwrap(),
                     task: Task::from_str(result.get_datum(3).unwrap()).unwrap(),
-                    created_at: result.get_datum(4).unwrap(),
-                    updated_at: result.get_datum(5).unwrap(),
+                    metric: result.get_datum(4),
+                    created_at: result.get_datum(5).unwrap(),
+                    updated_at: result.get_datum(6).unwrap(),
                 });
             }
             Ok(Some(1))
@@ -101,6 +109,40 @@ impl Project {
         project.unwrap()
     }
 
+    /// The metric used to pick the best estimator in a search, decide automatic
+    /// deployments and rank models for the best_score strategy.
+    pub fn target_metric(&self) -> &str {
+        match &self.metric {
+            Some(metric) => metric,
+            None => self.task.default_target_metric(),
+        }
+    }
+
+    /// Whether a higher target metric means a better model.
+    pub fn greater_is_better(&self) -> bool {
+        task::greater_is_better(self.target_metric())
+    }
+
+    pub fn update_metric(&mut self, metric: &str) {
+        if !self.task.metrics().contains(&metric) {
+            error!(
+                "`{}` is not a {:?} metric, use one of: {}",
+                metric,
+                self.task,
+                self.task.metrics().join(", ")
+            );
+        }
+
+        Spi::get_one_with_args::<i64>(
+            "UPDATE pgml.projects SET metric = $1 WHERE id = $2 RETURNING id",
+            vec![
+                (PgBuiltInOids::TEXTOID.oid(), metric.into_datum()),
+                (PgBuiltInOids::INT8OID.oid(), self.id.into_datum()),
+            ],
+        );
+        self.metric = Some(metric.to_string());
+    }
+
     pub fn last_snapshot(&self) -> Option<Snapshot> {
         Snapshot::find_last_by_project_id(self.id)
     }
@@ -0,0 +1,222 @@
+use std::str::FromStr;
+
+use pgx::*;
+
+use crate::orm::Algorithm;
+use crate::orm::Sampling;
+use crate::orm::Search;
+use crate::orm::Snapshot;
+use crate::orm::Status;
+
+/// How a project is retrained when its data changes, stored in `pgml.retrain_policies`.
+#[derive(Debug, Clone)]
+pub struct RetrainPolicy {
+    pub id: i64,
+    pub project_id: i64,
+    pub project_name: String,
+    pub relation_name: String,
+    pub y_column_name: Vec<String>,
+    pub algorithm: Algorithm,
+    pub hyperparams: serde_json::Value,
+    pub search: Option<Search>,
+    pub search_params: serde_json::Value,
+    pub search_args: serde_json::Value,
+    pub preprocess: serde_json::Value,
+    pub test_size: f32,
+    pub test_sampling: Sampling,
+    pub row_delta: Option<i64>,
+    /// The role that set the policy, its project is retrained with its privileges.
+    pub owner: String,
+}
+
+impl RetrainPolicy {
+    /// The enabled policies whose project should be retrained now, with the reason why.
+    /// Schedules count from the last run of the policy, or the last time the project was
+    /// trained manually, and row deltas from the number of rows in its last snapshot.
+    /// A policy whose last run failed backs off for its schedule, or a day without one,
+    /// so a run that keeps failing isn't retried over and over.
+    pub fn find_due() -> Vec<(RetrainPolicy, String)> {
+        let mut policies = Vec::new();
+        Spi::connect(|client| {
+            client
+                .select(
+                    "SELECT
+                        policies.id,
+                        policies.project_id,
+                        projects.name,
+                        policies.relation_name,
+                        policies.y_column_name,
+                        policies.algorithm,
+                        policies.hyperparams,
+                        policies.search,
+                        policies.search_params,
+                        policies.search_args,
+                        policies.preprocess,
+                        policies.test_size,
+                        policies.test_sampling::TEXT,
+                        policies.row_delta,
+                        policies.owner,
+                        policies.schedule IS NOT NULL AND COALESCE(
+                            GREATEST(
+                                last_run.created_at,
+                                (SELECT max(models.created_at) FROM pgml.models WHERE models.project_id = policies.project_id)
+                            ) + policies.schedule <= clock_timestamp(),
+                            true
+                        ),
+                        to_regclass(policies.relation_name)::TEXT,
+                        CASE WHEN EXISTS (SELECT 1 FROM pg_roles WHERE rolname = policies.owner)
+                            THEN has_table_privilege(policies.owner, to_regclass(policies.relation_name), 'SELECT')
+                        END
+                    FROM pgml.retrain_policies policies
+                    JOIN pgml.projects ON projects.id = policies.project_id
+                    LEFT JOIN LATERAL (
+                        SELECT runs.status, runs.created_at
+                        FROM pgml.retrain_runs runs
+                        WHERE runs.policy_id = policies.id
+                        ORDER BY runs.id DESC
+                        LIMIT 1
+                    ) last_run ON true
+                    WHERE policies.enabled
+                        AND NOT COALESCE(
+                            last_run.status = $1
+                                AND last_run.created_at + COALESCE(policies.schedule, INTERVAL '1 day') > clock_timestamp(),
+                            false
+                        )
+                    ORDER BY policies.id",
+                    None,
+                    Some(vec![(
+                        PgBuiltInOids::TEXTOID.oid(),
+                        Status::failed.to_string().into_datum(),
+                    )]),
+                )
+                .for_each(|row| {
+                    let policy = RetrainPolicy {
+                        id: row[1].value().unwrap(),
+                        project_id: row[2].value().unwrap(),
+                        project_name: row[3].value().unwrap(),
+                        relation_name: row[4].value().unwrap(),
+                        y_column_name: row[5].value().unwrap(),
+                        algorithm: Algorithm::from_str(row[6].value().unwrap()).unwrap(),
+                        hyperparams: row[7].value::<JsonB>().unwrap().0,
+                        search: row[8]
+                            .value()
+                            .map(|search: &str| Search::from_str(search).unwrap()),
+                        search_params: row[9].value::<JsonB>().unwrap().0,
+                        search_args: row[10].value::<JsonB>().unwrap().0,
+                        preprocess: row[11].value::<JsonB>().unwrap().0,
+                        test_size: row[12].value().unwrap(),
+                        test_sampling: Sampling::from_str(row[13].value().unwrap()).unwrap(),
+                        row_delta: row[14].value(),
+                        owner: row[15].value().unwrap(),
+                    };
+                    let scheduled: bool = row[16].value().unwrap();
+                    let relation: Option<String> = row[17].value();
+                    let readable: bool = row[18].value().unwrap_or(false);
+                    policies.push((policy, scheduled, relation, readable));
+                });
+            Ok(Some(1))
+        });
+
+        policies
+            .into_iter()
+            .filter_map(|(policy, scheduled, relation, readable)| {
+                // Relations are counted on behalf of the policy's owner, who may not read them.
+                let relation = match (relation, readable) {
+                    (Some(relation), true) => relation,
+                    _ => {
+                        warning!(
+                            "Skipping the retraining policy of project `{}`, `{}` does not exist or `{}` can't read it.",
+                            policy.project_name,
+                            policy.relation_name,
+                            policy.owner
+                        );
+                        return None;
+                    }
+                };
+                if scheduled {
+                    return Some((policy, "schedule".to_string()));
+                }
+                let row_delta = policy.row_delta?;
+                // The text of a regclass is quoted, so it's safe to interpolate.
+                let rows = Spi::get_one::<i64>(&format!("SELECT count(*) FROM {}", relation))
+                    .unwrap();
+                let samples = Snapshot::find_last_by_project_id(policy.project_id)
+                    .and_then(|snapshot| snapshot.analysis)
+                    .and_then(|analysis| analysis.0.get("samples").and_then(|s| s.as_f64()))
+                    .unwrap_or(0.) as i64;
+                if (rows - samples).abs() >= row_delta {
+                    Some((policy, "row_delta".to_string()))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Retrain with the privileges of the policy's owner, for the rest of the transaction,
+    /// rather than the background worker's superuser.
+    pub fn set_role(&self) {
+        let role = Spi::get_one_with_args::<String>(
+            "SELECT quote_ident($1)",
+            vec![(
+                PgBuiltInOids::TEXTOID.oid(),
+                self.owner.clone().into_datum(),
+            )],
+        )
+        .unwrap();
+        Spi::run(&format!("SET LOCAL ROLE {}", role));
+    }
+
+    /// Record that the project is being retrained, and return the id of the run.
+    /// Runs of the background worker are flagged, so it only cleans up its own.
+    pub fn start_run(&self, reason: &str, worker: bool) -> i64 {
+        Spi::get_one_with_args::<i64>(
+            "INSERT INTO pgml.retrain_runs (policy_id, project_id, reason, status, worker) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            vec![
+                (PgBuiltInOids::INT8OID.oid(), self.id.into_datum()),
+                (PgBuiltInOids::INT8OID.oid(), self.project_id.into_datum()),
+                (PgBuiltInOids::TEXTOID.oid(), reason.into_datum()),
+                (PgBuiltInOids::TEXTOID.oid(), Status::in_progress.to_string().into_datum()),
+                (PgBuiltInOids::BOOLOID.oid(), worker.into_datum()),
+            ],
+        )
+        .unwrap()
+    }
+
+    /// Record the model a run trained, and whether it was deployed.
+    pub fn finish_run(run_id: i64, model_id: i64, deployed: bool) {
+        Spi::get_one_with_args::<i64>(
+            "UPDATE pgml.retrain_runs SET status = $2, model_id = $3, deployed = $4 WHERE id = $1 RETURNING id",
+            vec![
+                (PgBuiltInOids::INT8OID.oid(), run_id.into_datum()),
+                (PgBuiltInOids::TEXTOID.oid(), Status::successful.to_string().into_datum()),
+                (PgBuiltInOids::INT8OID.oid(), model_id.into_datum()),
+                (PgBuiltInOids::BOOLOID.oid(), deployed.into_datum()),
+            ],
+        );
+    }
+
+    /// Record that a run raised an error.
+    pub fn fail_run(run_id: i64) {
+        Spi::get_one_with_args::<i64>(
+            "UPDATE pgml.retrain_runs SET status = $2 WHERE id = $1 RETURNING id",
+            vec![
+                (PgBuiltInOids::INT8OID.oid(), run_id.into_datum()),
+                (
+                    PgBuiltInOids::TEXTOID.oid(),
+                    Status::failed.to_string().into_datum(),
+                ),
+            ],
+        );
+    }
+
+    /// Runs the background worker left in progress when it was terminated are marked as
+    /// failed when it restarts. Runs of `pgml.retrain()` belong to their own transactions.
+    pub fn fail_interrupted_runs() {
+        Spi::run(&format!(
+            "UPDATE pgml.retrain_runs SET status = '{}' WHERE status = '{}' AND worker",
+            Status::failed.to_string(),
+            Status::in_progress.to_string()
+        ));
+    }
+}
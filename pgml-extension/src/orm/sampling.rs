@@ -6,6 +6,8 @@ use serde::Deserialize;
 pub enum Sampling {
     random,
     last,
+    stratified,
+    time,
 }
 
 impl std::str::FromStr for Sampling {
@@ -15,6 +17,8 @@ impl std::str::FromStr for Sampling {
         match input {
             "random" => Ok(Sampling::random),
             "last" => Ok(Sampling::last),
+            "stratified" => Ok(Sampling::stratified),
+            "time" => Ok(Sampling::time),
             _ => Err(()),
         }
     }
@@ -25,6 +29,8 @@ impl std::string::ToString for Sampling {
         match *self {
             Sampling::random => "random".to_string(),
             Sampling::last => "last".to_string(),
+            Sampling::stratified => "stratified".to_string(),
+            Sampling::time => "time".to_string(),
         }
     }
 }
@@ -0,0 +1,254 @@
+use pgx::*;
+use serde::{Deserialize, Serialize};
+
+use crate::orm::Dataset;
+
+/// How a feature is scaled before it's passed to the estimator.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum Scale {
+    /// The feature is used as is.
+    #[default]
+    preserve,
+    /// Zero mean and unit variance.
+    standard,
+    /// Between 0 and 1.
+    min_max,
+    /// Zero median and unit interquartile range, so outliers have less influence.
+    robust,
+    /// Uniformly distributed between 0 and 1.
+    quantile,
+    /// The natural log of 1 + the feature, which must be greater than -1.
+    log1p,
+}
+
+/// The parameters of a scale, fitted to the training data of a feature.
+#[derive(Debug, Serialize, Deserialize)]
+enum Transform {
+    Preserve,
+    Standard { mean: f32, stddev: f32 },
+    MinMax { min: f32, range: f32 },
+    Robust { median: f32, iqr: f32 },
+    Quantile { quantiles: Vec<f32> },
+    Log1p { min: f32 },
+}
+
+/// The most quantiles kept per feature by the quantile scale.
+const MAX_QUANTILES: usize = 1000;
+
+impl Transform {
+    fn fit(scale: Scale, values: &mut [f32]) -> Transform {
+        if values.is_empty() {
+            return Transform::Preserve;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        let n = values.len();
+        let percentile = |p: f32| values[((n - 1) as f32 * p).round() as usize];
+
+        match scale {
+            Scale::preserve => Transform::Preserve,
+            Scale::standard => {
+                let mean = values.iter().sum::<f32>() / n as f32;
+                let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n as f32;
+                Transform::Standard {
+                    mean,
+                    stddev: non_zero(variance.sqrt()),
+                }
+            }
+            Scale::min_max => Transform::MinMax {
+                min: values[0],
+                range: non_zero(values[n - 1] - values[0]),
+            },
+            Scale::robust => Transform::Robust {
+                median: percentile(0.5),
+                iqr: non_zero(percentile(0.75) - percentile(0.25)),
+            },
+            Scale::quantile => {
+                let num_quantiles = n.min(MAX_QUANTILES);
+                let quantiles = (0..num_quantiles)
+                    .map(|i| percentile(i as f32 / (num_quantiles - 1).max(1) as f32))
+                    .collect();
+                Transform::Quantile { quantiles }
+            }
+            Scale::log1p => Transform::Log1p { min: values[0] },
+        }
+    }
+
+    fn transform(&self, x: f32) -> f32 {
+        match self {
+            Transform::Preserve => x,
+            Transform::Standard { mean, stddev } => (x - mean) / stddev,
+            Transform::MinMax { min, range } => (x - min) / range,
+            Transform::Robust { median, iqr } => (x - median) / iqr,
+            Transform::Quantile { quantiles } => {
+                let last = quantiles.len() - 1;
+                if last == 0 || x <= quantiles[0] {
+                    return 0.;
+                }
+                if x >= quantiles[last] {
+                    return 1.;
+                }
+                // Interpolate between the quantiles on either side of x.
+                let i = quantiles.partition_point(|q| *q <= x) - 1;
+                let width = quantiles[i + 1] - quantiles[i];
+                let offset = if width > 0. {
+                    (x - quantiles[i]) / width
+                } else {
+                    0.
+                };
+                (i as f32 + offset) / last as f32
+            }
+            // Values below the training minimum are clamped to it, so they stay above -1.
+            Transform::Log1p { min } => x.max(*min).ln_1p(),
+        }
+    }
+}
+
+fn non_zero(x: f32) -> f32 {
+    if x > 0. {
+        x
+    } else {
+        1.
+    }
+}
+
+/// Scales each feature with the parameters fitted to the training data.
+/// It's saved in `pgml.files` as `scaler.rmp` next to the estimator,
+/// and applied to features before every prediction.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Scaler {
+    transforms: Vec<Transform>,
+}
+
+impl Scaler {
+    /// Fit a scale per feature to the training data, or None if every feature is preserved.
+    pub fn fit(scales: &[Scale], feature_names: &[String], dataset: &Dataset) -> Option<Scaler> {
+        if scales.iter().all(|scale| *scale == Scale::preserve) {
+            return None;
+        }
+
+        let transforms = scales
+            .iter()
+            .enumerate()
+            .map(|(i, scale)| {
+                let mut values: Vec<f32> = dataset
+                    .x_train
+                    .iter()
+                    .skip(i)
+                    .step_by(dataset.num_features)
+                    .copied()
+                    .collect();
+                if *scale == Scale::log1p && values.iter().any(|x| *x <= -1.) {
+                    error!(
+                        "Feature `{}` can't be scaled with `log1p`, it has values less than or equal to -1",
+                        feature_names[i]
+                    );
+                }
+                Transform::fit(*scale, &mut values)
+            })
+            .collect();
+
+        Some(Scaler { transforms })
+    }
+
+    /// Scale rows of features in place.
+    pub fn transform(&self, features: &mut [f32]) {
+        for (x, transform) in features.iter_mut().zip(self.transforms.iter().cycle()) {
+            *x = transform.transform(*x);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Scaler {
+        rmp_serde::from_read(bytes).unwrap()
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use crate::orm::Sampling;
+
+    fn dataset(x_train: Vec<f32>, num_features: usize) -> Dataset {
+        let num_rows = x_train.len() / num_features;
+        Dataset {
+            x_train,
+            y_train: vec![0.; num_rows],
+            x_test: Vec::new(),
+            y_test: Vec::new(),
+            num_features,
+            num_labels: 1,
+            num_rows,
+            num_train_rows: num_rows,
+            num_test_rows: 0,
+            num_distinct_labels: 0,
+            sampling: Sampling::last,
+        }
+    }
+
+    fn names(num_features: usize) -> Vec<String> {
+        (0..num_features)
+            .map(|i| format!("feature_{}", i))
+            .collect()
+    }
+
+    #[pg_test]
+    fn test_preserve() {
+        let dataset = dataset(vec![1., 2., 3., 4.], 2);
+        assert!(Scaler::fit(&[Scale::preserve, Scale::preserve], &names(2), &dataset).is_none());
+    }
+
+    #[pg_test]
+    fn test_scales() {
+        // Each row has a feature per scale.
+        let dataset = dataset(
+            vec![
+                1., 10., 0., 0., 0., //
+                2., 20., 1., 1., 1., //
+                3., 30., 2., 2., 2., //
+                4., 40., 3., 3., 3., //
+            ],
+            5,
+        );
+        let scales = [
+            Scale::standard,
+            Scale::min_max,
+            Scale::robust,
+            Scale::quantile,
+            Scale::log1p,
+        ];
+        let scaler = Scaler::fit(&scales, &names(5), &dataset).unwrap();
+
+        // e - 1 is scaled to 1 by log1p.
+        let e = std::f32::consts::E - 1.;
+        let mut features = vec![
+            2.5, 25., 2., 1.5, e, //
+            2.5, 100., 2., -1., -5., //
+        ];
+        scaler.transform(&mut features);
+        let expected = [
+            0., 0.5, 0., 0.5, 1., //
+            0., 3., 0., 0., 0., //
+        ];
+        for (x, expected) in features.iter().zip(expected) {
+            assert!((x - expected).abs() < 1e-5, "{:?}", features);
+        }
+
+        // Scalers are saved with their model.
+        let mut restored = vec![2.5, 25., 2., 1.5, e];
+        Scaler::from_bytes(&scaler.to_bytes()).transform(&mut restored);
+        assert_eq!(restored, features[..5].to_vec());
+    }
+
+    #[pg_test(
+        error = "Feature `feature_0` can't be scaled with `log1p`, it has values less than or equal to -1"
+    )]
+    fn test_log1p_out_of_range() {
+        let dataset = dataset(vec![-2., 0., 1.], 1);
+        Scaler::fit(&[Scale::log1p], &names(1), &dataset);
+    }
+}
@@ -1,4 +1,6 @@
 use pgx::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use serde::Deserialize;
 
 #[derive(PostgresEnum, Copy, Clone, Eq, PartialEq, Debug, Deserialize)]
@@ -6,6 +8,8 @@ use serde::Deserialize;
 pub enum Search {
     grid,
     random,
+    bayesian,
+    halving,
 }
 
 impl std::str::FromStr for Search {
@@ -15,6 +19,8 @@ impl std::str::FromStr for Search {
         match input {
             "grid" => Ok(Search::grid),
             "random" => Ok(Search::random),
+            "bayesian" => Ok(Search::bayesian),
+            "halving" => Ok(Search::halving),
             _ => Err(()),
         }
     }
@@ -25,6 +31,354 @@ impl std::string::ToString for Search {
         match *self {
             Search::grid => "grid".to_string(),
             Search::random => "random".to_string(),
+            Search::bayesian => "bayesian".to_string(),
+            Search::halving => "halving".to_string(),
+        }
+    }
+}
+
+/// The values a hyperparameter can take in a search, from `search_params`.
+#[derive(Debug, Clone)]
+pub enum Distribution {
+    /// One of a list of values, e.g. `[1, 2, 3]`.
+    Choice(Vec<serde_json::Value>),
+    /// A float between low and high, e.g. `{"uniform": [0.01, 0.3]}`.
+    Uniform(f64, f64),
+    /// An integer between low and high inclusive, e.g. `{"randint": [2, 12]}`.
+    RandInt(i64, i64),
+    /// A float between low and high whose log is uniform, e.g. `{"loguniform": [1e-4, 1e-1]}`.
+    LogUniform(f64, f64),
+}
+
+impl Distribution {
+    pub fn parse(name: &str, value: &serde_json::Value) -> Distribution {
+        if let Some(values) = value.as_array() {
+            if values.is_empty() {
+                error!("search_params `{name}` must have at least one value");
+            }
+            return Distribution::Choice(values.to_vec());
+        }
+
+        let spec = match value.as_object() {
+            Some(spec) if spec.len() == 1 => spec,
+            _ => error!("search_params `{name}` must be an array of values, or an object like {{\"uniform\": [low, high]}}, got: {value}"),
+        };
+        let (kind, bounds) = spec.iter().next().unwrap();
+        let bounds = match bounds.as_array() {
+            Some(bounds) if bounds.len() == 2 => bounds,
+            _ => error!("search_params `{name}` {kind} needs [low, high], got: {bounds}"),
+        };
+
+        let distribution = match kind.as_str() {
+            "uniform" | "loguniform" => {
+                let (low, high) = match (bounds[0].as_f64(), bounds[1].as_f64()) {
+                    (Some(low), Some(high)) => (low, high),
+                    _ => error!("search_params `{name}` {kind} bounds must be numbers, got: {bounds:?}"),
+                };
+                if kind == "loguniform" {
+                    if low <= 0. {
+                        error!("search_params `{name}` loguniform bounds must be positive, got: {bounds:?}");
+                    }
+                    Distribution::LogUniform(low, high)
+                } else {
+                    Distribution::Uniform(low, high)
+                }
+            }
+            "randint" => match (bounds[0].as_i64(), bounds[1].as_i64()) {
+                (Some(low), Some(high)) => Distribution::RandInt(low, high),
+                _ => error!("search_params `{name}` randint bounds must be integers, got: {bounds:?}"),
+            },
+            _ => error!("search_params `{name}` has an unknown distribution `{kind}`, expected one of: uniform, randint, loguniform"),
+        };
+
+        let (low, high) = match distribution {
+            Distribution::Uniform(low, high) | Distribution::LogUniform(low, high) => (low, high),
+            Distribution::RandInt(low, high) => (low as f64, high as f64),
+            Distribution::Choice(_) => unreachable!(),
+        };
+        if low > high {
+            error!(
+                "search_params `{name}` {kind} low must not be greater than high, got: {bounds:?}"
+            );
+        }
+
+        distribution
+    }
+
+    /// The values to try in a grid search, which only makes sense for a list of choices.
+    pub fn choices(&self, name: &str) -> Vec<serde_json::Value> {
+        match self {
+            Distribution::Choice(values) => values.clone(),
+            _ => error!("search_params `{name}` is a distribution, which can't be enumerated by search => 'grid'"),
+        }
+    }
+
+    /// Draw a single value.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> serde_json::Value {
+        match self {
+            Distribution::Choice(values) => values.choose(rng).unwrap().clone(),
+            Distribution::Uniform(low, high) => serde_json::json!(rng.gen_range(*low..=*high)),
+            Distribution::RandInt(low, high) => serde_json::json!(rng.gen_range(*low..=*high)),
+            Distribution::LogUniform(low, high) => {
+                serde_json::json!(rng.gen_range(low.ln()..=high.ln()).exp())
+            }
+        }
+    }
+}
+
+/// The fraction of trials a bayesian search considers good.
+const GAMMA: f64 = 0.25;
+
+/// How many candidates are drawn for each bayesian search suggestion.
+const NUM_CANDIDATES: usize = 24;
+
+/// Suggests the values of the next trial of a bayesian search, with a tree-structured
+/// Parzen estimator (TPE). The trials so far are split into the good and the bad by
+/// their score (higher is better), candidates are drawn from a density fitted to the
+/// good ones, and the candidate most likely to be good rather than bad wins.
+pub fn suggest<R: Rng + ?Sized>(
+    distributions: &[(String, Distribution)],
+    trials: &[(Vec<serde_json::Value>, f32)],
+    rng: &mut R,
+) -> Vec<serde_json::Value> {
+    let mut ranked: Vec<&(Vec<serde_json::Value>, f32)> = trials.iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let num_good = ((ranked.len() as f64 * GAMMA).ceil() as usize).clamp(1, ranked.len());
+    let (good, bad) = ranked.split_at(num_good);
+
+    let estimators: Vec<(Parzen, Parzen)> = distributions
+        .iter()
+        .enumerate()
+        .map(|(i, (_, distribution))| {
+            (
+                Parzen::fit(distribution, good.iter().map(|trial| &trial.0[i])),
+                Parzen::fit(distribution, bad.iter().map(|trial| &trial.0[i])),
+            )
+        })
+        .collect();
+
+    let mut best = None;
+    let mut best_ratio = f64::NEG_INFINITY;
+    for _ in 0..NUM_CANDIDATES {
+        let mut ratio = 0.;
+        let mut candidate = Vec::with_capacity(distributions.len());
+        for ((_, distribution), (good, bad)) in distributions.iter().zip(estimators.iter()) {
+            let x = good.sample(rng);
+            ratio += good.density(x).ln() - bad.density(x).ln();
+            candidate.push(distribution.value(x));
+        }
+        if ratio > best_ratio {
+            best_ratio = ratio;
+            best = Some(candidate);
+        }
+    }
+
+    best.unwrap()
+}
+
+impl Distribution {
+    /// The position of a value in the space the Parzen estimator works in,
+    /// e.g. the log of a log uniform value, or the index of a choice.
+    fn position(&self, value: &serde_json::Value) -> f64 {
+        match self {
+            Distribution::Choice(values) => {
+                values.iter().position(|v| v == value).unwrap_or(0) as f64
+            }
+            Distribution::Uniform(..) | Distribution::RandInt(..) => value.as_f64().unwrap(),
+            Distribution::LogUniform(..) => value.as_f64().unwrap().ln(),
+        }
+    }
+
+    /// The value at a position, the inverse of position().
+    fn value(&self, x: f64) -> serde_json::Value {
+        match self {
+            Distribution::Choice(values) => values[x as usize].clone(),
+            Distribution::Uniform(..) => serde_json::json!(x),
+            Distribution::RandInt(low, high) => {
+                serde_json::json!((x.round() as i64).clamp(*low, *high))
+            }
+            Distribution::LogUniform(..) => serde_json::json!(x.exp()),
+        }
+    }
+}
+
+/// A density over the positions of one hyperparam: a uniform prior mixed
+/// with a gaussian around each observation, or smoothed counts for choices.
+enum Parzen {
+    Categorical(Vec<f64>),
+    Continuous {
+        low: f64,
+        high: f64,
+        bandwidth: f64,
+        observations: Vec<f64>,
+    },
+}
+
+impl Parzen {
+    fn fit<'a>(
+        distribution: &Distribution,
+        values: impl Iterator<Item = &'a serde_json::Value>,
+    ) -> Parzen {
+        let observations: Vec<f64> = values.map(|value| distribution.position(value)).collect();
+        let (low, high) = match distribution {
+            Distribution::Choice(choices) => {
+                let mut weights = vec![1.; choices.len()];
+                for x in observations {
+                    weights[x as usize] += 1.;
+                }
+                let total: f64 = weights.iter().sum();
+                return Parzen::Categorical(weights.into_iter().map(|w| w / total).collect());
+            }
+            Distribution::Uniform(low, high) => (*low, *high),
+            Distribution::RandInt(low, high) => (*low as f64 - 0.5, *high as f64 + 0.5),
+            Distribution::LogUniform(low, high) => (low.ln(), high.ln()),
+        };
+
+        // Narrower gaussians as the observations grow, like Scott's rule.
+        let range = (high - low).max(f64::EPSILON);
+        let bandwidth = range * (observations.len() as f64 + 1.).powf(-0.2);
+
+        Parzen::Continuous {
+            low,
+            high,
+            bandwidth,
+            observations,
+        }
+    }
+
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match self {
+            Parzen::Categorical(weights) => {
+                let mut x = rng.gen::<f64>();
+                for (i, weight) in weights.iter().enumerate() {
+                    if x < *weight {
+                        return i as f64;
+                    }
+                    x -= weight;
+                }
+                (weights.len() - 1) as f64
+            }
+            Parzen::Continuous {
+                low,
+                high,
+                bandwidth,
+                observations,
+            } => {
+                // The prior is one more component of the mixture.
+                let component = rng.gen_range(0..=observations.len());
+                if component == observations.len() {
+                    return rng.gen_range(*low..=*high);
+                }
+                // Box-Muller
+                let normal = (-2. * (1. - rng.gen::<f64>()).ln()).sqrt()
+                    * (2. * std::f64::consts::PI * rng.gen::<f64>()).cos();
+                (observations[component] + bandwidth * normal).clamp(*low, *high)
+            }
+        }
+    }
+
+    fn density(&self, x: f64) -> f64 {
+        match self {
+            Parzen::Categorical(weights) => weights[x as usize],
+            Parzen::Continuous {
+                low,
+                high,
+                bandwidth,
+                observations,
+            } => {
+                let prior = 1. / (high - low).max(f64::EPSILON);
+                let gaussians: f64 = observations
+                    .iter()
+                    .map(|o| {
+                        (-0.5 * ((x - o) / bandwidth).powi(2)).exp()
+                            / (bandwidth * (2. * std::f64::consts::PI).sqrt())
+                    })
+                    .sum();
+                (prior + gaussians) / (observations.len() + 1) as f64
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use serde_json::json;
+
+    #[pg_test]
+    fn test_parse_distributions() {
+        match Distribution::parse("max_depth", &json!([2, 4, 6])) {
+            Distribution::Choice(values) => assert_eq!(values, vec![json!(2), json!(4), json!(6)]),
+            distribution => panic!("Expected a choice, got: {:?}", distribution),
+        }
+        assert!(matches!(
+            Distribution::parse("subsample", &json!({"uniform": [0.5, 1.0]})),
+            Distribution::Uniform(low, high) if low == 0.5 && high == 1.0
+        ));
+        assert!(matches!(
+            Distribution::parse("max_depth", &json!({"randint": [2, 12]})),
+            Distribution::RandInt(2, 12)
+        ));
+        assert!(matches!(
+            Distribution::parse("eta", &json!({"loguniform": [0.01, 0.3]})),
+            Distribution::LogUniform(low, high) if low == 0.01 && high == 0.3
+        ));
+    }
+
+    #[pg_test(
+        error = "search_params `eta` has an unknown distribution `normal`, expected one of: uniform, randint, loguniform"
+    )]
+    fn test_parse_unknown_distribution() {
+        Distribution::parse("eta", &json!({"normal": [0.0, 1.0]}));
+    }
+
+    #[pg_test]
+    fn test_sample_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let uniform = Distribution::parse("subsample", &json!({"uniform": [0.5, 1.0]}));
+        let randint = Distribution::parse("max_depth", &json!({"randint": [2, 4]}));
+        let loguniform = Distribution::parse("eta", &json!({"loguniform": [0.01, 0.3]}));
+        for _ in 0..100 {
+            let x = uniform.sample(&mut rng).as_f64().unwrap();
+            assert!((0.5..=1.0).contains(&x));
+            let x = randint.sample(&mut rng).as_i64().unwrap();
+            assert!((2..=4).contains(&x));
+            let x = loguniform.sample(&mut rng).as_f64().unwrap();
+            assert!((0.01 - 1e-9..=0.3 + 1e-9).contains(&x));
+        }
+    }
+
+    #[pg_test]
+    fn test_suggest() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let distributions = vec![
+            (
+                "booster".to_string(),
+                Distribution::parse("booster", &json!(["gbtree", "dart"])),
+            ),
+            (
+                "max_depth".to_string(),
+                Distribution::parse("max_depth", &json!({"randint": [2, 12]})),
+            ),
+        ];
+
+        // The best trials are the closest to a depth of 4, but suggestions stay in the search space.
+        let trials: Vec<(Vec<serde_json::Value>, f32)> = (2..=12)
+            .map(|depth| {
+                let booster = if depth % 2 == 0 { "gbtree" } else { "dart" };
+                (
+                    vec![json!(booster), json!(depth)],
+                    -(depth as f32 - 4.).abs(),
+                )
+            })
+            .collect();
+        for _ in 0..10 {
+            let suggestion = suggest(&distributions, &trials, &mut rng);
+            assert!(["gbtree", "dart"].contains(&suggestion[0].as_str().unwrap()));
+            assert!((2..=12).contains(&suggestion[1].as_i64().unwrap()));
         }
     }
 }
@@ -1,17 +1,62 @@
+use parking_lot::Mutex;
 use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::fmt::{Display, Error, Formatter};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Error, Formatter, Write};
 use std::str::FromStr;
+use std::sync::Arc;
 
+use itertools::Itertools;
+use once_cell::sync::{Lazy, OnceCell};
 use pgx::*;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::orm::Dataset;
 use crate::orm::Sampling;
+use crate::orm::Scale;
 use crate::orm::Status;
 
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+/// How a column is converted into features.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum Encode {
+    /// Numbers and booleans are used as is.
+    #[default]
+    native,
+    /// Each category is a distinct integer, starting at 1.
+    ordinal,
+    /// Each category is a distinct feature set to 1.
+    one_hot,
+    /// Each category is the mean of the label for that category.
+    target,
+}
+
+/// How NULLs in a column are replaced.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum Impute {
+    /// Raise an error, this is the default for numeric columns.
+    error,
+    /// Leave the row out of the dataset.
+    drop,
+    mean,
+    median,
+    mode,
+    /// Use the `fill_value` of the column.
+    constant,
+}
+
+/// Per column preprocessing, passed to `pgml.train` as `preprocess => '{"column": {...}}'`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preprocessor {
+    encode: Option<Encode>,
+    impute: Option<Impute>,
+    fill_value: Option<f32>,
+    scale: Option<Scale>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Column {
     name: String,
     pg_type: String,
@@ -19,8 +64,26 @@ pub struct Column {
     label: bool,
     position: usize,
     size: usize,
+    #[serde(default)]
+    encode: Encode,
+    /// The encoded value of each category seen in the training data.
+    #[serde(default)]
+    categories: BTreeMap<String, f32>,
+    /// The encoded value of categories that weren't seen in the training data.
+    #[serde(default)]
+    unknown: f32,
+    #[serde(default)]
+    impute: Option<Impute>,
+    /// The value NULLs are replaced with, learned from the training data.
+    #[serde(default)]
+    fill: f32,
+    /// Scales are fitted when a model is trained, and saved with the model.
+    #[serde(default)]
+    scale: Scale,
 }
 
+impl Eq for Column {}
+
 impl Column {
     fn quoted_name(&self) -> String {
         format!(r#""{}""#, self.name)
@@ -33,6 +96,147 @@ impl Column {
             _ => self.quoted_name(),
         }
     }
+
+    /// The value to replace a NULL with, or None if the row should be dropped.
+    fn impute(&self) -> Option<f32> {
+        match self.impute {
+            Some(Impute::drop) => None,
+            Some(Impute::mean | Impute::median | Impute::mode | Impute::constant) => {
+                Some(self.fill)
+            }
+            Some(Impute::error) | None => error!(
+                "Column `{}` contains NULL. Pass an `impute` strategy for it in `preprocess`, e.g. `preprocess => '{{\"{}\": {{\"impute\": \"mean\"}}}}'`",
+                self.name, self.name
+            ),
+        }
+    }
+
+    /// The value to replace a NULL feature with, after it was encoded.
+    fn impute_feature(&self) -> f32 {
+        if self.is_categorical() && self.impute.is_none() {
+            return match self.encode {
+                Encode::one_hot => 0.,
+                _ => self.unknown,
+            };
+        }
+
+        self.impute().unwrap_or_else(|| {
+            error!(
+                "Column `{}` is NULL, and rows with NULLs were dropped in training",
+                self.name
+            )
+        })
+    }
+
+    /// The names of the features this column is flattened into. Array elements are named
+    /// by their 1 indexed position, and one hot encoded categories by their value.
+    fn feature_names(&self) -> Vec<String> {
+        if self.is_categorical() && self.encode == Encode::one_hot {
+            let mut names = vec![String::new(); self.size];
+            for (category, i) in &self.categories {
+                names[*i as usize] = format!("{}_{}", self.name, category);
+            }
+            names
+        } else if self.pg_type.ends_with("[]") {
+            (1..=self.size)
+                .map(|i| format!("{}[{}]", self.name, i))
+                .collect()
+        } else {
+            vec![self.name.clone()]
+        }
+    }
+
+    fn is_categorical(&self) -> bool {
+        matches!(self.pg_type.as_str(), "text" | "varchar" | "bpchar")
+    }
+
+    /// Encode a category into features, NULL is treated as an unknown category.
+    fn push_category(&self, value: Option<&str>, features: &mut Vec<f32>) {
+        let encoded = value.and_then(|value| self.categories.get(value));
+        match self.encode {
+            Encode::ordinal | Encode::target => features.push(*encoded.unwrap_or(&self.unknown)),
+            Encode::one_hot => {
+                let start = features.len();
+                features.resize(start + self.size, 0.);
+                if let Some(i) = encoded {
+                    features[start + *i as usize] = 1.;
+                }
+            }
+            Encode::native => error!(
+                "Column `{}` of type `{}` must be encoded",
+                self.name, self.pg_type
+            ),
+        }
+    }
+
+    /// Flatten the JSON value of this column in a row into features.
+    fn push_json_features(&self, value: &Value, features: &mut Vec<f32>) {
+        if self.is_categorical() {
+            match value {
+                Value::String(value) => self.push_category(Some(value), features),
+                // Text columns can only be imputed with `error` or `drop`.
+                Value::Null if self.impute.is_some() => error!("Column `{}` is NULL", self.name),
+                Value::Null => self.push_category(None, features),
+                _ => error!(
+                    "Column `{}` should be of type `{}`, got: {}",
+                    self.name, self.pg_type, value
+                ),
+            }
+            return;
+        }
+
+        if value.is_null() {
+            match self.impute() {
+                Some(fill) => features.extend(std::iter::repeat(fill).take(self.size)),
+                None => error!(
+                    "Column `{}` is NULL, and rows with NULLs were dropped in training",
+                    self.name
+                ),
+            }
+            return;
+        }
+
+        let start = features.len();
+        let valid = match self.pg_type.strip_suffix("[]") {
+            Some(pg_type) => value.is_array() && push_json_array(pg_type, value, features),
+            None => push_json_scalar(&self.pg_type, value, features),
+        };
+        if !valid {
+            error!(
+                "Column `{}` should be of type `{}`, got: {}",
+                self.name, self.pg_type, value
+            );
+        }
+
+        let size = features.len() - start;
+        if size != self.size {
+            error!(
+                "Column `{}` should have {} elements, got {}",
+                self.name, self.size, size
+            );
+        }
+    }
+}
+
+fn push_json_scalar(pg_type: &str, value: &Value, features: &mut Vec<f32>) -> bool {
+    match (pg_type, value) {
+        ("bool", Value::Bool(value)) => features.push(*value as u8 as f32),
+        ("int2" | "int4" | "int8" | "float4" | "float8", Value::Number(value)) => {
+            features.push(value.as_f64().unwrap() as f32)
+        }
+        _ => return false,
+    }
+    true
+}
+
+// Multidimensional arrays are nested in JSON.
+fn push_json_array(pg_type: &str, value: &Value, features: &mut Vec<f32>) -> bool {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .all(|value| push_json_array(pg_type, value, features)),
+        value => push_json_scalar(pg_type, value, features),
+    }
 }
 
 impl PartialOrd for Column {
@@ -47,6 +251,9 @@ impl Ord for Column {
     }
 }
 
+static SNAPSHOTS_BY_MODEL_ID: Lazy<Mutex<HashMap<i64, Arc<Snapshot>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
 #[derive(Debug)]
 pub struct Snapshot {
     pub id: i64,
@@ -59,6 +266,8 @@ pub struct Snapshot {
     pub analysis: Option<JsonB>,
     pub created_at: Timestamp,
     pub updated_at: Timestamp,
+    /// The feature columns, parsed from `columns` once per snapshot.
+    feature_columns: OnceCell<Vec<Column>>,
 }
 
 impl Display for Snapshot {
@@ -110,6 +319,7 @@ impl Snapshot {
                     analysis: result.get_datum(8),
                     created_at: result.get_datum(9).unwrap(),
                     updated_at: result.get_datum(10).unwrap(),
+                    feature_columns: OnceCell::new(),
                 });
             }
             Ok(Some(1))
@@ -117,12 +327,89 @@ impl Snapshot {
         snapshot
     }
 
+    /// Fetch the snapshot a model was trained on, cached in process memory.
+    pub fn find_by_model_id(model_id: i64) -> Arc<Snapshot> {
+        {
+            let snapshots = SNAPSHOTS_BY_MODEL_ID.lock();
+            if let Some(snapshot) = snapshots.get(&model_id) {
+                return snapshot.clone();
+            }
+        }
+
+        let mut snapshot = None;
+        Spi::connect(|client| {
+            let result = client
+                .select(
+                    "SELECT
+                        snapshots.id,
+                        snapshots.relation_name,
+                        snapshots.y_column_name,
+                        snapshots.test_size,
+                        snapshots.test_sampling::TEXT,
+                        snapshots.status::TEXT,
+                        snapshots.columns,
+                        snapshots.analysis,
+                        snapshots.created_at,
+                        snapshots.updated_at 
+                    FROM pgml.snapshots 
+                    JOIN pgml.models
+                      ON models.snapshot_id = snapshots.id
+                    WHERE models.id = $1 
+                    LIMIT 1;
+                    ",
+                    Some(1),
+                    Some(vec![(PgBuiltInOids::INT8OID.oid(), model_id.into_datum())]),
+                )
+                .first();
+            if !result.is_empty() {
+                snapshot = Some(Snapshot {
+                    id: result.get_datum(1).unwrap(),
+                    relation_name: result.get_datum(2).unwrap(),
+                    y_column_name: result.get_datum(3).unwrap(),
+                    test_size: result.get_datum(4).unwrap(),
+                    test_sampling: Sampling::from_str(result.get_datum(5).unwrap()).unwrap(),
+                    status: Status::from_str(result.get_datum(6).unwrap()).unwrap(),
+                    columns: result.get_datum(7),
+                    analysis: result.get_datum(8),
+                    created_at: result.get_datum(9).unwrap(),
+                    updated_at: result.get_datum(10).unwrap(),
+                    feature_columns: OnceCell::new(),
+                });
+            }
+            Ok(Some(1))
+        });
+
+        let snapshot = Arc::new(snapshot.unwrap_or_else(|| {
+            error!(
+                "Snapshot for model pgml.models.id = {} does not exist, the model store has been corrupted.",
+                model_id
+            )
+        }));
+
+        let mut snapshots = SNAPSHOTS_BY_MODEL_ID.lock();
+        snapshots.insert(model_id, snapshot.clone());
+
+        snapshot
+    }
+
     pub fn create(
         relation_name: &str,
-        y_column_name: &str,
+        y_column_name: Vec<String>,
         test_size: f32,
         test_sampling: Sampling,
+        time_column_name: Option<&str>,
+        preprocess: JsonB,
     ) -> Snapshot {
+        match (test_sampling, time_column_name) {
+            (Sampling::time, None) => {
+                error!("You must pass a `time_column_name` to order the snapshot by, when `test_sampling` is `time`.")
+            }
+            (Sampling::time, Some(_)) | (_, None) => (),
+            (_, Some(_)) => {
+                error!("`time_column_name` is only used when `test_sampling` is `time`.")
+            }
+        }
+
         let mut snapshot: Option<Snapshot> = None;
         let status = Status::in_progress;
         Spi::connect(|client| {
@@ -130,7 +417,7 @@ impl Snapshot {
                 Some(1),
                 Some(vec![
                     (PgBuiltInOids::TEXTOID.oid(), relation_name.into_datum()),
-                    (PgBuiltInOids::TEXTARRAYOID.oid(), vec![y_column_name].into_datum()),
+                    (PgBuiltInOids::TEXTARRAYOID.oid(), y_column_name.into_datum()),
                     (PgBuiltInOids::FLOAT4OID.oid(), test_size.into_datum()),
                     (PgBuiltInOids::TEXTOID.oid(), test_sampling.to_string().into_datum()),
                     (PgBuiltInOids::TEXTOID.oid(), status.to_string().into_datum()),
@@ -147,16 +434,33 @@ impl Snapshot {
                 analysis: None, // 8
                 created_at: result.get_datum(9).unwrap(),
                 updated_at: result.get_datum(10).unwrap(),
+                feature_columns: OnceCell::new(),
             };
             let mut sql = format!(
                 r#"CREATE TABLE "pgml"."snapshot_{}" AS SELECT * FROM {}"#,
                 s.id, s.relation_name
             );
-            if s.test_sampling == Sampling::random {
-                sql += " ORDER BY random()";
+            match s.test_sampling {
+                Sampling::random => sql += " ORDER BY random()",
+                // Rows are ordered by their percentile within their class, so
+                // the classes are interleaved evenly across the whole snapshot.
+                Sampling::stratified => {
+                    let y_column_name = s.y_column_name.first().unwrap_or_else(|| {
+                        error!("Stratified sampling requires a `y_column_name` to stratify by.")
+                    });
+                    let _ = write!(
+                        sql,
+                        r#" ORDER BY (row_number() OVER (PARTITION BY "{y_column_name}" ORDER BY random()))::FLOAT8 / count(*) OVER (PARTITION BY "{y_column_name}"), random()"#
+                    );
+                }
+                // The most recent rows are the test set.
+                Sampling::time => {
+                    let _ = write!(sql, r#" ORDER BY "{}" ASC"#, time_column_name.unwrap());
+                }
+                Sampling::last => (),
             }
             client.select(&sql, None, None);
-            s.analyze();
+            s.analyze(time_column_name, preprocess);
             snapshot = Some(s);
             Ok(Some(1))
         });
@@ -165,7 +469,12 @@ impl Snapshot {
     }
 
     #[allow(clippy::format_push_string)]
-    fn analyze(&mut self) {
+    fn analyze(&mut self, time_column_name: Option<&str>, preprocess: JsonB) {
+        let preprocess = match preprocess.0 {
+            Value::Object(preprocess) => preprocess,
+            _ => error!("`preprocess` must be a JSON object of column names to preprocessors"),
+        };
+
         Spi::connect(|client| {
             let parts = self
                 .relation_name
@@ -181,6 +490,7 @@ impl Snapshot {
                 ),
             };
             let mut columns: Vec<Column> = Vec::new();
+            let mut found_time_column = false;
             client.select("SELECT column_name::TEXT, udt_name::TEXT, is_nullable::BOOLEAN, ordinal_position::INTEGER FROM information_schema.columns WHERE table_schema = $1 AND table_name = $2 ORDER BY ordinal_position ASC",
                 None,
                 Some(vec![
@@ -189,10 +499,20 @@ impl Snapshot {
                 ]))
             .for_each(|row| {
                 let name = row[1].value::<String>().unwrap();
+                // The time column only orders the snapshot, it's not a feature.
+                if Some(name.as_str()) == time_column_name {
+                    found_time_column = true;
+                    return;
+                }
                 let mut pg_type = row[2].value::<String>().unwrap();
                 if pg_type.starts_with('_') {
                     pg_type = pg_type[1..].to_string() + "[]";
                 }
+                if !is_supported_type(&pg_type) {
+                    error!(
+                        "Column `{name}` of type `{pg_type}` can't be snapshotted. Only booleans, integers, floats, their arrays and text are supported, use a view to convert it, e.g. `extract(epoch FROM \"{name}\")` for dates and timestamps, or `\"{name}\"::TEXT` for enums."
+                    );
+                }
                 let nullable = row[3].value::<bool>().unwrap();
                 let position = row[4].value::<i32>().unwrap() as usize;
                 let label = self.y_column_name.contains(&name);
@@ -204,107 +524,101 @@ impl Snapshot {
                         label,
                         position,
                         size: 1,
+                        encode: Encode::native,
+                        categories: BTreeMap::new(),
+                        unknown: 0.,
+                        impute: None,
+                        fill: 0.,
+                        scale: Scale::preserve,
                     }
                 );
             });
 
-            for column in &self.y_column_name {
-                if !columns.iter().any(|c| c.label && &c.name == column) {
-                    error!(
-                        "Column `{}` not found. Did you pass the correct `y_column_name`?",
-                        column
-                    )
+            for name in preprocess.keys() {
+                if !columns.iter().any(|c| &c.name == name) {
+                    error!("Column `{}` not found. Did you pass the correct column names to `preprocess`?", name);
                 }
             }
 
-            // We have to pull this analysis data into Rust as opposed to using Postgres
-            // json_build_object(...), because Postgres functions have a limit of 100 arguments.
-            // Any table that has more than 10 columns will exceed the Postgres limit since we
-            // calculate 10 statistics per column.
-            let mut stats = vec![r#"count(*)::FLOAT4 AS "samples""#.to_string()];
-            let mut fields = vec!["samples".to_string()];
-            let mut laterals = String::new();
-            for column in &columns {
-                match column.pg_type.as_str() {
-                    "bool" | "int2" | "int4" | "int8" | "float4" | "float8" => {
-                        let name = &column.name;
-                        let stats_safe_name = column.stats_safe_name();
-                        stats.push(format!(r#"min({stats_safe_name})::FLOAT4 AS "{name}_min""#));
-                        stats.push(format!(r#"max({stats_safe_name})::FLOAT4 AS "{name}_max""#));
-                        stats.push(format!(
-                            r#"avg({stats_safe_name})::FLOAT4 AS "{name}_mean""#
-                        ));
-                        stats.push(format!(
-                            r#"stddev({stats_safe_name})::FLOAT4 AS "{name}_stddev""#
-                        ));
-                        stats.push(format!(r#"percentile_disc(0.25) within group (order by {stats_safe_name})::FLOAT4 AS "{name}_p25""#));
-                        stats.push(format!(r#"percentile_disc(0.5) within group (order by {stats_safe_name})::FLOAT4 AS "{name}_p50""#));
-                        stats.push(format!(r#"percentile_disc(0.75) within group (order by {stats_safe_name})::FLOAT4 AS "{name}_p75""#));
-                        stats.push(format!(
-                            r#"count({stats_safe_name})::FLOAT4 AS "{name}_count""#
-                        ));
-                        stats.push(format!(
-                            r#"count(distinct {stats_safe_name})::FLOAT4 AS "{name}_distinct""#
-                        ));
-                        stats.push(format!(
-                            r#"sum(({stats_safe_name} IS NULL)::INT)::FLOAT4 AS "{name}_nulls""#
-                        ));
-                        fields.push(format!("{name}_min"));
-                        fields.push(format!("{name}_max"));
-                        fields.push(format!("{name}_mean"));
-                        fields.push(format!("{name}_stddev"));
-                        fields.push(format!("{name}_p25"));
-                        fields.push(format!("{name}_p50"));
-                        fields.push(format!("{name}_p75"));
-                        fields.push(format!("{name}_count"));
-                        fields.push(format!("{name}_distinct"));
-                        fields.push(format!("{name}_nulls"));
-                    }
-                    "bool[]" | "int2[]" | "int4[]" | "int8[]" | "float4[]" | "float8[]" => {
-                        let name = &column.name;
-                        let stats_safe_name = column.stats_safe_name();
-                        let quoted_name = column.quoted_name();
-                        let unnested_column = format!(r#""unnested_{}""#, name);
-                        let lateral_table = format!(r#""{}_lateral""#, name);
-                        stats.push(format!(
-                            r#"max(array_ndims({quoted_name}))::FLOAT4 AS "{name}_dims""#
-                        ));
-                        stats.push(format!(
-                            r#"max(cardinality({quoted_name}))::FLOAT4 AS "{name}_cardinality""#
-                        ));
-                        stats.push(format!(
-                            r#"min({lateral_table}.{unnested_column})::FLOAT4 AS "{name}_min""#
-                        ));
-                        stats.push(format!(
-                            r#"max({lateral_table}.{unnested_column})::FLOAT4 AS "{name}_max""#
-                        ));
-                        fields.push(format!("{name}_dims"));
-                        fields.push(format!("{name}_cardinality"));
-                        fields.push(format!("{name}_min"));
-                        fields.push(format!("{name}_max"));
-                        laterals += &format!(", LATERAL (SELECT unnest({stats_safe_name}) AS {unnested_column}) {lateral_table}");
+            for column in &mut columns {
+                let preprocessor: Preprocessor = match preprocess.get(&column.name) {
+                    Some(preprocessor) => serde_json::from_value(preprocessor.clone())
+                        .unwrap_or_else(|e| {
+                            error!("Invalid `preprocess` for column `{}`: {}", column.name, e)
+                        }),
+                    None => Preprocessor::default(),
+                };
+
+                column.encode = match (column.is_categorical(), preprocessor.encode) {
+                    (true, _) if column.label => error!(
+                        "Column `{}` of type `{}` can't be a label, labels must be numeric",
+                        column.name, column.pg_type
+                    ),
+                    (true, None) => Encode::ordinal,
+                    (true, Some(Encode::native)) => error!(
+                        "Column `{}` of type `{}` must be encoded with `ordinal`, `one_hot` or `target`",
+                        column.name, column.pg_type
+                    ),
+                    (false, None | Some(Encode::native)) => Encode::native,
+                    (false, Some(encode)) => error!(
+                        "Column `{}` of type `{}` can't be encoded with `{:?}`, only text columns are categorical",
+                        column.name, column.pg_type, encode
+                    ),
+                    (true, Some(encode)) => encode,
+                };
+
+                column.impute = preprocessor.impute;
+                match column.impute {
+                    Some(Impute::mean | Impute::median | Impute::mode | Impute::constant)
+                        if column.label || column.is_categorical() =>
+                    {
+                        error!(
+                            "Column `{}` can only be imputed with `error` or `drop`",
+                            column.name
+                        )
                     }
-                    &_ => {
-                        error!("unhandled type: `{}` for `{}`", column.pg_type, column.name);
+                    Some(Impute::constant) => {
+                        column.fill = preprocessor.fill_value.unwrap_or_else(|| {
+                            error!(
+                                "Column `{}` is imputed with a `constant`, pass its `fill_value` in `preprocess`",
+                                column.name
+                            )
+                        })
                     }
+                    _ => (),
                 }
+
+                column.scale = match preprocessor.scale {
+                    Some(scale) if column.label && scale != Scale::preserve => error!(
+                        "Column `{}` is a label, only features can be scaled",
+                        column.name
+                    ),
+                    Some(scale) if column.is_categorical() && scale != Scale::preserve => error!(
+                        "Column `{}` is categorical, only numeric features can be scaled",
+                        column.name
+                    ),
+                    Some(scale) => scale,
+                    None => Scale::preserve,
+                };
             }
 
-            let stats = stats.join(", ");
-            let sql = format!(
-                r#"SELECT {stats} FROM "pgml"."snapshot_{}" {laterals}"#,
-                self.id
-            );
-            let result = client.select(&sql, Some(1), None).first();
-            let mut analysis = HashMap::new();
-            for (i, field) in fields.iter().enumerate() {
-                analysis.insert(
-                    field.to_owned(),
-                    result
-                        .get_datum::<f32>((i + 1).try_into().unwrap())
-                        .unwrap(),
+            if time_column_name.is_some() && !found_time_column {
+                error!(
+                    "Column `{}` not found. Did you pass the correct `time_column_name`?",
+                    time_column_name.unwrap()
                 );
             }
+
+            for column in &self.y_column_name {
+                if !columns.iter().any(|c| c.label && &c.name == column) {
+                    error!(
+                        "Column `{}` not found. Did you pass the correct `y_column_name`?",
+                        column
+                    )
+                }
+            }
+
+            let analysis = analyze_columns(&client, &columns, &self.snapshot_name());
             for column in &mut columns {
                 let cardinality = format!("{}_cardinality", column.name);
                 match analysis.get(&cardinality) {
@@ -312,10 +626,101 @@ impl Snapshot {
                     None => (),
                 }
             }
+
+            // Categories are learned from the training rows only, so the test rows
+            // don't leak into the target encoding. The snapshot was written in its
+            // sampling order, so its physical order is the split order.
+            let num_rows = *analysis.get("samples").unwrap() as usize;
+            let num_train_rows = num_rows - self.num_test_rows(num_rows);
+            let label = columns.iter().find(|c| c.label).cloned();
+            let train = format!(
+                "(SELECT * FROM {} ORDER BY ctid LIMIT {num_train_rows}) train",
+                self.snapshot_name()
+            );
+            for column in columns.iter_mut().filter(|c| c.is_categorical()) {
+                let quoted_name = column.quoted_name();
+                match column.encode {
+                    Encode::ordinal | Encode::one_hot => {
+                        let start = if column.encode == Encode::ordinal {
+                            1
+                        } else {
+                            0
+                        };
+                        client.select(
+                            &format!("SELECT DISTINCT {quoted_name}::TEXT FROM {train} WHERE {quoted_name} IS NOT NULL ORDER BY 1"),
+                            None,
+                            None,
+                        )
+                        .enumerate()
+                        .for_each(|(i, row)| {
+                            column.categories.insert(row[1].value::<String>().unwrap(), (start + i) as f32);
+                        });
+                        if column.encode == Encode::one_hot {
+                            column.size = column.categories.len();
+                        }
+                    }
+                    Encode::target => {
+                        let label = match &label {
+                            Some(label) if self.y_column_name.len() == 1 && !label.pg_type.ends_with("[]") => label.stats_safe_name(),
+                            _ => error!("Column `{}` can't be target encoded, target encoding requires a single scalar `y_column_name`", column.name),
+                        };
+                        client.select(
+                            &format!("SELECT {quoted_name}::TEXT, avg({label})::FLOAT4 FROM {train} WHERE {quoted_name} IS NOT NULL GROUP BY 1 ORDER BY 1"),
+                            None,
+                            None,
+                        )
+                        .for_each(|row| {
+                            column.categories.insert(row[1].value::<String>().unwrap(), row[2].value::<f32>().unwrap());
+                        });
+                        column.unknown = client
+                            .select(
+                                &format!("SELECT avg({label})::FLOAT4 FROM {train}"),
+                                Some(1),
+                                None,
+                            )
+                            .first()
+                            .get_datum::<f32>(1)
+                            .unwrap_or(0.);
+                    }
+                    Encode::native => unreachable!(),
+                }
+            }
+
+            // Fill values are also learned from the training rows only.
+            for column in &mut columns {
+                let aggregate = match column.impute {
+                    Some(Impute::mean) => "avg(value)",
+                    Some(Impute::median) => "percentile_cont(0.5) WITHIN GROUP (ORDER BY value)",
+                    Some(Impute::mode) => "mode() WITHIN GROUP (ORDER BY value)",
+                    _ => continue,
+                };
+                let stats_safe_name = column.stats_safe_name();
+                let values = if column.pg_type.ends_with("[]") {
+                    format!("SELECT unnest({stats_safe_name}) AS value FROM {train}")
+                } else {
+                    format!("SELECT {stats_safe_name} AS value FROM {train}")
+                };
+                column.fill = client
+                    .select(
+                        &format!("SELECT {aggregate}::FLOAT4 FROM ({values}) values"),
+                        Some(1),
+                        None,
+                    )
+                    .first()
+                    .get_datum::<f32>(1)
+                    .unwrap_or_else(|| {
+                        error!(
+                            "Column `{}` has no values to impute NULLs from",
+                            column.name
+                        )
+                    });
+            }
+
             let analysis_datum = JsonB(json!(analysis));
             let column_datum = JsonB(json!(columns));
             self.analysis = Some(JsonB(json!(analysis)));
             self.columns = Some(JsonB(json!(columns)));
+            self.feature_columns = OnceCell::new();
             client.select("UPDATE pgml.snapshots SET status = $1::pgml.status, analysis = $2, columns = $3 WHERE id = $4", Some(1), Some(vec![
                 (PgBuiltInOids::TEXTOID.oid(), Status::successful.to_string().into_datum()),
                 (PgBuiltInOids::JSONBOID.oid(), analysis_datum.into_datum()),
@@ -334,7 +739,7 @@ impl Snapshot {
             let mut columns: Vec<Column> = serde_json::from_value(json).unwrap();
             columns.sort();
             let sql = format!(
-                "SELECT {} FROM {}",
+                "SELECT {} FROM {} ORDER BY ctid",
                 columns
                     .iter()
                     .map(|c| c.quoted_name())
@@ -357,11 +762,7 @@ impl Snapshot {
             let result = client.select(&sql, None, None);
             let num_rows = result.len();
 
-            let num_test_rows = if self.test_size > 1.0 {
-                self.test_size as usize
-            } else {
-                (num_rows as f32 * self.test_size).round() as usize
-            };
+            let num_test_rows = self.num_test_rows(num_rows);
 
             let num_train_rows = num_rows - num_test_rows;
             if num_train_rows == 0 {
@@ -376,85 +777,140 @@ impl Snapshot {
             let mut x_test: Vec<f32> = Vec::with_capacity(num_test_rows * num_features);
             let mut y_test: Vec<f32> = Vec::with_capacity(num_test_rows * num_labels);
 
+            // Rows with NULLs may be dropped, so the split is counted as rows are kept.
+            let mut x_row: Vec<f32> = Vec::with_capacity(num_features);
+            let mut y_row: Vec<f32> = Vec::with_capacity(num_labels);
+            let mut num_dropped_train_rows = 0;
+            let mut num_dropped_test_rows = 0;
+
             // result: SpiTupleTable
             // row: SpiHeapTupleData
             // row[i]: SpiHeapTupleDataEntry
             result.enumerate().for_each(|(i, row)| {
-                for column in &columns {
-                    let vector = if column.label {
-                        if i < num_train_rows {
-                            &mut y_train
-                        } else {
-                            &mut y_test
-                        }
-                    } else if i < num_train_rows {
-                        &mut x_train
-                    } else {
-                        &mut x_test
-                    };
-                    match column.pg_type.as_str() {
-                        "bool" => {
-                            vector.push(row[column.position].value::<bool>().unwrap() as u8 as f32)
-                        }
-                        "bool[]" => {
-                            for j in row[column.position].value::<Vec<bool>>().unwrap() {
-                                vector.push(j as u8 as f32)
-                            }
-                        }
-                        "int2" => vector.push(row[column.position].value::<i16>().unwrap() as f32),
-                        "int2[]" => {
-                            for j in row[column.position].value::<Vec<i16>>().unwrap() {
-                                vector.push(j as f32)
-                            }
-                        }
-                        "int4" => vector.push(row[column.position].value::<i32>().unwrap() as f32),
-                        "int4[]" => {
-                            for j in row[column.position].value::<Vec<i32>>().unwrap() {
-                                vector.push(j as f32)
-                            }
-                        }
-                        "int8" => vector.push(row[column.position].value::<i64>().unwrap() as f32),
-                        "int8[]" => {
-                            for j in row[column.position].value::<Vec<i64>>().unwrap() {
-                                vector.push(j as f32)
-                            }
-                        }
-                        "float4" => {
-                            vector.push(row[column.position].value::<f32>().unwrap() as f32)
-                        }
-                        "float4[]" => {
-                            for j in row[column.position].value::<Vec<f32>>().unwrap() {
-                                vector.push(j as f32)
-                            }
-                        }
-                        "float8" => {
-                            vector.push(row[column.position].value::<f64>().unwrap() as f32)
-                        }
-                        "float8[]" => {
-                            for j in row[column.position].value::<Vec<f64>>().unwrap() {
-                                vector.push(j as f32)
+                x_row.clear();
+                y_row.clear();
+                let mut drop = false;
+                for (position, column) in columns.iter().enumerate() {
+                    // Postgres SPI tuples are 1 indexed.
+                    let position = position + 1;
+                    let vector = if column.label { &mut y_row } else { &mut x_row };
+                    let present = match column.pg_type.as_str() {
+                        "bool" => row[position]
+                            .value::<bool>()
+                            .map(|j| vector.push(j as u8 as f32))
+                            .is_some(),
+                        "bool[]" => row[position]
+                            .value::<Vec<bool>>()
+                            .map(|j| vector.extend(j.into_iter().map(|j| j as u8 as f32)))
+                            .is_some(),
+                        "int2" => row[position]
+                            .value::<i16>()
+                            .map(|j| vector.push(j as f32))
+                            .is_some(),
+                        "int2[]" => row[position]
+                            .value::<Vec<i16>>()
+                            .map(|j| vector.extend(j.into_iter().map(|j| j as f32)))
+                            .is_some(),
+                        "int4" => row[position]
+                            .value::<i32>()
+                            .map(|j| vector.push(j as f32))
+                            .is_some(),
+                        "int4[]" => row[position]
+                            .value::<Vec<i32>>()
+                            .map(|j| vector.extend(j.into_iter().map(|j| j as f32)))
+                            .is_some(),
+                        "int8" => row[position]
+                            .value::<i64>()
+                            .map(|j| vector.push(j as f32))
+                            .is_some(),
+                        "int8[]" => row[position]
+                            .value::<Vec<i64>>()
+                            .map(|j| vector.extend(j.into_iter().map(|j| j as f32)))
+                            .is_some(),
+                        "float4" => row[position]
+                            .value::<f32>()
+                            .map(|j| vector.push(j))
+                            .is_some(),
+                        "float4[]" => row[position]
+                            .value::<Vec<f32>>()
+                            .map(|j| vector.extend(j))
+                            .is_some(),
+                        "float8" => row[position]
+                            .value::<f64>()
+                            .map(|j| vector.push(j as f32))
+                            .is_some(),
+                        "float8[]" => row[position]
+                            .value::<Vec<f64>>()
+                            .map(|j| vector.extend(j.into_iter().map(|j| j as f32)))
+                            .is_some(),
+                        "text" | "varchar" | "bpchar" => {
+                            let value = row[position].value::<String>();
+                            // NULL is an unknown category, unless it's explicitly imputed.
+                            if value.is_none() && column.impute.is_some() {
+                                false
+                            } else {
+                                column.push_category(value.as_deref(), vector);
+                                true
                             }
                         }
                         _ => error!("unhandled type: `{}` for `{}`", column.pg_type, column.name),
+                    };
+
+                    if !present {
+                        match column.impute() {
+                            Some(fill) => vector.extend(std::iter::repeat(fill).take(column.size)),
+                            None => drop = true,
+                        }
                     }
                 }
+
+                if drop {
+                    if i < num_train_rows {
+                        num_dropped_train_rows += 1;
+                    } else {
+                        num_dropped_test_rows += 1;
+                    }
+                } else if i < num_train_rows {
+                    x_train.extend_from_slice(&x_row);
+                    y_train.extend_from_slice(&y_row);
+                } else {
+                    x_test.extend_from_slice(&x_row);
+                    y_test.extend_from_slice(&y_row);
+                }
             });
 
+            let num_train_rows = num_train_rows - num_dropped_train_rows;
+            let num_test_rows = num_test_rows - num_dropped_test_rows;
+            let num_rows = num_train_rows + num_test_rows;
+            if num_dropped_train_rows + num_dropped_test_rows > 0 {
+                info!(
+                    "Dropped {} rows with NULLs, {} samples remain.",
+                    num_dropped_train_rows + num_dropped_test_rows,
+                    num_rows
+                );
+            }
+            if num_train_rows == 0 {
+                error!("There are no training samples left after dropping rows with NULLs.");
+            }
+
             log!(
                 "Snapshot analysis: {}",
                 serde_json::to_string(&self.analysis).unwrap()
             );
 
-            let stat = format!("{}_distinct", self.y_column_name[0]);
-            let num_distinct_labels = self
-                .analysis
-                .as_ref()
-                .unwrap()
-                .0
-                .get(stat)
-                .unwrap()
-                .as_f64()
-                .unwrap() as usize;
+            // Unsupervised snapshots (e.g. clustering) don't have labels.
+            let num_distinct_labels = match self.y_column_name.first() {
+                Some(y_column_name) => self
+                    .analysis
+                    .as_ref()
+                    .unwrap()
+                    .0
+                    .get(format!("{}_distinct", y_column_name))
+                    .unwrap()
+                    .as_f64()
+                    .unwrap() as usize,
+                None => 0,
+            };
 
             data = Some(Dataset {
                 x_train,
@@ -467,6 +923,7 @@ impl Snapshot {
                 num_test_rows,
                 num_train_rows,
                 num_distinct_labels,
+                sampling: self.test_sampling,
             });
 
             Ok(Some(()))
@@ -479,7 +936,425 @@ impl Snapshot {
         data
     }
 
+    fn num_test_rows(&self, num_rows: usize) -> usize {
+        if self.test_size > 1.0 {
+            self.test_size as usize
+        } else {
+            (num_rows as f32 * self.test_size).round() as usize
+        }
+    }
+
+    /// The feature columns, in the order the estimator expects them.
+    fn feature_columns(&self) -> &[Column] {
+        self.feature_columns.get_or_init(|| {
+            let json = self.columns.as_ref().unwrap().0.clone();
+            let mut columns: Vec<Column> = serde_json::from_value(json).unwrap();
+            columns.retain(|column| !column.label);
+            columns.sort();
+            columns
+        })
+    }
+
+    /// Map the fields of a row to features by their column names.
+    /// Fields that aren't features, like the label, are ignored.
+    pub fn features_from_row(&self, row: &serde_json::Map<String, Value>) -> Vec<f32> {
+        let mut features = Vec::new();
+        for column in self.feature_columns() {
+            match row.get(&column.name) {
+                Some(value) => column.push_json_features(value, &mut features),
+                None => error!(
+                    "Column `{}` is missing from the row. The model was trained on a snapshot of `{}`.",
+                    column.name, self.relation_name
+                ),
+            }
+        }
+        features
+    }
+
+    /// The name of each feature, in the order the estimator expects them.
+    pub fn feature_names(&self) -> Vec<String> {
+        self.feature_columns()
+            .iter()
+            .flat_map(|column| column.feature_names())
+            .collect()
+    }
+
+    /// The number of features in each row, columns may be encoded as several.
+    pub fn num_features(&self) -> usize {
+        self.feature_columns()
+            .iter()
+            .map(|column| column.size)
+            .sum()
+    }
+
+    /// The scale of each feature, in the order the estimator expects them.
+    pub fn feature_scales(&self) -> Vec<Scale> {
+        self.feature_columns()
+            .iter()
+            .flat_map(|column| std::iter::repeat(column.scale).take(column.size))
+            .collect()
+    }
+
+    /// Replace NULLs in rows of features with the fill values of their columns.
+    pub fn impute(&self, features: &[Option<f32>]) -> Vec<f32> {
+        let columns = self.feature_columns();
+        let columns: Vec<&Column> = columns
+            .iter()
+            .flat_map(|column| std::iter::repeat(column).take(column.size))
+            .collect();
+        if columns.is_empty() || features.len() % columns.len() != 0 {
+            error!(
+                "Expected rows of {} features, got {} features",
+                columns.len(),
+                features.len()
+            );
+        }
+
+        features
+            .iter()
+            .zip(columns.iter().cycle())
+            .map(|(feature, column)| match feature {
+                Some(feature) => *feature,
+                None => column.impute_feature(),
+            })
+            .collect()
+    }
+
     pub fn snapshot_name(&self) -> String {
         format!("\"pgml\".\"snapshot_{}\"", self.id)
     }
+
+    /// Compare the feature columns of another relation to the ones of this snapshot. Numbers,
+    /// and the elements of arrays, are scored with the population stability index over the
+    /// deciles of the snapshot, and the Kolmogorov-Smirnov statistic. Categories are scored
+    /// with the PSI of their frequencies. Scalars also report how far their mean shifted, in
+    /// standard deviations of the snapshot.
+    pub fn drift(&self, relation_name: &str) -> Vec<Drift> {
+        let columns = self.feature_columns();
+        // Older snapshots have nulls in their analysis, which are skipped.
+        let expected_analysis: HashMap<String, f32> = self
+            .analysis
+            .as_ref()
+            .and_then(|analysis| analysis.0.as_object())
+            .map(|analysis| {
+                analysis
+                    .iter()
+                    .filter_map(|(stat, value)| {
+                        value.as_f64().map(|value| (stat.clone(), value as f32))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut drift = Vec::new();
+
+        Spi::connect(|client| {
+            let analysis = analyze_columns(&client, columns, relation_name);
+
+            for column in columns {
+                let mut stats = serde_json::Map::new();
+                for stat in [
+                    "min",
+                    "max",
+                    "mean",
+                    "stddev",
+                    "p25",
+                    "p50",
+                    "p75",
+                    "count",
+                    "distinct",
+                    "nulls",
+                    "dims",
+                    "cardinality",
+                ] {
+                    if let Some(value) = analysis.get(&format!("{}_{stat}", column.name)) {
+                        stats.insert(stat.to_string(), json!(value));
+                    }
+                }
+
+                let quoted_name = column.quoted_name();
+                let stats_safe_name = column.stats_safe_name();
+                let (psi, ks) = if column.is_categorical() {
+                    let frequencies = |relation: &str| {
+                        let mut frequencies = BTreeMap::new();
+                        client
+                            .select(
+                                &format!("SELECT {quoted_name}::TEXT, count(*)::FLOAT4 FROM {relation} WHERE {quoted_name} IS NOT NULL GROUP BY 1"),
+                                None,
+                                None,
+                            )
+                            .for_each(|row| {
+                                frequencies.insert(
+                                    row[1].value::<String>().unwrap(),
+                                    row[2].value::<f32>().unwrap(),
+                                );
+                            });
+                        frequencies
+                    };
+                    let expected = frequencies(&self.snapshot_name());
+                    let actual = frequencies(relation_name);
+                    let share = |frequencies: &BTreeMap<String, f32>, category: &String| {
+                        frequencies.get(category).copied().unwrap_or(0.)
+                            / frequencies.values().sum::<f32>().max(1.)
+                    };
+                    let categories: Vec<&String> =
+                        expected.keys().chain(actual.keys()).unique().collect();
+                    let expected_shares: Vec<f32> = categories
+                        .iter()
+                        .map(|category| share(&expected, category))
+                        .collect();
+                    let actual_shares: Vec<f32> = categories
+                        .iter()
+                        .map(|category| share(&actual, category))
+                        .collect();
+                    (
+                        population_stability_index(&expected_shares, &actual_shares),
+                        None,
+                    )
+                } else {
+                    // The values are binned and compared in Postgres, so they're never all
+                    // loaded into memory.
+                    let values = |relation: &str| {
+                        if column.pg_type.ends_with("[]") {
+                            format!("SELECT value::FLOAT8 AS value FROM {relation}, unnest({stats_safe_name}) AS value WHERE value IS NOT NULL")
+                        } else {
+                            format!("SELECT {stats_safe_name}::FLOAT8 AS value FROM {relation} WHERE {quoted_name} IS NOT NULL")
+                        }
+                    };
+                    let expected = values(&self.snapshot_name());
+                    let actual = values(relation_name);
+
+                    let mut edges: Vec<f64> = client
+                        .select(
+                            &format!("SELECT percentile_cont(ARRAY[0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9]) WITHIN GROUP (ORDER BY value) FROM ({expected}) expected"),
+                            Some(1),
+                            None,
+                        )
+                        .first()
+                        .get_datum::<Vec<f64>>(1)
+                        .unwrap_or_default();
+                    edges.dedup();
+                    let bin_shares = |values: &str| {
+                        let mut counts = vec![0_f32; edges.len() + 1];
+                        client
+                            .select(
+                                &format!("SELECT width_bucket(value, $1), count(*)::FLOAT4 FROM ({values}) binned GROUP BY 1"),
+                                None,
+                                Some(vec![(
+                                    PgBuiltInOids::FLOAT8ARRAYOID.oid(),
+                                    edges.clone().into_datum(),
+                                )]),
+                            )
+                            .for_each(|row| {
+                                counts[row[1].value::<i32>().unwrap() as usize] =
+                                    row[2].value::<f32>().unwrap();
+                            });
+                        let total = counts.iter().sum::<f32>().max(1.);
+                        counts
+                            .iter()
+                            .map(|count| count / total)
+                            .collect::<Vec<f32>>()
+                    };
+
+                    // The largest distance between the empirical distribution functions,
+                    // NULL when either relation has no values.
+                    let ks = client
+                        .select(
+                            &format!(
+                                "WITH samples AS (
+                                    SELECT value, 1 AS expected, 0 AS actual FROM ({expected}) expected
+                                    UNION ALL
+                                    SELECT value, 0, 1 FROM ({actual}) actual
+                                ), steps AS (
+                                    SELECT value, sum(expected)::FLOAT8 AS expected, sum(actual)::FLOAT8 AS actual
+                                    FROM samples
+                                    GROUP BY value
+                                ), totals AS (
+                                    SELECT NULLIF(sum(expected), 0) AS expected, NULLIF(sum(actual), 0) AS actual FROM steps
+                                )
+                                SELECT max(abs(distance))::FLOAT4 FROM (
+                                    SELECT sum(steps.expected) OVER steps_so_far / totals.expected
+                                        - sum(steps.actual) OVER steps_so_far / totals.actual AS distance
+                                    FROM steps, totals
+                                    WINDOW steps_so_far AS (ORDER BY steps.value)
+                                ) distances"
+                            ),
+                            Some(1),
+                            None,
+                        )
+                        .first()
+                        .get_datum::<f32>(1);
+                    (
+                        population_stability_index(&bin_shares(&expected), &bin_shares(&actual)),
+                        ks,
+                    )
+                };
+
+                let stat = |analysis: &HashMap<String, f32>, stat: &str| {
+                    analysis.get(&format!("{}_{stat}", column.name)).copied()
+                };
+                let mean_shift = match (
+                    stat(&analysis, "mean"),
+                    stat(&expected_analysis, "mean"),
+                    stat(&expected_analysis, "stddev"),
+                ) {
+                    (Some(mean), Some(expected_mean), Some(stddev)) if stddev > 0. => {
+                        Some((mean - expected_mean) / stddev)
+                    }
+                    _ => None,
+                };
+
+                drift.push(Drift {
+                    feature: column.name.clone(),
+                    psi,
+                    ks,
+                    mean_shift,
+                    analysis: Value::Object(stats),
+                });
+            }
+
+            Ok(Some(1))
+        });
+
+        drift
+    }
+}
+
+/// How far a feature column of a relation drifted from the snapshot a model was trained on.
+#[derive(Debug)]
+pub struct Drift {
+    pub feature: String,
+    /// The population stability index of its distribution.
+    pub psi: f32,
+    /// The Kolmogorov-Smirnov statistic, for numbers.
+    pub ks: Option<f32>,
+    /// The shift of its mean in standard deviations of the snapshot, for scalar numbers.
+    pub mean_shift: Option<f32>,
+    /// The statistics of the column in the relation, like the ones of the snapshot.
+    pub analysis: Value,
+}
+
+/// The column types a snapshot can turn into features and labels.
+fn is_supported_type(pg_type: &str) -> bool {
+    matches!(
+        pg_type.strip_suffix("[]").unwrap_or(pg_type),
+        "bool" | "int2" | "int4" | "int8" | "float4" | "float8"
+    ) || matches!(pg_type, "text" | "varchar" | "bpchar")
+}
+
+/// The statistics of each column in a relation, e.g. `age_mean`, plus the number of `samples`.
+fn analyze_columns(client: &SpiClient, columns: &[Column], relation: &str) -> HashMap<String, f32> {
+    // We have to pull this analysis data into Rust as opposed to using Postgres
+    // json_build_object(...), because Postgres functions have a limit of 100 arguments.
+    // Any table that has more than 10 columns will exceed the Postgres limit since we
+    // calculate 10 statistics per column.
+    let mut stats = vec![r#"count(*)::FLOAT4 AS "samples""#.to_string()];
+    let mut fields = vec!["samples".to_string()];
+    let mut laterals = String::new();
+    for column in columns {
+        match column.pg_type.as_str() {
+            "bool" | "int2" | "int4" | "int8" | "float4" | "float8" => {
+                let name = &column.name;
+                let stats_safe_name = column.stats_safe_name();
+                stats.push(format!(r#"min({stats_safe_name})::FLOAT4 AS "{name}_min""#));
+                stats.push(format!(r#"max({stats_safe_name})::FLOAT4 AS "{name}_max""#));
+                stats.push(format!(
+                    r#"avg({stats_safe_name})::FLOAT4 AS "{name}_mean""#
+                ));
+                stats.push(format!(
+                    r#"stddev({stats_safe_name})::FLOAT4 AS "{name}_stddev""#
+                ));
+                stats.push(format!(r#"percentile_disc(0.25) within group (order by {stats_safe_name})::FLOAT4 AS "{name}_p25""#));
+                stats.push(format!(r#"percentile_disc(0.5) within group (order by {stats_safe_name})::FLOAT4 AS "{name}_p50""#));
+                stats.push(format!(r#"percentile_disc(0.75) within group (order by {stats_safe_name})::FLOAT4 AS "{name}_p75""#));
+                stats.push(format!(
+                    r#"count({stats_safe_name})::FLOAT4 AS "{name}_count""#
+                ));
+                stats.push(format!(
+                    r#"count(distinct {stats_safe_name})::FLOAT4 AS "{name}_distinct""#
+                ));
+                stats.push(format!(
+                    r#"sum(({stats_safe_name} IS NULL)::INT)::FLOAT4 AS "{name}_nulls""#
+                ));
+                fields.push(format!("{name}_min"));
+                fields.push(format!("{name}_max"));
+                fields.push(format!("{name}_mean"));
+                fields.push(format!("{name}_stddev"));
+                fields.push(format!("{name}_p25"));
+                fields.push(format!("{name}_p50"));
+                fields.push(format!("{name}_p75"));
+                fields.push(format!("{name}_count"));
+                fields.push(format!("{name}_distinct"));
+                fields.push(format!("{name}_nulls"));
+            }
+            "text" | "varchar" | "bpchar" => {
+                let name = &column.name;
+                let quoted_name = column.quoted_name();
+                stats.push(format!(r#"count({quoted_name})::FLOAT4 AS "{name}_count""#));
+                stats.push(format!(
+                    r#"count(distinct {quoted_name})::FLOAT4 AS "{name}_distinct""#
+                ));
+                stats.push(format!(
+                    r#"sum(({quoted_name} IS NULL)::INT)::FLOAT4 AS "{name}_nulls""#
+                ));
+                fields.push(format!("{name}_count"));
+                fields.push(format!("{name}_distinct"));
+                fields.push(format!("{name}_nulls"));
+            }
+            "bool[]" | "int2[]" | "int4[]" | "int8[]" | "float4[]" | "float8[]" => {
+                let name = &column.name;
+                let stats_safe_name = column.stats_safe_name();
+                let quoted_name = column.quoted_name();
+                let unnested_column = format!(r#""unnested_{}""#, name);
+                let lateral_table = format!(r#""{}_lateral""#, name);
+                stats.push(format!(
+                    r#"max(array_ndims({quoted_name}))::FLOAT4 AS "{name}_dims""#
+                ));
+                stats.push(format!(
+                    r#"max(cardinality({quoted_name}))::FLOAT4 AS "{name}_cardinality""#
+                ));
+                stats.push(format!(
+                    r#"min({lateral_table}.{unnested_column})::FLOAT4 AS "{name}_min""#
+                ));
+                stats.push(format!(
+                    r#"max({lateral_table}.{unnested_column})::FLOAT4 AS "{name}_max""#
+                ));
+                fields.push(format!("{name}_dims"));
+                fields.push(format!("{name}_cardinality"));
+                fields.push(format!("{name}_min"));
+                fields.push(format!("{name}_max"));
+                laterals += &format!(", LATERAL (SELECT unnest({stats_safe_name}) AS {unnested_column}) {lateral_table}");
+            }
+            &_ => {
+                error!("unhandled type: `{}` for `{}`", column.pg_type, column.name);
+            }
+        }
+    }
+
+    let stats = stats.join(", ");
+    let sql = format!(r#"SELECT {stats} FROM {relation} {laterals}"#);
+    let result = client.select(&sql, Some(1), None).first();
+    let mut analysis = HashMap::new();
+    for (i, field) in fields.iter().enumerate() {
+        // Statistics of empty columns are NULL, e.g. the stddev of a single row.
+        if let Some(value) = result.get_datum::<f32>((i + 1).try_into().unwrap()) {
+            analysis.insert(field.to_owned(), value);
+        }
+    }
+    analysis
+}
+
+/// How much a distribution moved, from the `expected` share of each bin to the `actual` one.
+/// Under 0.1 is usually considered stable, and over 0.2 a significant shift.
+fn population_stability_index(expected: &[f32], actual: &[f32]) -> f32 {
+    // Empty bins would make the index infinite.
+    const MIN_SHARE: f32 = 1e-4;
+    expected
+        .iter()
+        .zip(actual)
+        .map(|(&expected, &actual)| {
+            let expected = expected.max(MIN_SHARE);
+            let actual = actual.max(MIN_SHARE);
+            (actual - expected) * (actual / expected).ln()
+        })
+        .sum()
 }
@@ -8,6 +8,12 @@ pub enum Strategy {
     best_score,
     most_recent,
     rollback,
+    /// Split traffic between models, with `pgml.deploy_split`.
+    ab_test,
+    /// Send a share of the traffic to the most recent model, while the live model keeps the rest.
+    canary,
+    /// Score the inputs of the live model with the most recent model too, without serving it.
+    shadow,
 }
 
 impl std::str::FromStr for Strategy {
@@ -19,6 +25,9 @@ impl std::str::FromStr for Strategy {
             "best_score" => Ok(Strategy::best_score),
             "most_recent" => Ok(Strategy::most_recent),
             "rollback" => Ok(Strategy::rollback),
+            "ab_test" => Ok(Strategy::ab_test),
+            "canary" => Ok(Strategy::canary),
+            "shadow" => Ok(Strategy::shadow),
             _ => Err(()),
         }
     }
@@ -31,6 +40,9 @@ impl std::string::ToString for Strategy {
             Strategy::best_score => "best_score".to_string(),
             Strategy::most_recent => "most_recent".to_string(),
             Strategy::rollback => "rollback".to_string(),
+            Strategy::ab_test => "ab_test".to_string(),
+            Strategy::canary => "canary".to_string(),
+            Strategy::shadow => "shadow".to_string(),
         }
     }
 }
@@ -6,6 +6,7 @@ use serde::Deserialize;
 pub enum Task {
     regression,
     classification,
+    clustering,
 }
 
 impl std::str::FromStr for Task {
@@ -15,6 +16,7 @@ impl std::str::FromStr for Task {
         match input {
             "regression" => Ok(Task::regression),
             "classification" => Ok(Task::classification),
+            "clustering" => Ok(Task::clustering),
             _ => Err(()),
         }
     }
@@ -25,6 +27,70 @@ impl std::string::ToString for Task {
         match *self {
             Task::regression => "regression".to_string(),
             Task::classification => "classification".to_string(),
+            Task::clustering => "clustering".to_string(),
         }
     }
 }
+
+impl Task {
+    /// The metric models are compared by, unless the project chooses another one.
+    pub fn default_target_metric(&self) -> &'static str {
+        match self {
+            Task::regression => "r2",
+            Task::classification => "f1",
+            Task::clustering => "silhouette",
+        }
+    }
+
+    /// The metrics models of this task are scored with, that a project can optimize.
+    pub fn metrics(&self) -> &'static [&'static str] {
+        match self {
+            Task::regression => &[
+                "r2",
+                "mean_absolute_error",
+                "mean_squared_error",
+                "root_mean_squared_error",
+                "mean_absolute_percentage_error",
+                "median_absolute_error",
+                "explained_variance",
+                "max_error",
+            ],
+            Task::classification => &[
+                "f1",
+                "precision",
+                "recall",
+                "accuracy",
+                "mcc",
+                "roc_auc",
+                "log_loss",
+                "f1_macro",
+                "f1_micro",
+                "f1_weighted",
+                "precision_macro",
+                "precision_micro",
+                "precision_weighted",
+                "recall_macro",
+                "recall_micro",
+                "recall_weighted",
+                "balanced_accuracy",
+                "cohen_kappa",
+            ],
+            Task::clustering => &["silhouette", "inertia"],
+        }
+    }
+}
+
+/// Whether a higher value of the metric means a better model, errors and losses are lower is better.
+pub fn greater_is_better(metric: &str) -> bool {
+    !matches!(
+        metric,
+        "mean_absolute_error"
+            | "mean_squared_error"
+            | "root_mean_squared_error"
+            | "mean_absolute_percentage_error"
+            | "median_absolute_error"
+            | "max_error"
+            | "log_loss"
+            | "inertia"
+    )
+}
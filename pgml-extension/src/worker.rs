@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use pgx::bgworkers::*;
+use pgx::*;
+
+use crate::orm::RetrainPolicy;
+
+static RETRAIN_DATABASE: GucSetting<Option<&'static str>> = GucSetting::new(None);
+static RETRAIN_NAPTIME: GucSetting<i32> = GucSetting::new(60);
+
+/// Register the settings of the retraining worker, and start it when PostgresML
+/// is loaded from `shared_preload_libraries`.
+pub fn init() {
+    GucRegistry::define_string_guc(
+        "pgml.retrain_database",
+        "The database the retraining worker checks the policies of.",
+        "Retraining policies are only checked when this is set, e.g. to the database PostgresML is installed in.",
+        &RETRAIN_DATABASE,
+        GucContext::Sighup,
+    );
+    GucRegistry::define_int_guc(
+        "pgml.retrain_naptime",
+        "Seconds between checks of the retraining policies.",
+        "The retraining worker checks which policies are due, and retrains their projects, this often.",
+        &RETRAIN_NAPTIME,
+        1,
+        i32::MAX,
+        GucContext::Sighup,
+    );
+
+    if unsafe { pg_sys::process_shared_preload_libraries_in_progress } {
+        BackgroundWorkerBuilder::new("PostgresML retraining")
+            .set_function("retrain_worker_main")
+            .set_library("pgml")
+            .enable_spi_access()
+            .set_restart_time(Some(Duration::from_secs(60)))
+            .load();
+    }
+}
+
+/// Check the retraining policies every `pgml.retrain_naptime` seconds, and retrain the
+/// projects that are due. Each run is recorded in its own transaction before training,
+/// and trained in a subtransaction, so an error fails the run instead of the worker, and
+/// the policy backs off before it's retried. Projects are retrained as the owner of their policy.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn retrain_worker_main(_arg: pg_sys::Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+
+    // Exiting cleanly keeps the worker from being restarted until the server is.
+    let database = match RETRAIN_DATABASE.get() {
+        Some(database) => database,
+        None => {
+            log!("pgml.retrain_database is not set, retraining policies won't be checked.");
+            return;
+        }
+    };
+    BackgroundWorker::connect_worker_to_spi(Some(&database), None);
+
+    let installed = BackgroundWorker::transaction(|| {
+        Spi::get_one::<bool>("SELECT to_regclass('pgml.retrain_policies') IS NOT NULL")
+            .unwrap_or(false)
+    });
+    if !installed {
+        log!(
+            "PostgresML is not installed in the database `{}`, retraining policies won't be checked.",
+            database
+        );
+        return;
+    }
+    BackgroundWorker::transaction(RetrainPolicy::fail_interrupted_runs);
+
+    while BackgroundWorker::wait_latch(Some(Duration::from_secs(RETRAIN_NAPTIME.get() as u64))) {
+        if BackgroundWorker::sighup_received() {
+            unsafe { pg_sys::ProcessConfigFile(pg_sys::GucContext_PGC_SIGHUP) };
+        }
+
+        let due = BackgroundWorker::transaction(RetrainPolicy::find_due);
+        for (policy, reason) in due {
+            let run_id = BackgroundWorker::transaction({
+                let policy = policy.clone();
+                move || policy.start_run(&reason, true)
+            });
+            BackgroundWorker::transaction(move || {
+                let retrained = subtransaction(|| {
+                    policy.set_role();
+                    crate::api::retrain_project(&policy, run_id)
+                });
+                if retrained.is_none() {
+                    warning!(
+                        "Retraining project `{}` failed, its policy will be retried later.",
+                        policy.project_name
+                    );
+                    RetrainPolicy::fail_run(run_id);
+                }
+            });
+        }
+    }
+}
+
+/// Run `f` in a subtransaction, and roll it back if it raises an error, rather than
+/// aborting the whole transaction. The error is logged, and None returned instead.
+pub fn subtransaction<R>(f: impl FnOnce() -> R) -> Option<R> {
+    let (context, owner) = unsafe {
+        let context = pg_sys::CurrentMemoryContext;
+        let owner = pg_sys::CurrentResourceOwner;
+        pg_sys::BeginInternalSubTransaction(std::ptr::null());
+        pg_sys::MemoryContextSwitchTo(context);
+        (context, owner)
+    };
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+
+    unsafe {
+        pg_sys::MemoryContextSwitchTo(context);
+        match &result {
+            Ok(_) => pg_sys::ReleaseCurrentSubTransaction(),
+            Err(error) => {
+                // Errors of Rust carry their message, errors of Postgres are still on its error stack.
+                match error
+                    .downcast_ref::<String>()
+                    .map(String::as_str)
+                    .or_else(|| error.downcast_ref::<&str>().copied())
+                {
+                    Some(message) => warning!("{}", message),
+                    None => pg_sys::EmitErrorReport(),
+                }
+                pg_sys::FlushErrorState();
+                pg_sys::RollbackAndReleaseCurrentSubTransaction();
+            }
+        }
+        pg_sys::MemoryContextSwitchTo(context);
+        pg_sys::CurrentResourceOwner = owner;
+    }
+
+    result.ok()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+
+    #[pg_test]
+    fn test_subtransaction() {
+        Spi::run("CREATE TABLE pgml.subtransactions (id INT4)");
+        assert_eq!(
+            subtransaction(|| Spi::run("INSERT INTO pgml.subtransactions VALUES (1)")),
+            Some(())
+        );
+
+        // Errors of Rust and of Postgres only roll back their own subtransaction.
+        assert_eq!(
+            subtransaction::<()>(|| {
+                Spi::run("INSERT INTO pgml.subtransactions VALUES (2)");
+                error!("Rolled back");
+            }),
+            None
+        );
+        assert_eq!(
+            subtransaction(|| {
+                Spi::run("INSERT INTO pgml.subtransactions VALUES (3)");
+                Spi::run("SELECT 1 / 0");
+            }),
+            None
+        );
+
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM pgml.subtransactions"),
+            Some(1)
+        );
+    }
+}